@@ -1,15 +1,21 @@
 use crate::{
     map::{self, tile::LocalSpace, ChunkCoordinate},
     player::{CameraPivot, MainPlayer, Player, PLAYER_SIZES},
-    GameMode,
+    quest, socket, ui, GameMode,
 };
 use bevy::prelude::*;
 use bevy_matchbox::prelude::PeerId;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, RngCore, SeedableRng};
 
 mod preparation;
+pub mod replay;
+pub mod spectator;
 mod ui;
 // mod your_turn;
 
+/// # Number of tiles fighters can be placed on
+const PLACEMENT_TILES: usize = 10;
+
 #[derive(States, Default, Debug, Clone, PartialEq, Eq, Hash)]
 enum CombatState {
     #[default]
@@ -30,8 +36,103 @@ struct TurnSequence(Vec<Player>);
 #[derive(Resource)]
 pub struct Owner(pub PeerId);
 
+/// What the fight is against, inserted along the `Owner`
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Duel,
+    Monsters,
+}
+
+/// How the fight ended for our side, set by the turns once a side has no fighter left
+/// Turns aren't played yet, so no fight has an outcome until they are
+#[allow(dead_code)]
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// With the type of every monster defeated, none in a duel
+    Victory(Vec<String>),
+    Defeat,
+}
+
+/// Peers fighting on the same side as us
+#[derive(Resource)]
+pub struct Allies(pub Vec<PeerId>);
+
+/// Every peer taking part in the fight, us included
+#[derive(Resource, Default)]
+pub struct Fighters(pub Vec<PeerId>);
+
+/// Randomness of the fight: placement, damage rolls, critical hits and AI decisions
+/// Seeded by the fight owner, every peer draw the same numbers in the same order
+#[derive(Resource)]
+pub struct FightRng {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl FightRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Kept in the replays to roll the same numbers again
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RngCore for FightRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Cells of the chunk fighters can be placed on, the first draw of the fight randomness
+/// Computed from the chunk data alone so the dedicated server draw the same cells as the fighters
+pub fn placement_cells(
+    chunk: &map::Chunk,
+    coord: ChunkCoordinate,
+    rng: &mut impl Rng,
+) -> Vec<map::TileCoordinate> {
+    let mut cells = (0..map::chunk::SIZE_Z as i32)
+        .flat_map(|z| {
+            (0..map::chunk::SIZE_X as i32)
+                .map(move |x| map::TileCoordinate::<LocalSpace>::new(x, z))
+        })
+        .filter(|local| chunk.is_walkable(*local, true))
+        .map(|local| local.to_absolute(coord))
+        .collect::<Vec<_>>();
+    cells.shuffle(rng);
+    cells.truncate(PLACEMENT_TILES);
+    cells
+}
+
+/// Present while watching someone else fight, nothing can be done but look
+#[derive(Resource)]
+pub struct Spectator;
+
+pub fn is_spectating(spectator: Option<Res<Spectator>>) -> bool {
+    spectator.is_some()
+}
+
 pub fn init(app: &mut App) {
     preparation::init(app);
+    spectator::init(app);
+    replay::init(app);
     //     your_turn::init(app);
     app.add_systems(
         OnTransition {
@@ -40,6 +141,10 @@ pub fn init(app: &mut App) {
         },
         combat_setup,
     )
+    .add_systems(OnExit(GameMode::Combat), end_fight)
+    .add_observer(on_fight_seed)
+    .add_observer(on_seed_requested)
+    .add_observer(on_fight_rejected)
     .init_state::<CombatState>();
     //     .add_systems(OnExit(GameMode::Combat), cleanup)
     //     .add_systems(OnEnter(CombatState::NextTurn), determine_whose_turn);
@@ -54,6 +159,11 @@ fn combat_setup(
     map_handles: ResMut<map::Handles>,
     mut q_camera: Query<&mut Transform, (With<CameraPivot>, Without<MainPlayer>)>,
     mut next_combat_state: ResMut<NextState<CombatState>>,
+    owner: Option<Res<Owner>>,
+    my_id: Option<Res<socket::MyId>>,
+    spectator: Option<Res<Spectator>>,
+    fighters: Option<Res<Fighters>>,
+    server_id: Option<Res<socket::ServerId>>,
 ) {
     info!("Entering combat");
     let player_transform = q_player.single();
@@ -61,18 +171,158 @@ fn combat_setup(
     let chunk = chunk_assets.get(current_chunk.grid.id()).unwrap();
     for z in 0..map::chunk::SIZE_Z {
         for x in 0..map::chunk::SIZE_X {
-            let tile = chunk.0[z][x];
-            let tile_coord =
-                map::TileCoordinate::<LocalSpace>::new(x as i32, z as i32).to_absolute(chunk_index);
-            map::tile::spawn(&mut commands, &map_handles, tile, tile_coord.to_world());
+            let tile = chunk.tiles[z][x];
+            let local = map::TileCoordinate::<LocalSpace>::new(x as i32, z as i32);
+            map::tile::spawn(
+                &mut commands,
+                &map_handles,
+                tile,
+                chunk.get_properties(local),
+                local.to_absolute(chunk_index).to_world(),
+            );
         }
     }
     let mut camera = q_camera.single_mut();
     let chunk_center = chunk_index.world_center();
     camera.translation = chunk_center;
+    if spectator.is_some() {
+        next_combat_state.set(CombatState::Preparation);
+        return;
+    }
+    let is_owner = match (&owner, &my_id) {
+        (Some(owner), Some(my_id)) => owner.0 == my_id.0,
+        (Some(_), None) => false,
+        // Offline fight
+        (None, _) => true,
+    };
+    // Others wait for our seed before placing anyone
+    if is_owner {
+        let seed = rand::random();
+        let fighters = fighters
+            .map(|fighters| fighters.0.clone())
+            .unwrap_or_default();
+        info!("Fight seed: {}", seed);
+        commands.insert_resource(FightRng::new(seed));
+        for fighter in fighters.iter() {
+            if my_id.as_ref().is_some_and(|my_id| my_id.0 == *fighter) {
+                continue;
+            }
+            commands.trigger(socket::SendMessageEvent::ToPeer(
+                socket::Message::FightSeed {
+                    seed,
+                    fighters: fighters.clone(),
+                },
+                *fighter,
+            ));
+        }
+        // The dedicated server check the fighters and their placements
+        if let Some(server_id) = server_id {
+            commands.trigger(socket::SendMessageEvent::ToPeer(
+                socket::Message::FightSeed { seed, fighters },
+                server_id.0,
+            ));
+        }
+        next_combat_state.set(CombatState::Preparation);
+    } else if let Some(owner) = owner {
+        // The seed may have been sent before we joined, like to the members of a group
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::FightSeedRequest,
+            owner.0,
+        ));
+    }
+}
+
+/// Only the seed of the fight we are waiting to start is taken,
+/// anything else would desync our numbers from the other fighters
+fn on_fight_seed(
+    trigger: Trigger<socket::FightSeedEvent>,
+    mut commands: Commands,
+    game_mode: Res<State<GameMode>>,
+    combat_state: Res<State<CombatState>>,
+    owner: Option<Res<Owner>>,
+    my_id: Option<Res<socket::MyId>>,
+    rng: Option<Res<FightRng>>,
+    mut next_combat_state: ResMut<NextState<CombatState>>,
+) {
+    let waiting = *game_mode.get() == GameMode::Combat
+        && *combat_state.get() == CombatState::NotInCombat
+        && rng.is_none();
+    let from_owner = owner.is_some_and(|owner| owner.0 == trigger.peer_id);
+    let fighting = my_id.is_some_and(|my_id| trigger.fighters.contains(&my_id.0));
+    if !waiting || !from_owner || !fighting {
+        warn!("Ignoring fight seed from {}", trigger.peer_id);
+        return;
+    }
+    info!("Fight seed received: {}", trigger.seed);
+    commands.insert_resource(FightRng::new(trigger.seed));
+    // The owner decide the placement order
+    commands.insert_resource(Fighters(trigger.fighters.clone()));
     next_combat_state.set(CombatState::Preparation);
 }
 
+/// Send the seed again to a fighter who joined after it went out
+fn on_seed_requested(
+    trigger: Trigger<socket::FightSeedRequestedEvent>,
+    mut commands: Commands,
+    game_mode: Res<State<GameMode>>,
+    owner: Option<Res<Owner>>,
+    my_id: Option<Res<socket::MyId>>,
+    fighters: Option<Res<Fighters>>,
+    rng: Option<Res<FightRng>>,
+    server_id: Option<Res<socket::ServerId>>,
+) {
+    let peer_id = trigger.0;
+    let (Some(owner), Some(my_id), Some(fighters), Some(rng)) = (owner, my_id, fighters, rng)
+    else {
+        return;
+    };
+    if *game_mode.get() != GameMode::Combat || owner.0 != my_id.0 || !fighters.0.contains(&peer_id)
+    {
+        return;
+    }
+    let seed = || socket::Message::FightSeed {
+        seed: rng.seed(),
+        fighters: fighters.0.clone(),
+    };
+    // The group members joined since the fight started, the server must know them
+    if let Some(server_id) = server_id {
+        commands.trigger(socket::SendMessageEvent::ToPeer(seed(), server_id.0));
+    }
+    commands.trigger(socket::SendMessageEvent::ToPeer(seed(), peer_id));
+}
+
+/// The dedicated server refused to run the fight we are in, back to the world
+fn on_fight_rejected(
+    trigger: Trigger<socket::FightRejectedEvent>,
+    mut commands: Commands,
+    game_mode: Res<State<GameMode>>,
+    owner: Option<Res<Owner>>,
+    mut next_game_mode: ResMut<NextState<GameMode>>,
+) {
+    if *game_mode.get() != GameMode::Combat || !owner.is_some_and(|owner| owner.0 == trigger.owner)
+    {
+        return;
+    }
+    warn!("The server rejected the fight of {}", trigger.owner);
+    ui::popup::spawn(&mut commands, "The server refused this fight".to_string());
+    next_game_mode.set(GameMode::Exploration);
+}
+
+/// Forget everything about the fight, for fighters and spectators alike
+fn end_fight(mut commands: Commands, outcome: Option<Res<Outcome>>) {
+    if let Some(Outcome::Victory(monsters)) = outcome.as_deref() {
+        for monster in monsters.iter() {
+            commands.trigger(quest::ProgressEvent::Killed(monster.clone()));
+        }
+    }
+    commands.remove_resource::<Outcome>();
+    commands.remove_resource::<FightRng>();
+    commands.remove_resource::<Owner>();
+    commands.remove_resource::<Kind>();
+    commands.remove_resource::<Fighters>();
+    commands.remove_resource::<Allies>();
+}
+
 // fn determine_whose_turn(
 //     mut next_combat_state: ResMut<NextState<CombatState>>,
 //     combat_state: Res<State<CombatState>>,
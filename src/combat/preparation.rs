@@ -4,12 +4,11 @@ use bevy::{
     winit::cursor::CursorIcon,
 };
 use bevy_mod_raycast::prelude::*;
-use rand::seq::SliceRandom;
 
 use crate::{
     combat::{self, CombatTimer},
     map,
-    player::{MainPlayer, Player, PLAYER_SIZES},
+    player::{self, MainPlayer, Player, PLAYER_SIZES},
     socket,
 };
 
@@ -21,11 +20,16 @@ use super::{
 pub fn init(app: &mut App) {
     app.add_systems(
         OnEnter(CombatState::Preparation),
-        (spawn_network_observers, setup),
+        (
+            spawn_network_observers,
+            setup.run_if(not(combat::is_spectating)),
+        ),
     )
     .add_systems(
         Update,
-        (update_timer, placement_tile_interaction).run_if(in_state(CombatState::Preparation)),
+        (update_timer, placement_tile_interaction)
+            .run_if(in_state(CombatState::Preparation))
+            .run_if(not(combat::is_spectating)),
     )
     .add_systems(OnExit(CombatState::Preparation), cleanup);
 }
@@ -48,13 +52,18 @@ fn on_player_join(
     trigger: Trigger<socket::CombatPlayerJoinedEvent>,
     mut commands: Commands,
     mut q_players: Query<(&mut Transform, &socket::Id, Entity), With<Player>>,
+    time: Res<Time>,
 ) {
     info!("Player {} joined", trigger.peer_id);
     for (mut player_transform, player_id, player_entity) in q_players.iter_mut() {
         if player_id.0 == trigger.peer_id {
-            player_transform.translation =
-                Vec3::new(trigger.position.x, PLAYER_SIZES.y / 2.0, trigger.position.z);
-            commands.entity(player_entity).insert(trigger.stats.clone());
+            let position = Vec3::new(trigger.position.x, PLAYER_SIZES.y / 2.0, trigger.position.z);
+            player_transform.translation = position;
+            commands.entity(player_entity).insert((
+                trigger.stats.clone(),
+                player::Interpolation::new(position),
+                map::movement::Validated::new(position, time.elapsed_secs()),
+            ));
             break;
         }
     }
@@ -68,28 +77,45 @@ fn on_combat_started(
     next_state.set(CombatState::NextTurn);
 }
 
+/// Fighters can only be placed on the placement tiles,
+/// or on any tile usable in a fight when we have none to compare with (spectating)
 fn on_peer_position_update(
     trigger: Trigger<socket::UpdatePlayerPositionEvent>,
     mut commands: Commands,
-    q_players: Query<(Entity, &socket::Id), With<Player>>,
+    mut q_players: Query<(Entity, &mut player::Interpolation, &socket::Id), With<Player>>,
+    q_placement_tiles: Query<&Transform, With<PlacementTile>>,
+    q_tiles: Query<(&Transform, &map::Tile, &map::tile::Properties)>,
+    time: Res<Time>,
 ) {
-    for player in q_players.iter() {
-        if player.1 .0 == trigger.1 {
-            info!("Player {} moved to {:?}", player.1 .0, trigger.0);
+    let position = Vec3::new(trigger.0.x, 0.0, trigger.0.z);
+    let target = map::TileCoordinate::from_world(position);
+    let is_target =
+        |transform: &Transform| map::TileCoordinate::from_world(transform.translation) == target;
+    let allowed = if q_placement_tiles.is_empty() {
+        q_tiles.iter().any(|(transform, tile, properties)| {
+            is_target(transform) && tile.is_walkable() && !properties.blocked_in_fight
+        })
+    } else {
+        q_placement_tiles.iter().any(is_target)
+    };
+    for (entity, mut interpolation, id) in q_players.iter_mut() {
+        if id.0 == trigger.1 {
+            if !allowed {
+                warn!("Rejected placement of {} on {}", id.0, target);
+                break;
+            }
+            info!("Player {} moved to {:?}", id.0, trigger.0);
+            interpolation.set_target(position);
             commands
-                .entity(player.0)
-                .insert(Transform::from_translation(Vec3::new(
-                    trigger.0.x,
-                    PLAYER_SIZES.y / 2.0,
-                    trigger.0.z,
-                )));
+                .entity(entity)
+                .insert(map::movement::Validated::new(position, time.elapsed_secs()));
             break;
         }
     }
 }
 
 #[derive(Component)]
-struct PlayerReady;
+pub(super) struct PlayerReady;
 
 fn on_combat_ready_state_changed(
     trigger: Trigger<socket::CombatReadyStateChangedEvent>,
@@ -98,11 +124,25 @@ fn on_combat_ready_state_changed(
     q_player_ready: Query<&PlayerReady>,
     owner: Res<combat::Owner>,
     my_id: Res<socket::MyId>,
+    mut fighters: Option<ResMut<combat::Fighters>>,
     mut next_state: ResMut<NextState<CombatState>>,
 ) {
+    // Group members joining their leader only show up once they get ready
+    if let Some(fighters) = fighters.as_mut() {
+        if !fighters.0.contains(&trigger.peer_id) {
+            fighters.0.push(trigger.peer_id);
+        }
+    }
     let mut player_count = 0;
     let mut ready_count = 0;
     for player in q_players.iter() {
+        // Spectators and players walking around never get ready
+        if fighters
+            .as_ref()
+            .is_some_and(|fighters| !fighters.0.contains(&player.1 .0))
+        {
+            continue;
+        }
         player_count += 1;
         if player.1 .0 == trigger.peer_id {
             info!("Player {} is ready: {}", player.1 .0, trigger.is_ready);
@@ -134,28 +174,37 @@ struct PlacementTile;
 
 fn setup(
     mut commands: Commands,
-    q_ground_tiles: Query<(Entity, &map::Tile), With<map::Tile>>,
+    q_ground_tiles: Query<(Entity, &map::Tile)>,
     mut q_player: Query<&mut Transform, With<MainPlayer>>,
     q_transform: Query<&Transform, Without<MainPlayer>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     map_handles: Res<map::Handles>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    current_chunk: Res<map::CurrentChunk>,
+    mut rng: ResMut<combat::FightRng>,
+    fighters: Option<Res<combat::Fighters>>,
+    my_id: Option<Res<socket::MyId>>,
 ) {
     info!("Setup");
     commands.insert_resource(CombatTimer(Timer::from_seconds(90.0, TimerMode::Once)));
-    let mut rng = rand::thread_rng();
+    let chunk_index = map::ChunkCoordinate::from_world(q_player.single().translation);
+    let Some(chunk) = chunk_assets.get(current_chunk.grid.id()) else {
+        error!("The chunk of the fight isn't loaded");
+        return;
+    };
     let random_tiles = {
-        let mut tiles = q_ground_tiles
-            .iter()
-            .filter_map(|(entity, tile_type)| {
-                if *tile_type == map::Tile::Ground {
-                    Some(entity)
-                } else {
-                    None
-                }
+        // Entities aren't spawned in the same order on every peer, the cells are drawn from the chunk data
+        let tiles = combat::placement_cells(chunk, chunk_index, &mut *rng)
+            .into_iter()
+            .filter_map(|cell| {
+                q_ground_tiles.iter().find_map(|(entity, tile_type)| {
+                    let transform = q_transform.get(entity).ok()?;
+                    (tile_type.is_walkable()
+                        && map::TileCoordinate::from_world(transform.translation) == cell)
+                        .then_some(entity)
+                })
             })
             .collect::<Vec<_>>();
-        tiles.shuffle(&mut rng);
-        tiles.truncate(10);
         for tile in tiles.iter() {
             let transform = q_transform.get(*tile).unwrap();
             let position = transform.translation + Vec3::new(0.0, 0.01, 0.0);
@@ -172,13 +221,29 @@ fn setup(
         }
         tiles
     };
-    // Teleport player to one of the random tiles
+    // Every fighter start on its own tile, in the order given by the owner
+    let slot = match (fighters, my_id) {
+        (Some(fighters), Some(my_id)) => fighters
+            .0
+            .iter()
+            .position(|id| *id == my_id.0)
+            .unwrap_or(fighters.0.len()),
+        _ => 0,
+    };
     let mut player = q_player.single_mut();
-    player.translation = q_transform.get(random_tiles[0]).unwrap().translation
+    player.translation = q_transform
+        .get(random_tiles[slot % random_tiles.len()])
+        .unwrap()
+        .translation
         + Vec3::new(0.0, PLAYER_SIZES.y / 2.0, 0.0);
     combat::ui::spawn_ready_button(&mut commands).observe(
-        |_trigger: Trigger<Pointer<Up>>, mut commands: Commands| {
+        |_trigger: Trigger<Pointer<Up>>,
+         mut commands: Commands,
+         q_main_player: Query<Entity, With<MainPlayer>>| {
             info!("You're ready!");
+            if let Ok(entity) = q_main_player.get_single() {
+                commands.entity(entity).insert(PlayerReady);
+            }
             commands.trigger(socket::SendMessageEvent::Broadcast(
                 socket::Message::CombatReadyStateChanged(true),
             ));
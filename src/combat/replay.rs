@@ -0,0 +1,602 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use bevy::{core_pipeline::tonemapping::Tonemapping, prelude::*, render::camera::ScalingMode};
+use bevy_matchbox::prelude::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combat::{self, CombatState},
+    map,
+    materials::{self, player_shadow::PlayerShadowMaterial},
+    player::{self, MainPlayer, Player},
+    socket, GameMode, ProcessArgs,
+};
+
+/// # Folder the fight recordings are written to
+pub const REPLAY_FOLDER: &str = "replays";
+/// # Fastest and slowest playback speed
+const MAX_SPEED: f32 = 16.0;
+const MIN_SPEED: f32 = 0.125;
+
+pub fn init(app: &mut App) {
+    app.add_systems(
+        OnEnter(GameMode::Combat),
+        start_recording.run_if(not(combat::is_spectating)),
+    )
+    // Fights never reach `CombatState::End` yet, leaving the combat mode is what ends them
+    .add_systems(
+        OnExit(GameMode::Combat),
+        (save_recording, |mut commands: Commands| {
+            commands.remove_resource::<Recording>();
+        })
+            .chain(),
+    )
+    .add_observer(record_sent)
+    .add_observer(record_position)
+    .add_observer(record_ready)
+    .add_observer(record_start)
+    .add_observer(on_record);
+}
+
+/// Everything a fighter can do that change the course of the fight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Input {
+    Placement {
+        id: PeerId,
+        x: f32,
+        z: f32,
+    },
+    Move {
+        id: PeerId,
+        x: f32,
+        z: f32,
+    },
+    Cast {
+        id: PeerId,
+        spell: String,
+        x: f32,
+        z: f32,
+    },
+    TurnEnd {
+        id: PeerId,
+    },
+    Ready {
+        id: PeerId,
+        ready: bool,
+    },
+    Start,
+}
+
+impl Input {
+    /// One line telling what happened, `name` gives the name of a fighter
+    pub fn describe(&self, name: impl Fn(&PeerId) -> String) -> String {
+        let cell = |x: f32, z: f32| map::TileCoordinate::from_world(Vec3::new(x, 0.0, z));
+        match self {
+            Input::Placement { id, x, z } => format!("{} placed on {}", name(id), cell(*x, *z)),
+            Input::Move { id, x, z } => format!("{} moved to {}", name(id), cell(*x, *z)),
+            Input::Cast { id, spell, x, z } => {
+                format!("{} cast {} on {}", name(id), spell, cell(*x, *z))
+            }
+            Input::TurnEnd { id } => format!("{} ended their turn", name(id)),
+            Input::Ready { id, ready } => {
+                if *ready {
+                    format!("{} is ready", name(id))
+                } else {
+                    format!("{} is not ready", name(id))
+                }
+            }
+            Input::Start => "Fight started".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Time (in seconds) since the start of the fight
+    pub at: f32,
+    pub input: Input,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FighterRecord {
+    pub id: PeerId,
+    pub name: String,
+    pub max_health: u32,
+}
+
+/// Content of a replay file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub chunk_x: i32,
+    pub chunk_z: i32,
+    pub fighters: Vec<FighterRecord>,
+    pub entries: Vec<Entry>,
+}
+
+impl Replay {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path.as_ref()).map_err(|error| error.to_string())?;
+        ron::from_str(&content).map_err(|error| error.to_string())
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) {
+        let file = match std::fs::File::create(path.as_ref()) {
+            Ok(file) => file,
+            Err(error) => {
+                error!("Can't create {}: {}", path.as_ref().display(), error);
+                return;
+            }
+        };
+        if let Err(error) =
+            ron::ser::to_writer_pretty(file, self, ron::ser::PrettyConfig::default())
+        {
+            error!("Can't write {}: {}", path.as_ref().display(), error);
+        }
+    }
+}
+
+/// The fight being recorded, present while fighting
+#[derive(Resource)]
+pub(super) struct Recording {
+    started_at: f32,
+    replay: Replay,
+}
+
+impl Recording {
+    fn push(&mut self, now: f32, input: Input) {
+        self.replay.entries.push(Entry {
+            at: now - self.started_at,
+            input,
+        });
+    }
+
+    /// Also forwarded to the spectators as the turn events of the fight
+    pub(super) fn entries(&self) -> &[Entry] {
+        &self.replay.entries
+    }
+
+    fn add_fighter(&mut self, id: PeerId, name: &str, max_health: u32) {
+        if self.replay.fighters.iter().any(|fighter| fighter.id == id) {
+            return;
+        }
+        self.replay.fighters.push(FighterRecord {
+            id,
+            name: name.to_string(),
+            max_health,
+        });
+    }
+}
+
+/// Record an input that doesn't go through the network, such as spell casts or turn ends
+/// Nothing triggers it yet: turns aren't played until `your_turn` is enabled again,
+/// so only placements, ready states, the start and moves sent to the others are recorded
+#[derive(Debug, Event)]
+pub struct RecordEvent(pub Input);
+
+fn start_recording(
+    mut commands: Commands,
+    time: Res<Time>,
+    rng: Option<Res<combat::FightRng>>,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+) {
+    let Ok(transform) = q_main_player.get_single() else {
+        return;
+    };
+    let chunk = map::ChunkCoordinate::from_world(transform.translation);
+    commands.insert_resource(Recording {
+        started_at: time.elapsed_secs(),
+        replay: Replay {
+            seed: rng.map(|rng| rng.seed()).unwrap_or_default(),
+            chunk_x: chunk.x,
+            chunk_z: chunk.z,
+            fighters: Vec::new(),
+            entries: Vec::new(),
+        },
+    });
+}
+
+fn save_recording(recording: Option<Res<Recording>>, rng: Option<Res<combat::FightRng>>) {
+    let Some(recording) = recording else {
+        return;
+    };
+    let mut replay = recording.replay.clone();
+    // The seed may have been received after the recording started
+    if let Some(rng) = rng {
+        replay.seed = rng.seed();
+    }
+    if let Err(error) = std::fs::create_dir_all(REPLAY_FOLDER) {
+        error!("Can't create the replay folder: {}", error);
+        return;
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let path = PathBuf::from(REPLAY_FOLDER).join(format!("{}.ron", timestamp));
+    info!("Saving fight replay to {}", path.display());
+    replay.write(path);
+}
+
+fn position_input(id: PeerId, x: f32, z: f32, combat_state: &CombatState) -> Input {
+    if *combat_state == CombatState::Preparation {
+        Input::Placement { id, x, z }
+    } else {
+        Input::Move { id, x, z }
+    }
+}
+
+/// Our own inputs, caught as they are sent to the other fighters
+fn record_sent(
+    trigger: Trigger<socket::SendMessageEvent>,
+    recording: Option<ResMut<Recording>>,
+    time: Res<Time>,
+    my_id: Option<Res<socket::MyId>>,
+    combat_state: Res<State<CombatState>>,
+    player_info: Res<player::Info>,
+) {
+    let (Some(mut recording), Some(my_id)) = (recording, my_id) else {
+        return;
+    };
+    let socket::SendMessageEvent::Broadcast(message) = trigger.event() else {
+        return;
+    };
+    let input = match message {
+        socket::Message::UpdatePlayerPosition(position) => {
+            position_input(my_id.0, position.x, position.z, combat_state.get())
+        }
+        socket::Message::CombatReadyStateChanged(ready) => Input::Ready {
+            id: my_id.0,
+            ready: *ready,
+        },
+        socket::Message::CombatStart => Input::Start,
+        _ => return,
+    };
+    recording.add_fighter(my_id.0, &player_info.name, player_info.max_health);
+    recording.push(time.elapsed_secs(), input);
+}
+
+/// Only inputs of peers taking part in the fight are kept
+fn fighter<'a>(
+    fighters: &Option<Res<combat::Fighters>>,
+    q_players: &'a Query<(&Player, &socket::Id, Option<&player::Info>)>,
+    peer_id: PeerId,
+) -> Option<(&'a Player, u32)> {
+    if fighters
+        .as_ref()
+        .is_some_and(|fighters| !fighters.0.contains(&peer_id))
+    {
+        return None;
+    }
+    q_players
+        .iter()
+        .find(|(_, id, _)| id.0 == peer_id)
+        .map(|(player, _, info)| (player, info.map(|info| info.max_health).unwrap_or_default()))
+}
+
+fn record_position(
+    trigger: Trigger<socket::UpdatePlayerPositionEvent>,
+    recording: Option<ResMut<Recording>>,
+    time: Res<Time>,
+    combat_state: Res<State<CombatState>>,
+    fighters: Option<Res<combat::Fighters>>,
+    q_players: Query<(&Player, &socket::Id, Option<&player::Info>)>,
+) {
+    let Some(mut recording) = recording else {
+        return;
+    };
+    let Some((player, max_health)) = fighter(&fighters, &q_players, trigger.1) else {
+        return;
+    };
+    recording.add_fighter(trigger.1, &player.name, max_health);
+    let input = position_input(trigger.1, trigger.0.x, trigger.0.z, combat_state.get());
+    recording.push(time.elapsed_secs(), input);
+}
+
+fn record_ready(
+    trigger: Trigger<socket::CombatReadyStateChangedEvent>,
+    recording: Option<ResMut<Recording>>,
+    time: Res<Time>,
+    fighters: Option<Res<combat::Fighters>>,
+    q_players: Query<(&Player, &socket::Id, Option<&player::Info>)>,
+) {
+    let Some(mut recording) = recording else {
+        return;
+    };
+    let Some((player, max_health)) = fighter(&fighters, &q_players, trigger.peer_id) else {
+        return;
+    };
+    recording.add_fighter(trigger.peer_id, &player.name, max_health);
+    recording.push(
+        time.elapsed_secs(),
+        Input::Ready {
+            id: trigger.peer_id,
+            ready: trigger.is_ready,
+        },
+    );
+}
+
+fn record_start(
+    _trigger: Trigger<socket::CombatStartedEvent>,
+    recording: Option<ResMut<Recording>>,
+    time: Res<Time>,
+) {
+    if let Some(mut recording) = recording {
+        recording.push(time.elapsed_secs(), Input::Start);
+    }
+}
+
+fn on_record(trigger: Trigger<RecordEvent>, recording: Option<ResMut<Recording>>, time: Res<Time>) {
+    if let Some(mut recording) = recording {
+        recording.push(time.elapsed_secs(), trigger.0.clone());
+    }
+}
+
+/// Where the playback is at
+#[derive(Resource)]
+struct Playback {
+    replay: Replay,
+    /// Index of the next entry to apply
+    cursor: usize,
+    time: f32,
+    speed: f32,
+    paused: bool,
+    grid: Handle<map::Chunk>,
+    map_spawned: bool,
+    last_input: String,
+}
+
+#[derive(Component)]
+struct PlaybackInfo;
+
+/// Replay a recorded fight, without any network
+pub fn run(args: ProcessArgs, path: PathBuf) {
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins);
+    let replay = match Replay::read(&path) {
+        Ok(replay) => replay,
+        Err(error) => {
+            error!("Can't read replay {}: {}", path.display(), error);
+            return;
+        }
+    };
+    app.insert_resource(args)
+        .insert_resource(ClearColor(Color::srgb(0.5, 0.5, 0.9)))
+        .insert_resource(Playback {
+            replay,
+            cursor: 0,
+            time: 0.0,
+            speed: 1.0,
+            paused: false,
+            grid: Handle::default(),
+            map_spawned: false,
+            last_input: String::new(),
+        })
+        .add_systems(Startup, (map::setup, setup_playback))
+        .add_systems(
+            Update,
+            (
+                spawn_map,
+                (playback_controls, play, update_playback_info).chain(),
+            ),
+        );
+
+    map::init(&mut app);
+    materials::grid::init(&mut app);
+    materials::player_shadow::init(&mut app);
+    player::init(&mut app);
+
+    app.run();
+}
+
+fn setup_playback(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut playback: ResMut<Playback>,
+) {
+    let chunk = map::ChunkCoordinate::new(playback.replay.chunk_x, playback.replay.chunk_z);
+    playback.grid = asset_server.load(map::chunk::path(chunk));
+    let camera_pivot = commands
+        .spawn((
+            Transform {
+                translation: chunk.world_center(),
+                rotation: Quat::from_rotation_x(-30.0f32.to_radians()),
+                ..default()
+            },
+            Visibility::Hidden,
+        ))
+        .id();
+    commands
+        .spawn((
+            Camera3d::default(),
+            Projection::Orthographic(OrthographicProjection {
+                scaling_mode: ScalingMode::FixedVertical {
+                    viewport_height: (20.0 * map::tile::SPACING_Z) / 100.0 * 116.0,
+                },
+                ..OrthographicProjection::default_3d()
+            }),
+            Tonemapping::None,
+            Transform::from_translation(Vec3::new(0.0, 0.0, 10.0)),
+        ))
+        .set_parent(camera_pivot);
+    commands.spawn((
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        },
+        PlaybackInfo,
+    ));
+}
+
+/// Spawn the fight chunk and the fighters once the chunk is loaded
+fn spawn_map(
+    mut commands: Commands,
+    mut playback: ResMut<Playback>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    map_handles: ResMut<map::Handles>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut player_shadow_materials: ResMut<Assets<PlayerShadowMaterial>>,
+) {
+    if playback.map_spawned {
+        return;
+    }
+    let Some(chunk) = chunk_assets.get(playback.grid.id()) else {
+        return;
+    };
+    playback.map_spawned = true;
+    let chunk_coordinate =
+        map::ChunkCoordinate::new(playback.replay.chunk_x, playback.replay.chunk_z);
+    for z in 0..map::chunk::SIZE_Z {
+        for x in 0..map::chunk::SIZE_X {
+            let local = map::TileCoordinate::<map::tile::LocalSpace>::new(x as i32, z as i32);
+            map::tile::spawn(
+                &mut commands,
+                &map_handles,
+                chunk.tiles[z][x],
+                chunk.get_properties(local),
+                local.to_absolute(chunk_coordinate).to_world(),
+            );
+        }
+    }
+    // Fighters stay hidden until their first placement
+    for fighter in playback.replay.fighters.iter() {
+        spawn_fighter(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &mut player_shadow_materials,
+            fighter,
+            chunk_coordinate.world_center(),
+        );
+    }
+}
+
+fn spawn_fighter(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    player_shadow_materials: &mut ResMut<Assets<PlayerShadowMaterial>>,
+    fighter: &FighterRecord,
+    position: Vec3,
+) {
+    player::spawn_player_character(
+        commands,
+        meshes,
+        materials,
+        player_shadow_materials,
+        Player {
+            name: fighter.name.clone(),
+        },
+        position,
+    )
+    .insert((
+        socket::Id(fighter.id),
+        player::Interpolation::new(position),
+        Visibility::Hidden,
+    ));
+}
+
+fn playback_controls(keyboard_input: Res<ButtonInput<KeyCode>>, mut playback: ResMut<Playback>) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        playback.speed = (playback.speed * 2.0).min(MAX_SPEED);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        playback.speed = (playback.speed / 2.0).max(MIN_SPEED);
+    }
+    // Jump to the next input
+    if playback.paused && keyboard_input.just_pressed(KeyCode::ArrowRight) {
+        if let Some(entry) = playback.replay.entries.get(playback.cursor) {
+            playback.time = entry.at;
+        }
+    }
+}
+
+fn play(
+    time: Res<Time>,
+    mut playback: ResMut<Playback>,
+    mut q_fighters: Query<(
+        &mut Transform,
+        &mut player::Interpolation,
+        &mut Visibility,
+        &socket::Id,
+    )>,
+) {
+    if !playback.map_spawned {
+        return;
+    }
+    if !playback.paused {
+        playback.time += time.delta_secs() * playback.speed;
+    }
+    let names = playback
+        .replay
+        .fighters
+        .iter()
+        .map(|fighter| (fighter.id, fighter.name.clone()))
+        .collect::<HashMap<_, _>>();
+    let name = |id: &PeerId| names.get(id).cloned().unwrap_or_else(|| id.to_string());
+    while let Some(entry) = playback.replay.entries.get(playback.cursor).cloned() {
+        if entry.at > playback.time {
+            break;
+        }
+        playback.cursor += 1;
+        match &entry.input {
+            Input::Placement { id, x, z } => {
+                let position = Vec3::new(*x, 0.0, *z);
+                if let Some((mut transform, mut interpolation, mut visibility, _)) = q_fighters
+                    .iter_mut()
+                    .find(|(_, _, _, fighter)| fighter.0 == *id)
+                {
+                    transform.translation = position + Vec3::Y * (player::PLAYER_SIZES.y / 2.0);
+                    *interpolation = player::Interpolation::new(position);
+                    *visibility = Visibility::Inherited;
+                }
+            }
+            Input::Move { id, x, z } => {
+                let position = Vec3::new(*x, 0.0, *z);
+                if let Some((_, mut interpolation, mut visibility, _)) = q_fighters
+                    .iter_mut()
+                    .find(|(_, _, _, fighter)| fighter.0 == *id)
+                {
+                    interpolation.set_target(position);
+                    *visibility = Visibility::Inherited;
+                }
+            }
+            _ => {}
+        }
+        playback.last_input = entry.input.describe(&name);
+        info!("[{:.2}s] {}", entry.at, playback.last_input);
+    }
+}
+
+fn update_playback_info(playback: Res<Playback>, mut q_info: Query<&mut Text, With<PlaybackInfo>>) {
+    if !playback.is_changed() {
+        return;
+    }
+    let state = if playback.paused { "Paused" } else { "Playing" };
+    for mut text in q_info.iter_mut() {
+        text.0 = format!(
+            "{} x{} - {:.1}s - {}/{} inputs (seed {})\n{}\nSpace: pause, Right: step, Up/Down: speed",
+            state,
+            playback.speed,
+            playback.time,
+            playback.cursor,
+            playback.replay.entries.len(),
+            playback.replay.seed,
+            playback.last_input,
+        );
+    }
+}
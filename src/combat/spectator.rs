@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_matchbox::prelude::PeerId;
+
+use crate::{
+    combat::{self, preparation::PlayerReady, replay, CombatState},
+    map,
+    player::{self, MainPlayer, Player},
+    socket, ui, GameMode,
+};
+
+/// # Interval (in seconds) between two snapshots sent to the spectators
+const SNAPSHOT_INTERVAL: f32 = 1.0;
+/// # Number of turn events shown to the spectators
+const SHOWN_TURN_EVENTS: usize = 5;
+
+pub fn init(app: &mut App) {
+    app.init_resource::<OngoingFights>()
+        .init_resource::<TurnEvents>()
+        .insert_resource(SnapshotTimer(Timer::from_seconds(
+            SNAPSHOT_INTERVAL,
+            TimerMode::Repeating,
+        )))
+        .add_systems(
+            OnEnter(GameMode::Combat),
+            (
+                announce_fight.run_if(not(combat::is_spectating)),
+                hide_main_player.run_if(combat::is_spectating),
+            ),
+        )
+        .add_systems(OnExit(GameMode::Combat), end_fight)
+        .add_systems(
+            Update,
+            (
+                send_snapshots.run_if(resource_exists::<Spectators>),
+                send_turn_events.run_if(resource_exists::<Spectators>),
+                update_panel.run_if(resource_exists::<LastSnapshot>),
+            )
+                .run_if(in_state(GameMode::Combat)),
+        )
+        .add_observer(on_watch)
+        .add_observer(on_stop_watching)
+        .add_observer(on_fight_started)
+        .add_observer(on_fight_over)
+        .add_observer(on_spectate_requested)
+        .add_observer(on_spectate_refused)
+        .add_observer(on_spectate_left)
+        .add_observer(on_snapshot)
+        .add_observer(on_turn_event)
+        .add_observer(on_peer_deconection);
+}
+
+#[derive(Debug, Clone)]
+pub struct OngoingFight {
+    pub chunk: map::ChunkCoordinate,
+    pub fighters: Vec<PeerId>,
+}
+
+/// Fights announced by other peers, by owner
+#[derive(Resource, Default)]
+pub struct OngoingFights(pub HashMap<PeerId, OngoingFight>);
+
+impl OngoingFights {
+    /// Owner of the fight `peer_id` is taking part in
+    pub fn owner_of(&self, peer_id: PeerId) -> Option<(PeerId, &OngoingFight)> {
+        self.0
+            .iter()
+            .find(|(owner, fight)| **owner == peer_id || fight.fighters.contains(&peer_id))
+            .map(|(owner, fight)| (*owner, fight))
+    }
+}
+
+/// Peers watching the fight we own, present for the whole fight
+#[derive(Resource)]
+struct Spectators {
+    chunk: map::ChunkCoordinate,
+    peers: Vec<PeerId>,
+    /// Number of recorded inputs already sent as turn events
+    sent_inputs: usize,
+}
+
+#[derive(Resource)]
+struct SnapshotTimer(Timer);
+
+/// Spectate request sent, waiting for the owner first snapshot
+#[derive(Resource)]
+struct PendingWatch(PeerId);
+
+/// Last snapshot received from the owner of the fight we watch
+#[derive(Resource)]
+struct LastSnapshot(socket::FightSnapshot);
+
+/// Latest turn events of the fight we watch, oldest first
+#[derive(Resource, Default)]
+struct TurnEvents(Vec<String>);
+
+#[derive(Component)]
+struct SpectatorPanel;
+
+/// Ask the owner of a fight to watch it
+#[derive(Debug, Event)]
+pub struct WatchEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+struct StopWatchingEvent;
+
+fn announce_fight(
+    mut commands: Commands,
+    owner: Option<Res<combat::Owner>>,
+    my_id: Option<Res<socket::MyId>>,
+    fighters: Option<Res<combat::Fighters>>,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+) {
+    let (Some(owner), Some(my_id)) = (owner, my_id) else {
+        return;
+    };
+    if owner.0 != my_id.0 {
+        return;
+    }
+    let Ok(transform) = q_main_player.get_single() else {
+        return;
+    };
+    let chunk = map::ChunkCoordinate::from_world(transform.translation);
+    commands.insert_resource(Spectators {
+        chunk,
+        peers: Vec::new(),
+        sent_inputs: 0,
+    });
+    commands.trigger(socket::SendMessageEvent::Broadcast(
+        socket::Message::FightStarted {
+            chunk_x: chunk.x,
+            chunk_z: chunk.z,
+            fighters: fighters
+                .map(|fighters| fighters.0.clone())
+                .unwrap_or_default(),
+        },
+    ));
+}
+
+fn hide_main_player(mut q_main_player: Query<&mut Visibility, With<MainPlayer>>) {
+    for mut visibility in q_main_player.iter_mut() {
+        *visibility = Visibility::Hidden;
+    }
+}
+
+fn end_fight(
+    mut commands: Commands,
+    spectators: Option<Res<Spectators>>,
+    spectator: Option<Res<combat::Spectator>>,
+    owner: Option<Res<combat::Owner>>,
+    mut q_main_player: Query<&mut Visibility, With<MainPlayer>>,
+    q_panel: Query<Entity, With<SpectatorPanel>>,
+) {
+    if spectators.is_some() {
+        commands.remove_resource::<Spectators>();
+        commands.trigger(socket::SendMessageEvent::Broadcast(
+            socket::Message::FightOver,
+        ));
+    }
+    if spectator.is_none() {
+        return;
+    }
+    if let Some(owner) = owner {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::SpectateLeave,
+            owner.0,
+        ));
+    }
+    commands.remove_resource::<combat::Spectator>();
+    commands.remove_resource::<LastSnapshot>();
+    commands.insert_resource(TurnEvents::default());
+    for mut visibility in q_main_player.iter_mut() {
+        *visibility = Visibility::Inherited;
+    }
+    for entity in q_panel.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn on_fight_started(
+    trigger: Trigger<socket::FightStartedEvent>,
+    mut fights: ResMut<OngoingFights>,
+) {
+    info!("{} started a fight", trigger.peer_id);
+    fights.0.insert(
+        trigger.peer_id,
+        OngoingFight {
+            chunk: map::ChunkCoordinate::new(trigger.chunk_x, trigger.chunk_z),
+            fighters: trigger.fighters.clone(),
+        },
+    );
+}
+
+fn on_fight_over(
+    trigger: Trigger<socket::FightOverEvent>,
+    mut commands: Commands,
+    mut fights: ResMut<OngoingFights>,
+    spectator: Option<Res<combat::Spectator>>,
+    owner: Option<Res<combat::Owner>>,
+    mut next_game_mode: ResMut<NextState<GameMode>>,
+) {
+    fights.0.remove(&trigger.0);
+    let watching = spectator.is_some() && owner.is_some_and(|owner| owner.0 == trigger.0);
+    if watching {
+        ui::popup::spawn(&mut commands, "The fight is over".to_string());
+        next_game_mode.set(GameMode::Exploration);
+    }
+}
+
+fn on_watch(
+    trigger: Trigger<WatchEvent>,
+    mut commands: Commands,
+    pending: Option<Res<PendingWatch>>,
+) {
+    if pending.is_some() {
+        return;
+    }
+    info!("Asking {} to watch their fight", trigger.0);
+    commands.insert_resource(PendingWatch(trigger.0));
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::SpectateRequest,
+        trigger.0,
+    ));
+}
+
+fn on_stop_watching(
+    _trigger: Trigger<StopWatchingEvent>,
+    spectator: Option<Res<combat::Spectator>>,
+    mut next_game_mode: ResMut<NextState<GameMode>>,
+) {
+    if spectator.is_some() {
+        next_game_mode.set(GameMode::Exploration);
+    }
+}
+
+fn snapshot(
+    fighters: &combat::Fighters,
+    my_id: PeerId,
+    player_info: &player::Info,
+    q_main_player: &Query<(&Transform, &Player, Option<&PlayerReady>), With<MainPlayer>>,
+    q_players: &Query<
+        (
+            &Transform,
+            &Player,
+            &socket::Id,
+            Option<&player::Info>,
+            Option<&PlayerReady>,
+        ),
+        Without<MainPlayer>,
+    >,
+    combat_state: &CombatState,
+) -> socket::FightSnapshot {
+    let fighters = fighters
+        .0
+        .iter()
+        .filter_map(|id| {
+            if *id == my_id {
+                let (transform, player, ready) = q_main_player.get_single().ok()?;
+                return Some(socket::FighterSnapshot {
+                    id: *id,
+                    name: player.name.clone(),
+                    health: player_info.health(),
+                    max_health: player_info.max_health,
+                    x: transform.translation.x,
+                    z: transform.translation.z,
+                    ready: ready.is_some(),
+                });
+            }
+            let (transform, player, _, info, ready) = q_players
+                .iter()
+                .find(|(_, _, player_id, _, _)| player_id.0 == *id)?;
+            Some(socket::FighterSnapshot {
+                id: *id,
+                name: player.name.clone(),
+                health: info.map(|info| info.health()).unwrap_or_default(),
+                max_health: info.map(|info| info.max_health).unwrap_or_default(),
+                x: transform.translation.x,
+                z: transform.translation.z,
+                ready: ready.is_some(),
+            })
+        })
+        .collect();
+    socket::FightSnapshot {
+        fighters,
+        preparation: *combat_state == CombatState::Preparation,
+    }
+}
+
+fn on_spectate_requested(
+    trigger: Trigger<socket::SpectateRequestedEvent>,
+    mut commands: Commands,
+    spectators: Option<ResMut<Spectators>>,
+    fighters: Option<Res<combat::Fighters>>,
+    my_id: Res<socket::MyId>,
+    player_info: Res<player::Info>,
+    combat_state: Res<State<CombatState>>,
+    q_main_player: Query<(&Transform, &Player, Option<&PlayerReady>), With<MainPlayer>>,
+    q_players: Query<
+        (
+            &Transform,
+            &Player,
+            &socket::Id,
+            Option<&player::Info>,
+            Option<&PlayerReady>,
+        ),
+        Without<MainPlayer>,
+    >,
+) {
+    let peer_id = trigger.0;
+    let (Some(mut spectators), Some(fighters)) = (spectators, fighters) else {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::SpectateRefused,
+            peer_id,
+        ));
+        return;
+    };
+    let on_chunk = q_players
+        .iter()
+        .find(|(_, _, id, _, _)| id.0 == peer_id)
+        .is_some_and(|(transform, _, _, _, _)| {
+            map::ChunkCoordinate::from_world(transform.translation) == spectators.chunk
+        });
+    if !on_chunk || fighters.0.contains(&peer_id) {
+        info!("Refusing spectator {}", peer_id);
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::SpectateRefused,
+            peer_id,
+        ));
+        return;
+    }
+    info!("{} is now watching the fight", peer_id);
+    if !spectators.peers.contains(&peer_id) {
+        spectators.peers.push(peer_id);
+    }
+    let snapshot = snapshot(
+        &fighters,
+        my_id.0,
+        &player_info,
+        &q_main_player,
+        &q_players,
+        combat_state.get(),
+    );
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::FightSnapshot(snapshot),
+        peer_id,
+    ));
+}
+
+fn on_spectate_refused(
+    trigger: Trigger<socket::SpectateRefusedEvent>,
+    mut commands: Commands,
+    pending: Option<Res<PendingWatch>>,
+) {
+    if !pending.is_some_and(|pending| pending.0 == trigger.0) {
+        return;
+    }
+    commands.remove_resource::<PendingWatch>();
+    ui::popup::spawn(&mut commands, "You can't watch this fight".to_string());
+}
+
+fn on_spectate_left(
+    trigger: Trigger<socket::SpectateLeftEvent>,
+    spectators: Option<ResMut<Spectators>>,
+) {
+    if let Some(mut spectators) = spectators {
+        spectators.peers.retain(|peer| *peer != trigger.0);
+    }
+}
+
+fn on_snapshot(
+    trigger: Trigger<socket::FightSnapshotEvent>,
+    mut commands: Commands,
+    pending: Option<Res<PendingWatch>>,
+    spectator: Option<Res<combat::Spectator>>,
+    owner: Option<Res<combat::Owner>>,
+    game_mode: Res<State<GameMode>>,
+    mut next_game_mode: ResMut<NextState<GameMode>>,
+    mut q_players: Query<(&mut player::Interpolation, &socket::Id), Without<MainPlayer>>,
+) {
+    let peer_id = trigger.1;
+    let snapshot = &trigger.0;
+    if pending.is_some_and(|pending| pending.0 == peer_id) {
+        commands.remove_resource::<PendingWatch>();
+        if *game_mode.get() != GameMode::Exploration {
+            return;
+        }
+        info!("Watching the fight of {}", peer_id);
+        commands.insert_resource(combat::Spectator);
+        commands.insert_resource(combat::Owner(peer_id));
+        commands.insert_resource(combat::Fighters(
+            snapshot.fighters.iter().map(|fighter| fighter.id).collect(),
+        ));
+        next_game_mode.set(GameMode::Combat);
+    } else if spectator.is_none() || !owner.is_some_and(|owner| owner.0 == peer_id) {
+        return;
+    }
+    for fighter in snapshot.fighters.iter() {
+        let Some((mut interpolation, _)) = q_players.iter_mut().find(|(_, id)| id.0 == fighter.id)
+        else {
+            continue;
+        };
+        interpolation.set_target(Vec3::new(fighter.x, 0.0, fighter.z));
+    }
+    commands.insert_resource(LastSnapshot(snapshot.clone()));
+}
+
+fn send_snapshots(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut timer: ResMut<SnapshotTimer>,
+    spectators: Res<Spectators>,
+    fighters: Option<Res<combat::Fighters>>,
+    my_id: Res<socket::MyId>,
+    player_info: Res<player::Info>,
+    combat_state: Res<State<CombatState>>,
+    q_main_player: Query<(&Transform, &Player, Option<&PlayerReady>), With<MainPlayer>>,
+    q_players: Query<
+        (
+            &Transform,
+            &Player,
+            &socket::Id,
+            Option<&player::Info>,
+            Option<&PlayerReady>,
+        ),
+        Without<MainPlayer>,
+    >,
+) {
+    timer.0.tick(time.delta());
+    if !timer.0.just_finished() || spectators.peers.is_empty() {
+        return;
+    }
+    let Some(fighters) = fighters else {
+        return;
+    };
+    let snapshot = snapshot(
+        &fighters,
+        my_id.0,
+        &player_info,
+        &q_main_player,
+        &q_players,
+        combat_state.get(),
+    );
+    for peer_id in spectators.peers.iter() {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::FightSnapshot(snapshot.clone()),
+            *peer_id,
+        ));
+    }
+}
+
+/// Forward the inputs recorded since the last call to the spectators
+fn send_turn_events(
+    mut commands: Commands,
+    mut spectators: ResMut<Spectators>,
+    recording: Option<Res<replay::Recording>>,
+) {
+    let Some(recording) = recording else {
+        return;
+    };
+    let entries = recording.entries();
+    if spectators.sent_inputs >= entries.len() {
+        return;
+    }
+    for entry in entries[spectators.sent_inputs..].iter() {
+        for peer_id in spectators.peers.iter() {
+            commands.trigger(socket::SendMessageEvent::ToPeer(
+                socket::Message::FightInput(entry.input.clone()),
+                *peer_id,
+            ));
+        }
+    }
+    spectators.sent_inputs = entries.len();
+}
+
+fn on_turn_event(
+    trigger: Trigger<socket::FightInputEvent>,
+    spectator: Option<Res<combat::Spectator>>,
+    owner: Option<Res<combat::Owner>>,
+    snapshot: Option<Res<LastSnapshot>>,
+    mut turn_events: ResMut<TurnEvents>,
+) {
+    if spectator.is_none() || !owner.is_some_and(|owner| owner.0 == trigger.1) {
+        return;
+    }
+    let name = |id: &PeerId| {
+        snapshot
+            .as_ref()
+            .and_then(|snapshot| snapshot.0.fighters.iter().find(|fighter| fighter.id == *id))
+            .map(|fighter| fighter.name.clone())
+            .unwrap_or_else(|| id.to_string())
+    };
+    turn_events.0.push(trigger.0.describe(name));
+    if turn_events.0.len() > SHOWN_TURN_EVENTS {
+        turn_events.0.remove(0);
+    }
+}
+
+fn on_peer_deconection(
+    trigger: Trigger<socket::PeerDeconectionEvent>,
+    mut fights: ResMut<OngoingFights>,
+    spectators: Option<ResMut<Spectators>>,
+    spectator: Option<Res<combat::Spectator>>,
+    owner: Option<Res<combat::Owner>>,
+    mut next_game_mode: ResMut<NextState<GameMode>>,
+) {
+    fights.0.remove(&trigger.0);
+    if let Some(mut spectators) = spectators {
+        spectators.peers.retain(|peer| *peer != trigger.0);
+    }
+    if spectator.is_some() && owner.is_some_and(|owner| owner.0 == trigger.0) {
+        next_game_mode.set(GameMode::Exploration);
+    }
+}
+
+/// Timeline of the watched fight, with every fighter and the latest turn events
+fn update_panel(
+    mut commands: Commands,
+    snapshot: Res<LastSnapshot>,
+    turn_events: Res<TurnEvents>,
+    q_panel: Query<Entity, With<SpectatorPanel>>,
+) {
+    if !snapshot.is_changed() && !turn_events.is_changed() {
+        return;
+    }
+    for entity in q_panel.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let panel = commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                width: Val::Px(160.0),
+                padding: UiRect::all(Val::Px(5.0)),
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            BorderRadius::all(Val::Px(5.0)),
+            BackgroundColor(Color::hsl(246.0, 0.21, 0.29)),
+            SpectatorPanel,
+        ))
+        .id();
+    let title = if snapshot.0.preparation {
+        "Watching - Preparation"
+    } else {
+        "Watching"
+    };
+    commands
+        .spawn((
+            Text::new(title),
+            TextFont {
+                font_size: 12.0,
+                ..default()
+            },
+        ))
+        .set_parent(panel);
+    for fighter in snapshot.0.fighters.iter() {
+        let ready = if snapshot.0.preparation && fighter.ready {
+            " (ready)"
+        } else {
+            ""
+        };
+        commands
+            .spawn((
+                Text::new(format!(
+                    "{}{}\n{}/{} HP",
+                    fighter.name, ready, fighter.health, fighter.max_health
+                )),
+                TextColor(Color::hsl(242.0, 0.15, 0.57)),
+                TextFont {
+                    font_size: 10.0,
+                    ..default()
+                },
+            ))
+            .set_parent(panel);
+        let health = fighter.health as f32 / fighter.max_health.max(1) as f32;
+        commands
+            .spawn((
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Px(4.0),
+                    ..default()
+                },
+                BackgroundColor(Color::hsl(244.0, 0.17, 0.19)),
+            ))
+            .with_child((
+                Node {
+                    width: Val::Percent(health * 100.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                BackgroundColor(Color::hsl(110.0, 0.52, 0.55)),
+            ))
+            .set_parent(panel);
+    }
+    for event in turn_events.0.iter() {
+        commands
+            .spawn((
+                Text::new(event.clone()),
+                TextFont {
+                    font_size: 9.0,
+                    ..default()
+                },
+            ))
+            .set_parent(panel);
+    }
+    commands
+        .spawn((
+            Text::new("Leave"),
+            BackgroundColor(Color::hsl(209.0, 0.59, 0.61)),
+        ))
+        .observe(|_trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+            commands.trigger(StopWatchingEvent);
+        })
+        .set_parent(panel);
+}
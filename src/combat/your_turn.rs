@@ -99,7 +99,7 @@ fn spawn_movement_tiles(
         |x, z, depth| {
             if depth == 0 {
                 true // Skip the player tile
-            } else if player_chunk.tiles[z][x] != map::Tile::Ground {
+            } else if !player_chunk.tiles[z][x].is_walkable() {
                 false // Dead end
             } else {
                 commands.spawn((
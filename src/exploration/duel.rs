@@ -0,0 +1,504 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_matchbox::prelude::PeerId;
+
+use crate::{combat, player::Player, socket, ui, GameMode};
+
+/// # Time (in seconds) a duel demand stay open before being dropped
+pub const TIMEOUT: f32 = 30.0;
+
+pub fn init(app: &mut App) {
+    app.init_resource::<Negotiations>()
+        .add_systems(
+            Update,
+            tick_timeouts.run_if(in_state(GameMode::Exploration)),
+        )
+        .add_systems(OnExit(GameMode::Exploration), drop_all)
+        .add_observer(on_challenge)
+        .add_observer(on_demand_received)
+        .add_observer(on_accepted)
+        .add_observer(on_refused)
+        .add_observer(on_busy)
+        .add_observer(on_cancelled)
+        .add_observer(on_peer_deconection);
+}
+
+/// Where a duel negotiation with a single peer is at
+#[derive(Debug)]
+enum Negotiation {
+    /// We challenged them and wait for their answer
+    Sent { popup: Entity, timeout: Timer },
+    /// They challenged us and we didn't answer yet
+    Received { popup: Entity, timeout: Timer },
+}
+
+impl Negotiation {
+    fn popup(&self) -> Entity {
+        match self {
+            Negotiation::Sent { popup, .. } | Negotiation::Received { popup, .. } => *popup,
+        }
+    }
+}
+
+/// Every ongoing duel negotiation, one per peer
+#[derive(Resource, Default)]
+struct Negotiations(HashMap<PeerId, Negotiation>);
+
+impl Negotiations {
+    fn is_challenging(&self) -> bool {
+        self.0
+            .values()
+            .any(|negotiation| matches!(negotiation, Negotiation::Sent { .. }))
+    }
+
+    fn remove(&mut self, commands: &mut Commands, peer_id: PeerId) -> Option<Negotiation> {
+        let negotiation = self.0.remove(&peer_id)?;
+        ui::popup::close(commands, negotiation.popup());
+        Some(negotiation)
+    }
+
+    /// Refuse or cancel everything, used once a duel has been settled
+    fn drop_all(&mut self, commands: &mut Commands) {
+        for (peer_id, negotiation) in self.0.drain() {
+            ui::popup::close(commands, negotiation.popup());
+            let message = match negotiation {
+                Negotiation::Sent { .. } => socket::Message::DuelCancelled,
+                Negotiation::Received { .. } => socket::Message::DuelBusy,
+            };
+            commands.trigger(socket::SendMessageEvent::ToPeer(message, peer_id));
+        }
+    }
+}
+
+/// Challenge a peer to a duel
+#[derive(Debug, Event)]
+pub struct ChallengeEvent(pub PeerId);
+
+fn player_name(q_player: &Query<(&Player, &socket::Id)>, peer_id: PeerId) -> String {
+    q_player
+        .iter()
+        .find(|(_, id)| id.0 == peer_id)
+        .map(|(player, _)| player.name.clone())
+        .unwrap_or_else(|| peer_id.to_string())
+}
+
+fn start_duel(
+    commands: &mut Commands,
+    negotiations: &mut Negotiations,
+    next_gamemode: &mut NextState<GameMode>,
+    owner: PeerId,
+    opponent: PeerId,
+) {
+    negotiations.drop_all(commands);
+    commands.insert_resource(combat::Owner(owner));
+    commands.insert_resource(combat::Kind::Duel);
+    commands.insert_resource(combat::Fighters(vec![owner, opponent]));
+    next_gamemode.set(GameMode::Combat);
+}
+
+fn on_challenge(
+    trigger: Trigger<ChallengeEvent>,
+    mut commands: Commands,
+    mut negotiations: ResMut<Negotiations>,
+) {
+    let peer_id = trigger.0;
+    if negotiations.is_challenging() {
+        ui::popup::spawn(
+            &mut commands,
+            "A duel demand is already pending".to_string(),
+        );
+        return;
+    }
+    if negotiations.0.contains_key(&peer_id) {
+        return;
+    }
+    info!("Sending duel demand to {}", peer_id);
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::DuelDemand,
+        peer_id,
+    ));
+    let popup = ui::popup::spawn(&mut commands, "Duel demand sent".to_string())
+        .observe(
+            move |_trigger: Trigger<ui::popup::CloseEvent>,
+                  mut commands: Commands,
+                  mut negotiations: ResMut<Negotiations>| {
+                if negotiations.0.remove(&peer_id).is_some() {
+                    info!("Duel demand cancelled");
+                    commands.trigger(socket::SendMessageEvent::ToPeer(
+                        socket::Message::DuelCancelled,
+                        peer_id,
+                    ));
+                }
+            },
+        )
+        .id();
+    negotiations.0.insert(
+        peer_id,
+        Negotiation::Sent {
+            popup,
+            timeout: Timer::from_seconds(TIMEOUT, TimerMode::Once),
+        },
+    );
+}
+
+fn on_demand_received(
+    trigger: Trigger<socket::DuelDemandReceivedEvent>,
+    mut commands: Commands,
+    mut negotiations: ResMut<Negotiations>,
+    game_mode: Res<State<GameMode>>,
+    q_player: Query<(&Player, &socket::Id)>,
+) {
+    let peer_id = trigger.0;
+    info!("Duel demand received from {}", peer_id);
+    let busy = *game_mode.get() != GameMode::Exploration || negotiations.is_challenging();
+    if busy {
+        info!("Already busy, declining duel from {}", peer_id);
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::DuelBusy,
+            peer_id,
+        ));
+        return;
+    }
+    if negotiations.0.contains_key(&peer_id) {
+        return;
+    }
+    let popup = ui::popup::spawn_with_choices(
+        &mut commands,
+        format!("{} want to fight!", player_name(&q_player, peer_id)),
+        "Accept",
+        "Decline",
+    )
+    .observe(
+        move |trigger: Trigger<ui::popup::ButtonEvent>,
+              mut commands: Commands,
+              mut negotiations: ResMut<Negotiations>,
+              mut next_gamemode: ResMut<NextState<GameMode>>,
+              my_id: Res<socket::MyId>| {
+            // Already settled, cancelled or timed out
+            if negotiations.0.remove(&peer_id).is_none() {
+                return;
+            }
+            match trigger.event() {
+                ui::popup::ButtonEvent::Primary => {
+                    info!("Duel accepted");
+                    commands.trigger(socket::SendMessageEvent::ToPeer(
+                        socket::Message::DuelAccepted,
+                        peer_id,
+                    ));
+                    start_duel(
+                        &mut commands,
+                        &mut negotiations,
+                        &mut next_gamemode,
+                        peer_id,
+                        my_id.0,
+                    );
+                }
+                ui::popup::ButtonEvent::Secondary => {
+                    info!("Duel refused");
+                    commands.trigger(socket::SendMessageEvent::ToPeer(
+                        socket::Message::DuelRefused,
+                        peer_id,
+                    ));
+                }
+            }
+        },
+    )
+    .id();
+    negotiations.0.insert(
+        peer_id,
+        Negotiation::Received {
+            popup,
+            timeout: Timer::from_seconds(TIMEOUT, TimerMode::Once),
+        },
+    );
+}
+
+fn on_accepted(
+    trigger: Trigger<socket::DuelAcceptedEvent>,
+    mut commands: Commands,
+    mut negotiations: ResMut<Negotiations>,
+    mut next_gamemode: ResMut<NextState<GameMode>>,
+    my_id: Res<socket::MyId>,
+) {
+    let peer_id = trigger.0;
+    if !matches!(negotiations.0.get(&peer_id), Some(Negotiation::Sent { .. })) {
+        // They answered too late, let them know the demand doesn't stand anymore
+        warn!("Duel accepted by {} but no demand is pending", peer_id);
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::DuelCancelled,
+            peer_id,
+        ));
+        return;
+    }
+    info!("Duel accepted by {}", peer_id);
+    negotiations.remove(&mut commands, peer_id);
+    start_duel(
+        &mut commands,
+        &mut negotiations,
+        &mut next_gamemode,
+        my_id.0,
+        peer_id,
+    );
+}
+
+fn on_refused(
+    trigger: Trigger<socket::DuelRefusedEvent>,
+    mut commands: Commands,
+    mut negotiations: ResMut<Negotiations>,
+    q_player: Query<(&Player, &socket::Id)>,
+) {
+    if !matches!(
+        negotiations.0.get(&trigger.0),
+        Some(Negotiation::Sent { .. })
+    ) {
+        return;
+    }
+    info!("Duel refused by {}", trigger.0);
+    negotiations.remove(&mut commands, trigger.0);
+    ui::popup::spawn(
+        &mut commands,
+        format!("{} refused the duel", player_name(&q_player, trigger.0)),
+    );
+}
+
+fn on_busy(
+    trigger: Trigger<socket::DuelBusyEvent>,
+    mut commands: Commands,
+    mut negotiations: ResMut<Negotiations>,
+    q_player: Query<(&Player, &socket::Id)>,
+) {
+    if !matches!(
+        negotiations.0.get(&trigger.0),
+        Some(Negotiation::Sent { .. })
+    ) {
+        return;
+    }
+    info!("{} is busy", trigger.0);
+    negotiations.remove(&mut commands, trigger.0);
+    ui::popup::spawn(
+        &mut commands,
+        format!("{} is busy", player_name(&q_player, trigger.0)),
+    );
+}
+
+fn on_cancelled(
+    trigger: Trigger<socket::DuelCancelEvent>,
+    mut commands: Commands,
+    mut negotiations: ResMut<Negotiations>,
+    game_mode: Res<State<GameMode>>,
+    owner: Option<Res<combat::Owner>>,
+    mut next_gamemode: ResMut<NextState<GameMode>>,
+) {
+    let peer_id = trigger.0;
+    if matches!(
+        negotiations.0.get(&peer_id),
+        Some(Negotiation::Received { .. })
+    ) {
+        info!("Duel cancelled by {}", peer_id);
+        negotiations.remove(&mut commands, peer_id);
+        return;
+    }
+    // We accepted after they gave up, the fight never started on their side
+    if *game_mode.get() == GameMode::Combat && owner.is_some_and(|owner| owner.0 == peer_id) {
+        warn!("Duel with {} cancelled after being accepted", peer_id);
+        commands.remove_resource::<combat::Owner>();
+        commands.remove_resource::<combat::Fighters>();
+        next_gamemode.set(GameMode::Exploration);
+    }
+}
+
+fn on_peer_deconection(
+    trigger: Trigger<socket::PeerDeconectionEvent>,
+    mut commands: Commands,
+    mut negotiations: ResMut<Negotiations>,
+) {
+    negotiations.remove(&mut commands, trigger.0);
+}
+
+fn tick_timeouts(mut commands: Commands, time: Res<Time>, mut negotiations: ResMut<Negotiations>) {
+    let mut expired = Vec::new();
+    for (peer_id, negotiation) in negotiations.0.iter_mut() {
+        let timeout = match negotiation {
+            Negotiation::Sent { timeout, .. } | Negotiation::Received { timeout, .. } => timeout,
+        };
+        if timeout.tick(time.delta()).finished() {
+            expired.push(*peer_id);
+        }
+    }
+    for peer_id in expired {
+        let Some(negotiation) = negotiations.remove(&mut commands, peer_id) else {
+            continue;
+        };
+        info!("Duel demand with {} timed out", peer_id);
+        let message = match negotiation {
+            Negotiation::Sent { .. } => {
+                ui::popup::spawn(&mut commands, "No answer to the duel demand".to_string());
+                socket::Message::DuelCancelled
+            }
+            Negotiation::Received { .. } => socket::Message::DuelRefused,
+        };
+        commands.trigger(socket::SendMessageEvent::ToPeer(message, peer_id));
+    }
+}
+
+fn drop_all(mut commands: Commands, mut negotiations: ResMut<Negotiations>) {
+    negotiations.drop_all(&mut commands);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        materials::player_shadow::PlayerShadowMaterial,
+        socket::simulation::{Harness, LinkConditions},
+    };
+
+    const STEP: Duration = Duration::from_millis(100);
+    /// Updates needed for a negotiation to time out, with some margin
+    const TIMEOUT_UPDATES: usize = (TIMEOUT / STEP.as_secs_f32()) as usize + 10;
+
+    /// Two peers running the duel negotiation, the first one is the challenger
+    fn harness(conditions: LinkConditions) -> (Harness, PeerId, PeerId) {
+        let mut harness = Harness::new(conditions, 42, STEP);
+        let setup = |app: &mut App| {
+            app.init_state::<GameMode>()
+                .init_resource::<Assets<Mesh>>()
+                .init_resource::<Assets<StandardMaterial>>()
+                .init_resource::<Assets<PlayerShadowMaterial>>()
+                .add_systems(Update, socket::poll_messages.run_if(socket::is_connected));
+            init(app);
+        };
+        let (_, challenger) = harness.add_app(setup);
+        let (_, challenged) = harness.add_app(setup);
+        (harness, challenger, challenged)
+    }
+
+    fn game_mode(app: &App) -> GameMode {
+        app.world().resource::<State<GameMode>>().get().clone()
+    }
+
+    fn negotiations(app: &App) -> &Negotiations {
+        app.world().resource::<Negotiations>()
+    }
+
+    /// Popup of the demand received from `peer_id`, if it arrived
+    fn received_popup(app: &App, peer_id: PeerId) -> Option<Entity> {
+        match negotiations(app).0.get(&peer_id)? {
+            Negotiation::Received { popup, .. } => Some(*popup),
+            Negotiation::Sent { .. } => None,
+        }
+    }
+
+    #[test]
+    fn accepted_demand_starts_the_duel_on_both_sides() {
+        let (mut harness, challenger, challenged) = harness(LinkConditions {
+            latency: Duration::from_millis(150),
+            jitter: Duration::from_millis(100),
+            ..default()
+        });
+        harness.apps[0]
+            .world_mut()
+            .trigger(ChallengeEvent(challenged));
+        assert!(harness.update_until(10, |apps| received_popup(&apps[1], challenger).is_some()));
+
+        let popup = received_popup(&harness.apps[1], challenger).unwrap();
+        harness.apps[1]
+            .world_mut()
+            .trigger_targets(ui::popup::ButtonEvent::Primary, popup);
+        assert!(harness.update_until(10, |apps| apps
+            .iter()
+            .all(|app| game_mode(app) == GameMode::Combat)));
+        for app in harness.apps.iter() {
+            assert_eq!(app.world().resource::<combat::Owner>().0, challenger);
+            assert_eq!(
+                app.world().resource::<combat::Fighters>().0,
+                vec![challenger, challenged]
+            );
+            assert!(negotiations(app).0.is_empty());
+        }
+    }
+
+    #[test]
+    fn duel_can_be_demanded_again_after_a_fight() {
+        let (mut harness, challenger, challenged) = harness(LinkConditions::default());
+        harness.apps[0]
+            .world_mut()
+            .trigger(ChallengeEvent(challenged));
+        assert!(harness.update_until(10, |apps| received_popup(&apps[1], challenger).is_some()));
+        let popup = received_popup(&harness.apps[1], challenger).unwrap();
+        harness.apps[1]
+            .world_mut()
+            .trigger_targets(ui::popup::ButtonEvent::Primary, popup);
+        assert!(harness.update_until(10, |apps| apps
+            .iter()
+            .all(|app| game_mode(app) == GameMode::Combat)));
+
+        for app in harness.apps.iter_mut() {
+            app.world_mut()
+                .resource_mut::<NextState<GameMode>>()
+                .set(GameMode::Exploration);
+        }
+        harness.update();
+        harness.apps[1]
+            .world_mut()
+            .trigger(ChallengeEvent(challenger));
+        assert!(harness.update_until(10, |apps| received_popup(&apps[0], challenged).is_some()));
+    }
+
+    #[test]
+    fn lost_demand_times_out() {
+        let (mut harness, challenger, challenged) = harness(LinkConditions {
+            loss: 1.0,
+            ..default()
+        });
+        harness.apps[0]
+            .world_mut()
+            .trigger(ChallengeEvent(challenged));
+        harness.update();
+        assert!(negotiations(&harness.apps[0]).is_challenging());
+        assert!(
+            harness.update_until(TIMEOUT_UPDATES, |apps| !negotiations(&apps[0])
+                .is_challenging())
+        );
+        assert!(negotiations(&harness.apps[1]).0.is_empty());
+
+        // Nothing is left pending, the demand can be sent again once the network is back
+        harness.network.set_conditions(LinkConditions::default());
+        harness.apps[0]
+            .world_mut()
+            .trigger(ChallengeEvent(challenged));
+        assert!(harness.update_until(10, |apps| received_popup(&apps[1], challenger).is_some()));
+    }
+
+    /// The challenger never hears about the acceptance, its cancel once timed out
+    /// takes the other side back out of the fight
+    #[test]
+    fn lost_acceptance_is_cancelled() {
+        let (mut harness, challenger, challenged) = harness(LinkConditions::default());
+        harness.apps[0]
+            .world_mut()
+            .trigger(ChallengeEvent(challenged));
+        assert!(harness.update_until(10, |apps| received_popup(&apps[1], challenger).is_some()));
+
+        let popup = received_popup(&harness.apps[1], challenger).unwrap();
+        harness.network.set_conditions(LinkConditions {
+            loss: 1.0,
+            ..default()
+        });
+        harness.apps[1]
+            .world_mut()
+            .trigger_targets(ui::popup::ButtonEvent::Primary, popup);
+        harness.update();
+        assert_eq!(game_mode(&harness.apps[1]), GameMode::Combat);
+
+        harness.network.set_conditions(LinkConditions::default());
+        assert!(harness.update_until(TIMEOUT_UPDATES, |apps| apps
+            .iter()
+            .all(|app| game_mode(app) == GameMode::Exploration)));
+        assert!(negotiations(&harness.apps[0]).0.is_empty());
+        assert!(!harness.apps[1].world().contains_resource::<combat::Owner>());
+    }
+}
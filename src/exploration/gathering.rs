@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+
+use crate::{
+    map::{self, chunk::NodeKind, nodes},
+    player::{MainPlayer, Player},
+    profession::Profession,
+    save, socket, ui, GameMode, ProcessArgs,
+};
+
+use super::interaction::UseEvent;
+
+/// # Profession levels needed for every extra item gathered at once
+const LEVELS_PER_EXTRA_ITEM: u32 = 10;
+
+pub fn init(app: &mut App) {
+    app.add_systems(Update, gather.run_if(in_state(GameMode::Exploration)))
+        .add_systems(OnExit(GameMode::Exploration), cancel)
+        .add_observer(on_use);
+}
+
+/// Node the main player is harvesting, interrupted if they leave the cell they stand on
+#[derive(Resource, Debug)]
+struct Gathering {
+    node: map::TileCoordinate,
+    stand: map::TileCoordinate,
+    kind: NodeKind,
+    timer: Timer,
+}
+
+fn system_line(commands: &mut Commands, text: String) {
+    commands.trigger(ui::chat::ChatLineEvent {
+        channel: socket::ChatChannel::System,
+        author: None,
+        text,
+    });
+}
+
+/// Start harvesting the resource node next to the main player
+fn on_use(
+    trigger: Trigger<UseEvent>,
+    mut commands: Commands,
+    gathering: Option<Res<Gathering>>,
+    depleted: Res<nodes::Depleted>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+    q_nodes: Query<(Entity, &nodes::NodeMarker)>,
+) {
+    let cell = trigger.0;
+    let Some(node) = map::movement::loaded_chunk(cell.to_chunk(), &asset_server, &chunk_assets)
+        .and_then(|chunk| chunk.node_at(cell.to_local()))
+    else {
+        return;
+    };
+    if gathering.is_some() {
+        return;
+    }
+    if depleted.contains(cell) {
+        system_line(
+            &mut commands,
+            format!("This {:?} was harvested recently", node.kind),
+        );
+        return;
+    }
+    let Ok(transform) = q_main_player.get_single() else {
+        return;
+    };
+    if let Some((entity, _)) = q_nodes.iter().find(|(_, marker)| marker.cell == cell) {
+        commands.entity(entity).insert(nodes::Harvesting);
+    }
+    commands.insert_resource(Gathering {
+        node: cell,
+        stand: map::TileCoordinate::from_world(transform.translation),
+        kind: node.kind,
+        timer: Timer::from_seconds(nodes::harvest(node.kind).duration, TimerMode::Once),
+    });
+}
+
+/// Wait for the harvest to end, then collect the items and tell the players on the chunk,
+/// through the dedicated server when there is one
+fn gather(
+    mut commands: Commands,
+    time: Res<Time>,
+    gathering: Option<ResMut<Gathering>>,
+    mut depleted: ResMut<nodes::Depleted>,
+    mut save_data_assets: ResMut<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+    args: Res<ProcessArgs>,
+    server_id: Option<Res<socket::ServerId>>,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+    q_players: Query<(&Transform, &socket::Id), (With<Player>, Without<MainPlayer>)>,
+    q_nodes: Query<(Entity, &nodes::NodeMarker), With<nodes::Harvesting>>,
+) {
+    let Some(mut gathering) = gathering else {
+        return;
+    };
+    let Ok(transform) = q_main_player.get_single() else {
+        return;
+    };
+    let moved = map::TileCoordinate::from_world(transform.translation) != gathering.stand;
+    // Someone else was faster
+    let taken = depleted.contains(gathering.node);
+    if !moved && !taken && !gathering.timer.tick(time.delta()).finished() {
+        return;
+    }
+    commands.remove_resource::<Gathering>();
+    for (entity, _) in q_nodes
+        .iter()
+        .filter(|(_, marker)| marker.cell == gathering.node)
+    {
+        commands.entity(entity).remove::<nodes::Harvesting>();
+    }
+    if taken {
+        system_line(
+            &mut commands,
+            format!("This {:?} was harvested by someone else", gathering.kind),
+        );
+    }
+    if moved || taken {
+        return;
+    }
+
+    let harvest = nodes::harvest(gathering.kind);
+    depleted.deplete(gathering.node, gathering.kind);
+    let message = || socket::Message::NodeHarvested {
+        x: gathering.node.x,
+        z: gathering.node.z,
+    };
+    if let Some(server_id) = server_id {
+        // The server relays it to the players on the chunk
+        commands.trigger(socket::SendMessageEvent::ToPeer(message(), server_id.0));
+    } else {
+        let chunk = gathering.node.to_chunk();
+        for (_, id) in q_players.iter().filter(|(transform, _)| {
+            map::ChunkCoordinate::from_world(transform.translation) == chunk
+        }) {
+            commands.trigger(socket::SendMessageEvent::ToPeer(message(), id.0));
+        }
+    }
+
+    let Some(save_data) = save_data_assets.get_mut(save_handle.0.id()) else {
+        return;
+    };
+    let profession = Profession::of(gathering.kind);
+    let progress = save_data.professions.entry(profession).or_default();
+    let amount = 1 + progress.level / LEVELS_PER_EXTRA_ITEM;
+    let level_up = progress.gain(harvest.experience);
+    let level = progress.level;
+    save_data.inventory.add(harvest.item, amount);
+    save::persist(save_data, &args);
+    system_line(
+        &mut commands,
+        format!(
+            "Harvested {} x{} (+{} {:?} XP)",
+            harvest.item, amount, harvest.experience, profession
+        ),
+    );
+    if level_up {
+        system_line(
+            &mut commands,
+            format!("{:?} reached level {}", profession, level),
+        );
+    }
+}
+
+fn cancel(mut commands: Commands, q_nodes: Query<Entity, With<nodes::Harvesting>>) {
+    commands.remove_resource::<Gathering>();
+    for entity in q_nodes.iter() {
+        commands.entity(entity).remove::<nodes::Harvesting>();
+    }
+}
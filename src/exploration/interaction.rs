@@ -0,0 +1,210 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    map::{self, chunk::ObjectKind, objects},
+    player::{self, MainPlayer, Player},
+    socket, ui, GameMode,
+};
+
+use super::{path_finding, transition};
+
+pub fn init(app: &mut App) {
+    app.add_systems(
+        Update,
+        (click_object.run_if(not(ui::chat::is_typing)), reach_object)
+            .chain()
+            .run_if(in_state(GameMode::Exploration)),
+    )
+    .add_systems(OnExit(GameMode::Exploration), cancel)
+    .add_observer(on_use);
+}
+
+/// Object or resource node the main player is walking to, and the cell from which it will be used
+#[derive(Resource, Debug)]
+struct PendingUse {
+    object: map::TileCoordinate,
+    stand: map::TileCoordinate,
+}
+
+/// Use the object or harvest the resource node standing on an absolute cell,
+/// the main player being next to it
+#[derive(Debug, Event)]
+pub struct UseEvent(pub map::TileCoordinate);
+
+/// Walk next to the object or resource node under the cursor, it will be used once reached
+fn click_object(
+    mut commands: Commands,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    q_main_player: Query<(Entity, &Transform), With<MainPlayer>>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    states: Res<objects::ObjectStates>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Ok((camera, camera_transform)) = q_camera.get_single() else {
+        return;
+    };
+    let Some(position) = q_window
+        .single()
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .and_then(|ray| {
+            ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))
+                .map(|distance| ray.get_point(distance))
+        })
+    else {
+        return;
+    };
+    let object = map::TileCoordinate::from_world(position);
+    let is_usable = map::movement::loaded_chunk(object.to_chunk(), &asset_server, &chunk_assets)
+        .is_some_and(|chunk| {
+            chunk.object_at(object.to_local()).is_some()
+                || chunk.node_at(object.to_local()).is_some()
+        });
+    if !is_usable {
+        return;
+    }
+    let Ok((entity, transform)) = q_main_player.get_single() else {
+        return;
+    };
+    let player_cell = map::TileCoordinate::from_world(transform.translation);
+    // Already next to it, no need to move
+    let stand = if player_cell.walk_distance(object) <= 1 {
+        Some(player_cell)
+    } else {
+        object
+            .adjacent()
+            .filter(|cell| {
+                map::movement::loaded_tile(*cell, &asset_server, &chunk_assets, &states)
+                    .is_some_and(map::Tile::is_walkable)
+            })
+            .min_by_key(|cell| cell.walk_distance(player_cell))
+    };
+    let Some(walk) = stand
+        .and_then(|stand| {
+            path_finding::find(player_cell, stand, |cell| {
+                map::movement::loaded_tile(cell, &asset_server, &chunk_assets, &states)
+            })
+        })
+        .and_then(player::Walk::new)
+    else {
+        info!("Nowhere to stand next to the object on {}", object);
+        return;
+    };
+    commands.insert_resource(PendingUse {
+        object,
+        stand: walk.destination(),
+    });
+    commands.entity(entity).insert(walk);
+}
+
+fn reach_object(
+    mut commands: Commands,
+    pending_use: Option<Res<PendingUse>>,
+    mut q_main_player: Query<(&mut Transform, Option<&player::Walk>), With<MainPlayer>>,
+) {
+    let Some(pending_use) = pending_use else {
+        return;
+    };
+    let Ok((mut transform, walk)) = q_main_player.get_single_mut() else {
+        return;
+    };
+    // Walked somewhere else in the meantime
+    if walk.is_some_and(|walk| walk.destination() != pending_use.stand) {
+        commands.remove_resource::<PendingUse>();
+        return;
+    }
+    if map::TileCoordinate::from_world(transform.translation) != pending_use.stand || walk.is_some()
+    {
+        return;
+    }
+    commands.remove_resource::<PendingUse>();
+    let target = pending_use
+        .object
+        .to_world()
+        .with_y(transform.translation.y);
+    if target != transform.translation {
+        transform.look_at(target, Vec3::Y);
+    }
+    commands.trigger(UseEvent(pending_use.object));
+}
+
+/// Apply the object effect, and tell the players on its chunk about the new states
+fn on_use(
+    trigger: Trigger<UseEvent>,
+    mut commands: Commands,
+    mut states: ResMut<objects::ObjectStates>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    q_objects: Query<(Entity, &objects::ObjectMarker)>,
+    q_players: Query<(&Transform, &socket::Id), (With<Player>, Without<MainPlayer>)>,
+    server_id: Option<Res<socket::ServerId>>,
+) {
+    let cell = trigger.0;
+    let Some(object) = map::movement::loaded_chunk(cell.to_chunk(), &asset_server, &chunk_assets)
+        .and_then(|chunk| chunk.object_at(cell.to_local()))
+    else {
+        return;
+    };
+    if let Some((entity, _)) = q_objects.iter().find(|(_, marker)| marker.0 == cell) {
+        commands.entity(entity).insert(objects::Pulse::default());
+    }
+
+    let chunk = cell.to_chunk();
+    let toggled = match &object.kind {
+        ObjectKind::Teleporter { x, z } => {
+            transition::travel(&mut commands, map::TileCoordinate::new(*x, *z));
+            return;
+        }
+        ObjectKind::Door { .. } => vec![cell],
+        ObjectKind::Lever { targets } => std::iter::once(cell)
+            .chain(targets.iter().map(|(x, z)| {
+                map::TileCoordinate::<map::tile::LocalSpace>::new(*x, *z).to_absolute(chunk)
+            }))
+            .collect(),
+    };
+    let chunk_data = map::movement::loaded_chunk(cell.to_chunk(), &asset_server, &chunk_assets);
+    // The dedicated server validates the new states and relays them to the players on the chunk
+    let peers = match server_id {
+        Some(server_id) => vec![server_id.0],
+        None => q_players
+            .iter()
+            .filter(|(transform, _)| {
+                map::ChunkCoordinate::from_world(transform.translation) == chunk
+            })
+            .map(|(_, id)| id.0)
+            .collect::<Vec<_>>(),
+    };
+    for target in toggled {
+        let Some(kind) = chunk_data
+            .and_then(|data| data.object_at(target.to_local()))
+            .map(|object| &object.kind)
+        else {
+            warn!(
+                "Lever on {} is linked to {}, where there is no object",
+                cell, target
+            );
+            continue;
+        };
+        let active = !states.is_active(target, kind);
+        states.set(target, active);
+        for peer in peers.iter() {
+            commands.trigger(socket::SendMessageEvent::ToPeer(
+                socket::Message::ObjectState {
+                    x: target.x,
+                    z: target.z,
+                    active,
+                },
+                *peer,
+            ));
+        }
+    }
+}
+
+fn cancel(mut commands: Commands) {
+    commands.remove_resource::<PendingUse>();
+}
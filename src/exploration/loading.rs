@@ -1,4 +1,7 @@
-use bevy::prelude::*;
+use bevy::{
+    asset::{LoadState, UntypedAssetId},
+    prelude::*,
+};
 
 use crate::{map, save};
 
@@ -22,55 +25,106 @@ pub fn init(app: &mut App) {
         );
 }
 
+/// Assets waited for before entering the world
 #[derive(Resource)]
-pub struct Handles(pub Vec<UntypedHandle>);
+pub struct Handles {
+    save_data: Handle<save::Data>,
+    world_map: Handle<map::WorldMap>,
+    /// Chunk the player starts in, requested once the save and the world map are loaded
+    chunk: Option<map::CurrentChunk>,
+    /// Spawn chunk the player is moved to, when the saved position isn't on the world map
+    relocated_to: Option<map::ChunkCoordinate>,
+}
 
 pub fn start(
     mut next_loading_state: ResMut<NextState<LoadingState>>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    args: Res<crate::ProcessArgs>,
 ) {
-    commands.insert_resource(Handles(vec![asset_server
-        .load::<save::Data>("player_info.ron")
-        .untyped()]));
+    commands.insert_resource(Handles {
+        save_data: asset_server.load(save::file_name(&args)),
+        world_map: asset_server.load(map::world::PATH),
+        chunk: None,
+        relocated_to: None,
+    });
 
     next_loading_state.set(LoadingState::Loading);
 }
 
 pub fn poll(
+    mut commands: Commands,
     mut next_loading_state: ResMut<NextState<LoadingState>>,
     asset_server: Res<AssetServer>,
+    mut streamer: ResMut<map::streaming::Streamer>,
+    streaming_settings: Res<map::streaming::Settings>,
     mut handles: ResMut<Handles>,
-    save_data_assets: Res<Assets<save::Data>>,
+    mut save_data_assets: ResMut<Assets<save::Data>>,
+    world_map_assets: Res<Assets<map::WorldMap>>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    mut app_exit: EventWriter<AppExit>,
 ) {
-    let count = handles.0.len();
-    if count == 1 {
-        let save_data_handle = handles.0.first().unwrap().clone().typed::<save::Data>();
-        if asset_server
-            .get_load_state(save_data_handle.id())
-            .unwrap()
-            .is_loaded()
-        {
-            let save_data = save_data_assets.get(save_data_handle.id()).unwrap();
-            let player_chunk_coords = map::ChunkCoordinate::from_world(save_data.player_position);
-            let chunk = map::chunk::load(player_chunk_coords, &asset_server).unwrap();
-            handles
-                .0
-                .extend([chunk.grid.untyped(), chunk.background.untyped()]);
+    let Some(chunk) = handles.chunk.clone() else {
+        let (Some(save_data), Some(world_map)) = (
+            save_data_assets.get(handles.save_data.id()),
+            world_map_assets.get(handles.world_map.id()),
+        ) else {
+            return;
+        };
+        let mut enter = |coord| {
+            streamer.enter(
+                &mut commands,
+                coord,
+                &asset_server,
+                world_map,
+                &streaming_settings,
+            )
+        };
+        let saved = map::ChunkCoordinate::from_world(save_data.player_position);
+        if let Some(chunk) = enter(saved) {
+            handles.chunk = Some(chunk);
+            return;
         }
+        let spawn = world_map.spawn_chunk();
+        warn!(
+            "Player is in {:?}, which isn't in the world map, moving them to {:?}",
+            saved, spawn
+        );
+        // Nowhere to put the player, the game can't start
+        let Some(chunk) = enter(spawn) else {
+            error!(
+                "The spawn chunk {:?} isn't in the world map or its files are missing, exiting",
+                spawn
+            );
+            commands.remove_resource::<Handles>();
+            next_loading_state.set(LoadingState::Idle);
+            app_exit.send(AppExit::error());
+            return;
+        };
+        handles.chunk = Some(chunk);
+        handles.relocated_to = Some(spawn);
+        return;
+    };
+
+    // Don't wait forever on a missing chunk file, the streaming will report it
+    let settled = |id: UntypedAssetId| {
+        matches!(
+            asset_server.get_load_state(id),
+            Some(LoadState::Loaded | LoadState::Failed(_))
+        )
+    };
+    if !settled(chunk.grid.id().untyped())
+        || (chunk.background != Handle::default() && !settled(chunk.background.id().untyped()))
+    {
+        return;
     }
 
-    for handle in handles.0.iter() {
-        if asset_server
-            .get_load_state(handle.id())
-            .unwrap()
-            .is_loaded()
-            == false
-        {
-            return;
+    if let Some(spawn) = handles.relocated_to {
+        let cell = map::world::spawn_cell(spawn, chunk_assets.get(chunk.grid.id()));
+        if let Some(save_data) = save_data_assets.get_mut(handles.save_data.id()) {
+            save_data.player_position = cell.to_world();
         }
     }
-
     next_loading_state.set(LoadingState::Completed);
 }
 
@@ -9,18 +9,22 @@ use bevy::{
     window::{PrimaryWindow, SystemCursorIcon},
     winit::cursor::CursorIcon,
 };
-use bevy_mod_raycast::prelude::{RaycastMesh, RaycastSource};
+use bevy_mod_raycast::prelude::{Raycast, RaycastMesh, RaycastSource};
 use loading::LoadingState;
 
 use crate::{
-    combat, map,
+    combat, map, party,
     player::{self, CameraPivot, MainPlayer, Player},
-    save, socket, ui, GameMode,
+    save, socket, trade, ui, GameMode,
 };
 
+mod duel;
+mod gathering;
+mod interaction;
 mod loading;
-// mod network;
-// mod path_finding;
+mod network;
+pub mod path_finding;
+mod transition;
 
 /// State only relevent if GameMode is Exploration
 #[derive(States, Default, Debug, Clone, PartialEq, Eq, Hash)]
@@ -32,7 +36,12 @@ enum State {
 }
 
 pub fn init(app: &mut App) {
-    // network::init(app);
+    network::init(app);
+    duel::init(app);
+    transition::init(app);
+    path_finding::init(app);
+    interaction::init(app);
+    gathering::init(app);
     map::init(app);
     loading::init(app);
 
@@ -46,6 +55,12 @@ pub fn init(app: &mut App) {
                 //map::preload_adjacents,
                 player::spawn_main_character,
             ),
+        )
+        .add_systems(
+            Update,
+            context_menu
+                .run_if(in_state(GameMode::Exploration))
+                .run_if(not(ui::chat::is_typing)),
         );
 
     // .add_systems(OnEnter(State::Exploration),
@@ -73,8 +88,8 @@ fn go_in_explaration_mode(
     next_game_mode.set(GameMode::Exploration);
 }
 
-// #[derive(Component)]
-// pub struct ExplorationObserver;
+#[derive(Component)]
+pub struct ExplorationObserver;
 
 // #[derive(Component)]
 // #[require(Transform)]
@@ -246,11 +261,24 @@ fn spawn_camera(mut commands: Commands, mut image_assets: ResMut<Assets<Image>>)
 //     asset_server: ResMut<AssetServer>,
 //     args: Res<crate::ProcessArgs>,
 //     render_target_image: Res<RenderTargetImage>,
+//     current_world_map: Res<map::world::CurrentWorldMap>,
+//     mut streamer: ResMut<map::streaming::Streamer>,
+//     streaming_settings: Res<map::streaming::Settings>,
+//     world_map_assets: Res<Assets<map::WorldMap>>,
 // ) {
 //     info!("Entering new chunk: {:?}", trigger.0);
-//     let current_chunk = map::load_chunk(trigger.0, commands.reborrow(), &asset_server);
+//     let world_map = world_map_assets.get(current_world_map.0.id()).unwrap();
+//     let Some(current_chunk) = streamer.enter(
+//         &mut commands,
+//         trigger.0,
+//         &asset_server,
+//         world_map,
+//         &streaming_settings,
+//     ) else {
+//         return;
+//     };
 //     q_camera_pivot.single_mut().translation = trigger.0.world_center();
-//     ui::update_map_info(&mut commands, trigger.0, q_map_info);
+//     ui::update_map_info(&mut commands, trigger.0, world_map, q_map_info);
 //     // despawn old map
 //     for tile_entity in q_map_component.iter() {
 //         commands.entity(tile_entity).despawn_recursive();
@@ -265,7 +293,14 @@ fn spawn_camera(mut commands: Commands, mut image_assets: ResMut<Assets<Image>>)
 //                     trigger.0.z * map::chunk::SIZE_Z as i32 + z as i32,
 //                 );
 //                 let tile = chunk.get_tile(tile_coord.to_local()).unwrap().clone();
-//                 map::tile::spawn(&mut commands, &map_handles, tile, tile_coord.to_world());
+//                 let properties = chunk.get_properties(tile_coord.to_local());
+//                 map::tile::spawn(
+//                     &mut commands,
+//                     &map_handles,
+//                     tile,
+//                     properties,
+//                     tile_coord.to_world(),
+//                 );
 //             }
 //         }
 //     }
@@ -364,63 +399,83 @@ fn spawn_camera(mut commands: Commands, mut image_assets: ResMut<Assets<Image>>)
 //     }
 // }
 
-// // TODO: Can be called when already opened
-// fn context_menu(
-//     mut commands: Commands,
-//     mouse_button_input: Res<ButtonInput<MouseButton>>,
-//     q_player: Query<&Transform, With<Player>>,
-//     q_camera: Query<(&Camera, &GlobalTransform)>,
-//     q_raycast_source: Query<&RaycastSource<()>>,
-//     q_main_player: Query<Entity, With<MainPlayer>>,
-// ) {
-//     if !mouse_button_input.just_pressed(MouseButton::Right) {
-//         return;
-//     }
-//     let Some(res) = q_raycast_source.single().get_intersections() else {
-//         return;
-//     };
-//     let (camera, camera_transform) = q_camera.single();
-//     for entry in res.iter() {
-//         if let Some(_) = q_main_player.get(entry.0).ok() {
-//             continue;
-//         }
-//         let Some(player) = q_player.get(entry.0).ok() else {
-//             continue;
-//         };
-//         let player_entity = entry.0;
-//         let viewport_position = camera
-//             .world_to_viewport(camera_transform, player.translation)
-//             .unwrap();
-//         ui::context_menu::spawn(&mut commands, viewport_position, |commands, entity| {
-//             commands.entity(entity).with_children(|parent| {
-//                 parent.spawn(Text::new("Duel")).observe(
-//                     move |_trigger: Trigger<Pointer<Down>>,
-//                           mut commands: Commands,
-//                           q_peer_id: Query<&socket::Id>| {
-//                         info!("Sending duel demand");
-//                         let peer_id = q_peer_id.get(player_entity).unwrap().0;
-//                         commands.trigger(socket::SendMessageEvent::ToPeer(
-//                             socket::Message::DuelDemand,
-//                             peer_id.clone(),
-//                         ));
-//                         ui::popup::spawn(&mut commands, "Duel demand sent".to_string()).observe(
-//                             move |trigger: Trigger<ui::popup::CloseEvent>,
-//                                   mut commands: Commands| {
-//                                 info!("Duel demand cancelled");
-//                                 commands.trigger(socket::SendMessageEvent::ToPeer(
-//                                     socket::Message::DuelCancelled,
-//                                     peer_id,
-//                                 ));
-//                                 commands.entity(trigger.entity()).despawn_recursive();
-//                             },
-//                         );
-//                     },
-//                 );
-//             });
-//         });
-//         return;
-//     }
-// }
+// TODO: Can be called when already opened
+fn context_menu(
+    mut commands: Commands,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    mut raycast: Raycast,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_player: Query<(&Transform, &socket::Id), (With<Player>, Without<MainPlayer>)>,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    fights: Res<combat::spectator::OngoingFights>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let (camera, camera_transform) = q_camera.single();
+    let Some(ray) = q_window
+        .single()
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+    else {
+        return;
+    };
+    for (entity, _) in raycast.cast_ray(ray, &default()).iter() {
+        let player_entity = *entity;
+        let Some((player, peer_id)) = q_player.get(player_entity).ok() else {
+            continue;
+        };
+        // Only fights happening on our chunk can be watched
+        let watchable_fight = fights
+            .owner_of(peer_id.0)
+            .filter(|(_, fight)| {
+                q_main_player.get_single().is_ok_and(|transform| {
+                    map::ChunkCoordinate::from_world(transform.translation) == fight.chunk
+                })
+            })
+            .map(|(owner, _)| owner);
+        let viewport_position = camera
+            .world_to_viewport(camera_transform, player.translation)
+            .unwrap();
+        ui::context_menu::spawn(&mut commands, viewport_position, |commands, entity| {
+            commands.entity(entity).with_children(|parent| {
+                parent.spawn(Text::new("Duel")).observe(
+                    move |_trigger: Trigger<Pointer<Down>>,
+                          mut commands: Commands,
+                          q_peer_id: Query<&socket::Id>| {
+                        let peer_id = q_peer_id.get(player_entity).unwrap().0;
+                        commands.trigger(duel::ChallengeEvent(peer_id));
+                    },
+                );
+                parent.spawn(Text::new("Invite to group")).observe(
+                    move |_trigger: Trigger<Pointer<Down>>,
+                          mut commands: Commands,
+                          q_peer_id: Query<&socket::Id>| {
+                        let peer_id = q_peer_id.get(player_entity).unwrap().0;
+                        commands.trigger(party::InviteEvent(peer_id));
+                    },
+                );
+                parent.spawn(Text::new("Trade")).observe(
+                    move |_trigger: Trigger<Pointer<Down>>,
+                          mut commands: Commands,
+                          q_peer_id: Query<&socket::Id>| {
+                        let peer_id = q_peer_id.get(player_entity).unwrap().0;
+                        commands.trigger(trade::RequestEvent(peer_id));
+                    },
+                );
+                if let Some(owner) = watchable_fight {
+                    parent.spawn(Text::new("Watch fight")).observe(
+                        move |_trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+                            commands.trigger(combat::spectator::WatchEvent(owner));
+                        },
+                    );
+                }
+            });
+        });
+        return;
+    }
+}
 
 // fn cleanup(
 //     mut commands: Commands,
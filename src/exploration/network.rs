@@ -1,105 +1,28 @@
 use crate::{exploration::*, socket};
 use bevy::prelude::*;
-use bevy_matchbox::prelude::PeerId;
 
 pub fn init(app: &mut App) {
-    app.add_systems(OnEnter(GameMode::Exploration), setup)
+    app.add_systems(OnEnter(GameMode::Exploration), setup.run_if(run_once))
         .add_systems(
             Update,
-            replicate_player_position
+            (replicate_player_position, catch_up_new_peers)
                 .run_if(in_state(GameMode::Exploration))
                 .run_if(socket::is_connected),
         );
 }
 
+/// Open the socket the first time the world is entered, it stays open when coming back from a fight
 fn setup(mut commands: Commands) {
     commands.spawn((Observer::new(on_peer_position_update), ExplorationObserver));
     commands.spawn((Observer::new(on_new_peer_connection), ExplorationObserver));
     commands.spawn((Observer::new(on_peer_deconection), ExplorationObserver));
-    commands.spawn((Observer::new(on_duel_demand_received), ExplorationObserver));
-    commands.spawn((Observer::new(on_duel_refused), ExplorationObserver));
-    commands.spawn((Observer::new(on_duel_accepted), ExplorationObserver));
-    commands.spawn((Observer::new(on_duel_cancelled), ExplorationObserver));
     commands.spawn((
-        Observer::new(
-            |trigger: Trigger<ui::popup::ButtonEvent>,
-             mut next_gamemode: ResMut<NextState<GameMode>>,
-             mut commands: Commands,
-             sender_network_id: Res<SenderNetworkId>| match trigger.event() {
-                ui::popup::ButtonEvent::Primary => {
-                    commands.trigger(socket::SendMessageEvent::ToPeer(
-                        socket::Message::DuelAccepted,
-                        sender_network_id.0,
-                    ));
-                    next_gamemode.set(GameMode::Combat);
-                    commands.insert_resource(combat::Owner(sender_network_id.0));
-                }
-                ui::popup::ButtonEvent::Secondary => {
-                    commands.trigger(socket::SendMessageEvent::ToPeer(
-                        socket::Message::DuelRefused,
-                        sender_network_id.0,
-                    ));
-                }
-            },
-        ),
+        Observer::new(on_authoritative_position),
         ExplorationObserver,
     ));
     socket::start_connection(commands);
 }
 
-#[derive(Resource)]
-struct SenderNetworkId(PeerId);
-
-fn on_duel_demand_received(
-    trigger: Trigger<socket::DuelDemandReceivedEvent>,
-    mut commands: Commands,
-    q_player: Query<(&Player, &socket::Id)>,
-) {
-    info!("Duel demand received");
-    let player = q_player.iter().find(|(_, id)| id.0 == trigger.0);
-    if let Some((player, _id)) = player {
-        let sender_network_id = trigger.0.clone();
-        commands.insert_resource(SenderNetworkId(sender_network_id));
-        ui::popup::spawn_with_choices(
-            &mut commands,
-            format!("{} want to fight!", player.name),
-            "Accept",
-            "Decline",
-        )
-        .observe(
-            |trigger: Trigger<ui::popup::ButtonEvent>, mut commands: Commands| {
-                todo!("Make it work");
-            },
-        );
-    }
-}
-
-fn on_duel_accepted(
-    _trigger: Trigger<socket::DuelAcceptedEvent>,
-    mut next_gamemode: ResMut<NextState<GameMode>>,
-    mut commands: Commands,
-    q_popup: Query<Entity, With<ui::popup::Popup>>,
-    my_id: Res<socket::MyId>,
-) {
-    info!("Duel accepted");
-    for popup in q_popup.iter() {
-        commands.entity(popup).despawn_recursive();
-    }
-    next_gamemode.set(GameMode::Combat);
-    commands.insert_resource(combat::Owner(my_id.0));
-}
-
-fn on_duel_refused(
-    _trigger: Trigger<socket::DuelRefusedEvent>,
-    mut commands: Commands,
-    q_popup: Query<Entity, With<ui::popup::Popup>>,
-) {
-    info!("Duel refused");
-    for popup in q_popup.iter() {
-        commands.entity(popup).despawn_recursive();
-    }
-}
-
 fn on_new_peer_connection(
     trigger: Trigger<socket::NewPeerConnectionEvent>,
     mut commands: Commands,
@@ -118,6 +41,31 @@ fn on_new_peer_connection(
     commands.trigger(socket::SendMessageEvent::ToPeer(message, trigger.0));
 }
 
+/// Without a dedicated server, the players already on a chunk tell the ones joining it
+/// what changed there
+fn catch_up_new_peers(
+    mut commands: Commands,
+    server_id: Option<Res<socket::ServerId>>,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+    q_new_players: Query<(&Transform, &socket::Id), (With<Player>, Added<socket::Id>)>,
+) {
+    if server_id.is_some() {
+        return;
+    }
+    let Ok(main_player) = q_main_player.get_single() else {
+        return;
+    };
+    let chunk = map::ChunkCoordinate::from_world(main_player.translation);
+    for (transform, id) in q_new_players.iter() {
+        if map::ChunkCoordinate::from_world(transform.translation) == chunk {
+            commands.trigger(map::streaming::CatchUpEvent {
+                peer_id: id.0,
+                chunk,
+            });
+        }
+    }
+}
+
 fn on_peer_deconection(
     trigger: Trigger<socket::PeerDeconectionEvent>,
     mut commands: Commands,
@@ -135,30 +83,73 @@ fn on_peer_deconection(
 fn on_peer_position_update(
     trigger: Trigger<socket::UpdatePlayerPositionEvent>,
     mut commands: Commands,
-    q_players: Query<(Entity, &socket::Id), With<Player>>,
+    mut q_players: Query<
+        (
+            &mut Transform,
+            &mut player::Interpolation,
+            &mut map::movement::Validated,
+            &Player,
+            &socket::Id,
+        ),
+        Without<MainPlayer>,
+    >,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+    server_id: Option<Res<socket::ServerId>>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    objects: Res<map::objects::ObjectStates>,
+    time: Res<Time>,
 ) {
-    for player in q_players.iter() {
-        if player.1 .0 == trigger.1 {
-            commands
-                .entity(player.0)
-                .insert(Transform::from_translation(Vec3::new(
-                    trigger.0.x,
-                    player::PLAYER_SIZES.y / 2.0,
-                    trigger.0.z,
-                )));
-            break;
-        }
+    // The dedicated server relay validated positions instead
+    if server_id.is_some() {
+        return;
     }
-}
-
-fn on_duel_cancelled(
-    _trigger: Trigger<socket::DuelCancelEvent>,
-    mut commands: Commands,
-    q_popup: Query<Entity, With<ui::popup::Popup>>,
-) {
-    info!("Duel cancelled");
-    for popup in q_popup.iter() {
-        commands.entity(popup).despawn_recursive();
+    let Some((mut transform, mut interpolation, mut validated, player, id)) = q_players
+        .iter_mut()
+        .find(|(_, _, _, _, id)| id.0 == trigger.1)
+    else {
+        return;
+    };
+    let position = Vec3::new(trigger.0.x, 0.0, trigger.0.z);
+    let target = map::TileCoordinate::from_world(position);
+    let now = time.elapsed_secs();
+    let validation = map::movement::validate_loaded(
+        validated.cell,
+        target,
+        now - validated.at,
+        &asset_server,
+        &chunk_assets,
+        &objects,
+    );
+    match validation {
+        Ok(()) => {
+            let chunk = target.to_chunk();
+            let arrived = chunk != validated.cell.to_chunk()
+                && q_main_player.get_single().is_ok_and(|main_player| {
+                    map::ChunkCoordinate::from_world(main_player.translation) == chunk
+                });
+            if arrived {
+                commands.trigger(map::streaming::CatchUpEvent {
+                    peer_id: id.0,
+                    chunk,
+                });
+            }
+            // Standing still doesn't earn extra steps for later
+            *validated = map::movement::Validated {
+                cell: target,
+                at: now,
+            };
+            interpolation.set_target(position);
+        }
+        Err(violation) => {
+            warn!(
+                "Rejected move of {} from {} to {}: {}",
+                player.name, validated.cell, target, violation
+            );
+            let last_valid = validated.cell.to_world();
+            transform.translation = last_valid + Vec3::Y * (player::PLAYER_SIZES.y / 2.0);
+            interpolation.set_target(last_valid);
+        }
     }
 }
 
@@ -175,3 +166,29 @@ fn replicate_player_position(
         }),
     ));
 }
+
+fn on_authoritative_position(
+    trigger: Trigger<socket::AuthoritativePositionEvent>,
+    my_id: Res<socket::MyId>,
+    mut q_main_player: Query<&mut Transform, With<MainPlayer>>,
+    mut q_players: Query<(&mut player::Interpolation, &socket::Id), With<Player>>,
+) {
+    let position = Vec3::new(trigger.position.x, 0.0, trigger.position.z);
+    if trigger.id == my_id.0 {
+        // Only corrections move us, valid moves are already where they should be
+        let mut transform = q_main_player.single_mut();
+        if map::TileCoordinate::from_world(transform.translation)
+            != map::TileCoordinate::from_world(position)
+        {
+            warn!("Position corrected by the server");
+            transform.translation = position + Vec3::Y * (player::PLAYER_SIZES.y / 2.0);
+        }
+        return;
+    }
+    for (mut interpolation, id) in q_players.iter_mut() {
+        if id.0 == trigger.id {
+            interpolation.set_target(position);
+            break;
+        }
+    }
+}
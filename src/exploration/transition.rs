@@ -0,0 +1,261 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    map,
+    player::{self, CameraPivot, MainPlayer},
+    ui, GameMode,
+};
+
+use super::path_finding;
+
+/// # Duration (in seconds) of the fade out, and then of the fade in, when changing chunk
+const FADE_DURATION: f32 = 0.25;
+
+pub fn init(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            click_exit.run_if(not(ui::chat::is_typing)),
+            reach_exit,
+            fade,
+        )
+            .chain()
+            .run_if(in_state(GameMode::Exploration)),
+    )
+    .add_systems(OnExit(GameMode::Exploration), cancel);
+}
+
+/// Exit the main player is walking to
+#[derive(Resource, Debug)]
+struct PendingExit {
+    cell: map::TileCoordinate,
+    exit: map::chunk::Exit,
+    /// Chunk the world map links the exit edge to
+    destination: map::ChunkCoordinate,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FadeStage {
+    Out,
+    In,
+}
+
+/// Black overlay hiding the chunk swap
+#[derive(Component, Debug)]
+struct Fade {
+    stage: FadeStage,
+    timer: Timer,
+    entry: map::TileCoordinate,
+}
+
+/// Walk to the exit under the cursor, leaving the chunk once it is reached
+fn click_exit(
+    mut commands: Commands,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    q_main_player: Query<(Entity, &Transform), With<MainPlayer>>,
+    q_fade: Query<(), With<Fade>>,
+    current_chunk: Option<Res<map::CurrentChunk>>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    current_world_map: Res<map::world::CurrentWorldMap>,
+    world_map_assets: Res<Assets<map::WorldMap>>,
+    asset_server: Res<AssetServer>,
+    objects: Res<map::objects::ObjectStates>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Left) || !q_fade.is_empty() {
+        return;
+    }
+    let Some(chunk) = current_chunk.and_then(|current| chunk_assets.get(current.grid.id())) else {
+        return;
+    };
+    let Ok((camera, camera_transform)) = q_camera.get_single() else {
+        return;
+    };
+    let Some(position) = q_window
+        .single()
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .and_then(|ray| {
+            ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))
+                .map(|distance| ray.get_point(distance))
+        })
+    else {
+        return;
+    };
+    let cell = map::TileCoordinate::from_world(position);
+    let Some(exit) = chunk.exit_at(cell.to_local()) else {
+        return;
+    };
+    let Some(destination) = world_map_assets
+        .get(current_world_map.0.id())
+        .and_then(|world_map| world_map.get(cell.to_chunk()))
+        .and_then(|info| info.neighbours.get(exit.edge))
+    else {
+        return;
+    };
+    let Ok((entity, transform)) = q_main_player.get_single() else {
+        return;
+    };
+    let Some(walk) = path_finding::find(
+        map::TileCoordinate::from_world(transform.translation),
+        cell,
+        |cell| map::movement::loaded_tile(cell, &asset_server, &chunk_assets, &objects),
+    )
+    .and_then(player::Walk::new) else {
+        info!("No way to the exit on {}", cell);
+        return;
+    };
+    commands.entity(entity).insert(walk);
+    commands.insert_resource(PendingExit {
+        cell,
+        exit,
+        destination,
+    });
+}
+
+/// Cell the player appear on in `destination`, the chunk linked to the exit edge
+/// Right across the edge if it is walkable, otherwise the closest exit going back
+/// The destination doesn't have to be the chunk laying across the edge on the grid,
+/// the cell across is mapped into it
+fn entry_cell(
+    exit_cell: map::TileCoordinate,
+    exit: map::chunk::Exit,
+    destination: map::ChunkCoordinate,
+    asset_server: &AssetServer,
+    chunk_assets: &Assets<map::Chunk>,
+    objects: &map::objects::ObjectStates,
+) -> Option<map::TileCoordinate> {
+    let (x, z) = exit.edge.direction();
+    let across = (exit_cell + map::TileCoordinate::new(x, z))
+        .to_local()
+        .to_absolute(destination);
+    if map::movement::loaded_tile(across, asset_server, chunk_assets, objects)
+        .is_some_and(map::Tile::is_walkable)
+    {
+        return Some(across);
+    }
+    map::movement::loaded_chunk(destination, asset_server, chunk_assets)?
+        .exits_on(exit.edge.opposite())
+        .into_iter()
+        .map(|entry| entry.cell().to_absolute(destination))
+        .min_by(|a, b| {
+            let target = across.to_world();
+            a.to_world()
+                .distance_squared(target)
+                .total_cmp(&b.to_world().distance_squared(target))
+        })
+}
+
+fn reach_exit(
+    mut commands: Commands,
+    pending_exit: Option<Res<PendingExit>>,
+    q_main_player: Query<(&Transform, Option<&player::Walk>), With<MainPlayer>>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    objects: Res<map::objects::ObjectStates>,
+) {
+    let Some(pending_exit) = pending_exit else {
+        return;
+    };
+    let Ok((transform, walk)) = q_main_player.get_single() else {
+        return;
+    };
+    // Walked somewhere else in the meantime
+    if walk.is_some_and(|walk| walk.destination() != pending_exit.cell) {
+        commands.remove_resource::<PendingExit>();
+        return;
+    }
+    if map::TileCoordinate::from_world(transform.translation) != pending_exit.cell || walk.is_some()
+    {
+        return;
+    }
+    commands.remove_resource::<PendingExit>();
+    let Some(entry) = entry_cell(
+        pending_exit.cell,
+        pending_exit.exit,
+        pending_exit.destination,
+        &asset_server,
+        &chunk_assets,
+        &objects,
+    ) else {
+        warn!(
+            "Next chunk isn't loaded or has no way in, can't leave by {}",
+            pending_exit.cell
+        );
+        return;
+    };
+    travel(&mut commands, entry);
+}
+
+/// Fade out, move the main player to `entry` (in any chunk) and fade back in
+pub(super) fn travel(commands: &mut Commands, entry: map::TileCoordinate) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.0)),
+        GlobalZIndex(200),
+        Fade {
+            stage: FadeStage::Out,
+            timer: Timer::from_seconds(FADE_DURATION, TimerMode::Once),
+            entry,
+        },
+    ));
+}
+
+/// Darken the screen, move the player to the next chunk while it is black, then fade back in
+fn fade(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut q_fade: Query<(Entity, &mut Fade, &mut BackgroundColor)>,
+    mut q_main_player: Query<(Entity, &mut Transform), (With<MainPlayer>, Without<CameraPivot>)>,
+    mut q_camera_pivot: Query<&mut Transform, (With<CameraPivot>, Without<MainPlayer>)>,
+    q_map_info: Query<Entity, With<ui::MapUiInfo>>,
+    current_world_map: Res<map::world::CurrentWorldMap>,
+    world_map_assets: Res<Assets<map::WorldMap>>,
+) {
+    let Ok((entity, mut fade, mut background)) = q_fade.get_single_mut() else {
+        return;
+    };
+    fade.timer.tick(time.delta());
+    let progress = fade.timer.fraction();
+    let alpha = match fade.stage {
+        FadeStage::Out => progress,
+        FadeStage::In => 1.0 - progress,
+    };
+    background.0 = Color::BLACK.with_alpha(alpha);
+    if !fade.timer.finished() {
+        return;
+    }
+    if fade.stage == FadeStage::In {
+        commands.entity(entity).despawn_recursive();
+        return;
+    }
+
+    let destination = fade.entry.to_chunk();
+    info!("Leaving through {} to {:?}", fade.entry, destination);
+    if let Ok((player_entity, mut transform)) = q_main_player.get_single_mut() {
+        transform.translation = fade.entry.to_world() + Vec3::Y * (player::PLAYER_SIZES.y / 2.0);
+        commands.entity(player_entity).remove::<player::Walk>();
+    }
+    if let Ok(mut pivot) = q_camera_pivot.get_single_mut() {
+        pivot.translation = destination.world_center();
+    }
+    commands.trigger(map::streaming::TransitionEvent(destination));
+    if let Some(world_map) = world_map_assets.get(current_world_map.0.id()) {
+        ui::update_map_info(&mut commands, destination, world_map, q_map_info);
+    }
+    fade.stage = FadeStage::In;
+    fade.timer.reset();
+}
+
+fn cancel(mut commands: Commands, q_fade: Query<Entity, With<Fade>>) {
+    commands.remove_resource::<PendingExit>();
+    for entity in q_fade.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Items and currency owned by a character
+/// Also used to describe a subset of them, like a trade offer
+#[derive(Default, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inventory {
+    pub currency: u64,
+    /// Amount owned per item name
+    pub items: BTreeMap<String, u32>,
+}
+
+impl Inventory {
+    pub fn count(&self, item: &str) -> u32 {
+        self.items.get(item).copied().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, item: impl Into<String>, amount: u32) {
+        if amount == 0 {
+            return;
+        }
+        *self.items.entry(item.into()).or_default() += amount;
+    }
+
+    /// Remove `amount` of `item`, nothing is removed if there is not enough of it
+    pub fn remove(&mut self, item: &str, amount: u32) -> bool {
+        let Some(count) = self.items.get_mut(item) else {
+            return amount == 0;
+        };
+        if *count < amount {
+            return false;
+        }
+        *count -= amount;
+        if *count == 0 {
+            self.items.remove(item);
+        }
+        true
+    }
+
+    /// Whether everything in `self` is also owned by `other`
+    pub fn is_subset_of(&self, other: &Inventory) -> bool {
+        self.currency <= other.currency
+            && self
+                .items
+                .iter()
+                .all(|(item, amount)| other.count(item) >= *amount)
+    }
+
+    /// Move everything in `given` out and everything in `received` in
+    /// Nothing change if `given` isn't owned
+    pub fn exchange(&mut self, given: &Inventory, received: &Inventory) -> bool {
+        if !given.is_subset_of(self) {
+            return false;
+        }
+        self.currency -= given.currency;
+        for (item, amount) in given.items.iter() {
+            self.remove(item, *amount);
+        }
+        self.currency += received.currency;
+        for (item, amount) in received.items.iter() {
+            self.add(item.clone(), *amount);
+        }
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.currency == 0 && self.items.is_empty()
+    }
+}
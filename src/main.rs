@@ -7,12 +7,19 @@ use clap::Parser;
 
 mod combat;
 mod exploration;
+mod inventory;
 // mod loading;
 mod map;
 mod materials;
+mod npc;
+mod party;
 mod player;
+mod profession;
+mod quest;
 mod save;
+mod server;
 mod socket;
+mod trade;
 mod ui;
 
 #[derive(States, Default, Debug, Clone, PartialEq, Eq, Hash)]
@@ -37,10 +44,39 @@ struct ProcessArgs {
     /// Draw map grid on top of background
     #[arg(long, short)]
     show_grid: bool,
+    /// Run as a headless dedicated server
+    #[arg(long)]
+    server: bool,
+    /// Peer id of the dedicated server to trust, without it every peer claiming to be one is ignored
+    #[arg(long)]
+    server_peer: Option<uuid::Uuid>,
+    /// Play back a recorded fight instead of starting the game
+    #[arg(long)]
+    replay: Option<std::path::PathBuf>,
+    /// Check every chunk file of the map folder and exit
+    #[arg(long)]
+    validate_maps: bool,
+    /// Convert every chunk file of the map folder to this format and exit
+    #[arg(long)]
+    convert_chunks: Option<map::chunk::binary::Format>,
 }
 
 fn main() {
     let args = ProcessArgs::parse();
+    if args.validate_maps {
+        std::process::exit(map::validate::run());
+    }
+    if let Some(format) = args.convert_chunks {
+        std::process::exit(map::chunk::binary::convert_all(format));
+    }
+    if args.server {
+        server::run(args);
+        return;
+    }
+    if let Some(path) = args.replay.clone() {
+        combat::replay::run(args, path);
+        return;
+    }
     let use_grid = args.show_grid;
 
     let mut app = App::new();
@@ -68,7 +104,7 @@ fn main() {
             ..default()
         },
     })
-    .add_systems(Update, dev_interaction);
+    .add_systems(Update, dev_interaction.run_if(not(ui::chat::is_typing)));
 
     materials::player_shadow::init(&mut app);
     if use_grid {
@@ -76,8 +112,13 @@ fn main() {
     }
 
     ui::init(&mut app);
+    player::init(&mut app);
     exploration::init(&mut app);
+    npc::init(&mut app);
+    quest::init(&mut app);
     combat::init(&mut app);
+    party::init(&mut app);
+    trade::init(&mut app);
     socket::init(&mut app);
 
     app.run();
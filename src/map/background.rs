@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use bevy::{pbr::NotShadowCaster, prelude::*};
+
+use crate::{
+    map::{self, streaming::Streamer, Chunk, ChunkCoordinate},
+    GameMode,
+};
+
+pub(super) fn init(app: &mut App) {
+    app.init_resource::<Backgrounds>()
+        .add_systems(
+            Update,
+            sync_backgrounds.run_if(in_state(GameMode::Exploration)),
+        )
+        .add_systems(OnExit(GameMode::Exploration), despawn_all);
+}
+
+/// Background entity of every loaded chunk that has an image
+#[derive(Resource, Default)]
+struct Backgrounds(HashMap<(i32, i32), Entity>);
+
+#[derive(Component)]
+pub struct BackgroundImage;
+
+/// Spawn the backgrounds of the chunks being loaded, despawn the released ones,
+/// and keep them where their chunk data say
+fn sync_backgrounds(
+    mut commands: Commands,
+    mut backgrounds: ResMut<Backgrounds>,
+    streamer: Res<Streamer>,
+    chunk_assets: Res<Assets<Chunk>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut q_transform: Query<&mut Transform, With<BackgroundImage>>,
+) {
+    backgrounds.0.retain(|(x, z), entity| {
+        let loaded = streamer.is_loaded(ChunkCoordinate::new(*x, *z));
+        if !loaded {
+            commands.entity(*entity).despawn_recursive();
+        }
+        loaded
+    });
+    for (coord, current) in streamer.loaded() {
+        if current.background == Handle::default() {
+            continue;
+        }
+        let Some(chunk) = chunk_assets.get(current.grid.id()) else {
+            continue;
+        };
+        let transform = chunk.background.transform(coord);
+        if let Some(entity) = backgrounds.0.get(&(coord.x, coord.z)) {
+            if let Ok(mut current_transform) = q_transform.get_mut(*entity) {
+                if *current_transform != transform {
+                    *current_transform = transform;
+                }
+            }
+            continue;
+        }
+        let entity = commands
+            .spawn((
+                Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(0.5)))),
+                MeshMaterial3d(materials.add(StandardMaterial {
+                    base_color_texture: Some(current.background.clone()),
+                    unlit: true,
+                    ..default()
+                })),
+                transform,
+                BackgroundImage,
+                map::MapComponent,
+                NotShadowCaster,
+            ))
+            .id();
+        backgrounds.0.insert((coord.x, coord.z), entity);
+    }
+}
+
+fn despawn_all(mut commands: Commands, mut backgrounds: ResMut<Backgrounds>) {
+    for (_, entity) in backgrounds.0.drain() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
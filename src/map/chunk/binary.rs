@@ -0,0 +1,275 @@
+use std::{collections::HashSet, io, path::Path, sync::OnceLock};
+
+use bevy::asset::{io::Reader, AssetLoader, LoadContext};
+use serde::{Deserialize, Serialize};
+
+use crate::map;
+
+use super::{
+    Background, CellProperties, Chunk, ChunkCoordinate, Exit, Npc, Object, ResourceNode, SIZE_X,
+    SIZE_Z,
+};
+
+/// # First bytes of every binary chunk file
+const MAGIC: &[u8; 4] = b"BFCK";
+/// # Bumped whenever the layout after the header changes
+const VERSION: u16 = 4;
+/// # Extension of the binary chunk files, the RON ones keep `.ron`
+pub const EXTENSION: &str = "chunk";
+
+/// Format the chunk files can be converted to
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ron,
+    Binary,
+}
+
+/// Everything after the header, the tiles are run-length encoded row after row
+#[derive(Serialize, Deserialize)]
+struct FileStructure {
+    size_x: u16,
+    size_z: u16,
+    runs: Vec<(map::Tile, u16)>,
+    exits: Vec<Exit>,
+    cells: Vec<CellProperties>,
+    background: Background,
+    objects: Vec<Object>,
+    npcs: Vec<Npc>,
+    nodes: Vec<ResourceNode>,
+}
+
+/// Path of the binary chunk data, relative to the assets folder
+pub fn path(coord: ChunkCoordinate) -> String {
+    format!("map/{},{}_TileData.{}", coord.x, coord.z, EXTENSION)
+}
+
+/// Whether the chunk was converted to the binary format
+/// The map folder is only read once, the files don't come and go while playing
+pub fn exists(coord: ChunkCoordinate) -> bool {
+    static CONVERTED: OnceLock<HashSet<(i32, i32)>> = OnceLock::new();
+    CONVERTED
+        .get_or_init(|| {
+            let suffix = format!("_TileData.{}", EXTENSION);
+            let Ok(entries) = std::fs::read_dir(Path::new("assets").join("map")) else {
+                return HashSet::new();
+            };
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter_map(|name| {
+                    let (x, z) = name.strip_suffix(&suffix)?.split_once(',')?;
+                    Some((x.parse().ok()?, z.parse().ok()?))
+                })
+                .collect()
+        })
+        .contains(&(coord.x, coord.z))
+}
+
+pub fn encode(chunk: &Chunk) -> io::Result<Vec<u8>> {
+    let mut runs: Vec<(map::Tile, u16)> = Vec::new();
+    for tile in chunk.tiles.iter().flatten() {
+        match runs.last_mut() {
+            Some((previous, count)) if previous == tile && *count < u16::MAX => *count += 1,
+            _ => runs.push((*tile, 1)),
+        }
+    }
+    let file = FileStructure {
+        size_x: SIZE_X as u16,
+        size_z: SIZE_Z as u16,
+        runs,
+        exits: chunk.exits.clone(),
+        cells: chunk.cells.clone(),
+        background: chunk.background,
+        objects: chunk.objects.clone(),
+        npcs: chunk.npcs.clone(),
+        nodes: chunk.nodes.clone(),
+    };
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, &file).map_err(io::Error::other)?;
+    Ok(bytes)
+}
+
+pub fn decode(bytes: &[u8]) -> io::Result<Chunk> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let Some((magic, rest)) = bytes.split_first_chunk::<4>() else {
+        return Err(invalid("file too short for a chunk header".to_string()));
+    };
+    if magic != MAGIC {
+        return Err(invalid("not a binary chunk file".to_string()));
+    }
+    let Some((version, body)) = rest.split_first_chunk::<2>() else {
+        return Err(invalid("file too short for a chunk header".to_string()));
+    };
+    let version = u16::from_le_bytes(*version);
+    if version != VERSION {
+        return Err(invalid(format!(
+            "unsupported chunk version {}, expected {}",
+            version, VERSION
+        )));
+    }
+    let file = bincode::deserialize::<FileStructure>(body).map_err(io::Error::other)?;
+    if file.size_x as usize != SIZE_X || file.size_z as usize != SIZE_Z {
+        return Err(invalid(format!(
+            "chunk is {}x{}, expected {}x{}",
+            file.size_x, file.size_z, SIZE_X, SIZE_Z
+        )));
+    }
+
+    let tiles = file
+        .runs
+        .iter()
+        .flat_map(|(tile, count)| std::iter::repeat(*tile).take(*count as usize))
+        .collect::<Vec<_>>();
+    if tiles.len() != SIZE_X * SIZE_Z {
+        return Err(invalid(format!(
+            "{} tiles, expected {}",
+            tiles.len(),
+            SIZE_X * SIZE_Z
+        )));
+    }
+    let mut chunk = Chunk {
+        tiles: [[map::Tile::default(); SIZE_X]; SIZE_Z],
+        exits: file.exits,
+        cells: file.cells,
+        background: file.background,
+        objects: file.objects,
+        npcs: file.npcs,
+        nodes: file.nodes,
+    };
+    for (row, tiles) in chunk.tiles.iter_mut().zip(tiles.chunks_exact(SIZE_X)) {
+        row.copy_from_slice(tiles);
+    }
+    Ok(chunk)
+}
+
+#[derive(Default)]
+pub struct Loader;
+
+impl AssetLoader for Loader {
+    type Asset = Chunk;
+    type Settings = ();
+    type Error = io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        decode(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[EXTENSION]
+    }
+}
+
+/// Rewrite every chunk file of `assets/map` into `format`, keeping the originals
+/// Return the process exit code (non zero when a file couldn't be converted)
+pub fn convert_all(format: Format) -> i32 {
+    let (from, to) = match format {
+        Format::Ron => (EXTENSION, "ron"),
+        Format::Binary => ("ron", EXTENSION),
+    };
+    let folder = Path::new("assets").join("map");
+    let entries = match std::fs::read_dir(&folder) {
+        Ok(entries) => entries,
+        Err(error) => {
+            eprintln!("{}: {}", folder.display(), error);
+            return 1;
+        }
+    };
+    let (mut converted, mut failed) = (0, 0);
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        let is_chunk = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| stem.ends_with("_TileData"));
+        if !is_chunk || path.extension().and_then(|ext| ext.to_str()) != Some(from) {
+            continue;
+        }
+        match convert(&path, &path.with_extension(to), format) {
+            Ok(()) => converted += 1,
+            Err(error) => {
+                eprintln!("{}: {}", path.display(), error);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} chunk files converted, {} failed", converted, failed);
+    if failed == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+fn convert(source: &Path, destination: &Path, format: Format) -> io::Result<()> {
+    let bytes = std::fs::read(source)?;
+    let converted = match format {
+        Format::Binary => {
+            let chunk = ron::de::from_bytes::<Chunk>(&bytes)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            encode(&chunk)?
+        }
+        Format::Ron => ron::ser::to_string(&decode(&bytes)?)
+            .map_err(io::Error::other)?
+            .into_bytes(),
+    };
+    std::fs::write(destination, converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(chunk: &Chunk) {
+        let bytes = encode(chunk).unwrap();
+        assert_eq!(&decode(&bytes).unwrap(), chunk);
+    }
+
+    /// Files the RON loader rejects are left to `--validate-maps`
+    #[test]
+    fn round_trip_every_chunk_file() {
+        let mut checked = 0;
+        for entry in std::fs::read_dir(Path::new("assets").join("map")).unwrap() {
+            let path = entry.unwrap().path();
+            if !path.to_string_lossy().ends_with("_TileData.ron") {
+                continue;
+            }
+            let Ok(chunk) = ron::de::from_bytes::<Chunk>(&std::fs::read(&path).unwrap()) else {
+                continue;
+            };
+            round_trip(&chunk);
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn round_trip_mixed_tiles() {
+        let mut chunk = ron::de::from_str::<Chunk>("([])").unwrap();
+        for (index, tile) in chunk.tiles.iter_mut().flatten().enumerate() {
+            *tile = match index % 7 {
+                0 | 1 => map::Tile::Block,
+                2 => map::Tile::Water,
+                3 => map::Tile::Empty,
+                _ => map::Tile::Ground,
+            };
+        }
+        round_trip(&chunk);
+    }
+
+    #[test]
+    fn reject_other_versions() {
+        let chunk = ron::de::from_str::<Chunk>("([])").unwrap();
+        let mut bytes = encode(&chunk).unwrap();
+        bytes[MAGIC.len()] = bytes[MAGIC.len()].wrapping_add(1);
+        assert!(decode(&bytes).is_err());
+        assert!(decode(b"BFC").is_err());
+        assert!(decode(b"RON!\x04\x00").is_err());
+    }
+}
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::map;
 
-use super::{Chunk, SIZE_X, SIZE_Z};
+use super::{Background, CellProperties, Chunk, Exit, Npc, Object, ResourceNode, SIZE_X, SIZE_Z};
 
 #[derive(Default)]
 pub struct Loader;
@@ -36,28 +36,87 @@ impl Serialize for Chunk {
         S: serde::Serializer,
     {
         #[derive(Serialize)]
-        struct FileStructure<'a>(Vec<Vec<&'a map::Tile>>);
+        struct FileStructure<'a>(
+            Vec<Vec<&'a map::Tile>>,
+            &'a Vec<Exit>,
+            &'a Vec<CellProperties>,
+            &'a Background,
+            &'a Vec<Object>,
+            &'a Vec<Npc>,
+            &'a Vec<ResourceNode>,
+        );
 
-        let tiles = FileStructure(self.0.iter().map(|row| row.iter().collect()).collect());
+        let tiles = FileStructure(
+            self.tiles.iter().map(|row| row.iter().collect()).collect(),
+            &self.exits,
+            &self.cells,
+            &self.background,
+            &self.objects,
+            &self.npcs,
+            &self.nodes,
+        );
         tiles.serialize(serializer)
     }
 }
 
+/// Chunk file as written on disk, before the tiles are fit into the fixed size grid
+/// Older files stop early, every part after the tiles is optional
+#[derive(Deserialize)]
+pub struct RawChunk(
+    pub Vec<Vec<map::Tile>>,
+    #[serde(default)] pub Vec<Exit>,
+    #[serde(default)] pub Vec<CellProperties>,
+    #[serde(default)] pub Background,
+    #[serde(default)] pub Vec<Object>,
+    #[serde(default)] pub Vec<Npc>,
+    #[serde(default)] pub Vec<ResourceNode>,
+);
+
+/// Missing tiles are left to the default one, and overflowing ones are dropped
+impl From<RawChunk> for Chunk {
+    fn from(data: RawChunk) -> Self {
+        let mut chunk = Chunk {
+            tiles: [[map::Tile::default(); SIZE_X]; SIZE_Z],
+            exits: data.1,
+            cells: data.2,
+            background: data.3,
+            objects: data.4,
+            npcs: data.5,
+            nodes: data.6,
+        };
+        for (z, row) in data.0.iter().take(SIZE_Z).enumerate() {
+            for (x, tile) in row.iter().take(SIZE_X).enumerate() {
+                chunk.tiles[z][x] = *tile;
+            }
+        }
+        chunk
+    }
+}
+
 impl<'de> Deserialize<'de> for Chunk {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        #[derive(Deserialize)]
-        struct FileStructure(Vec<Vec<map::Tile>>);
-
-        let mut chunk = Chunk([[map::Tile::default(); SIZE_X]; SIZE_Z]);
-        let data = FileStructure::deserialize(deserializer)?;
-        for (z, row) in data.0.iter().enumerate() {
-            for (x, tile) in row.iter().enumerate() {
-                chunk.0[z][x] = *tile;
-            }
-        }
-        Ok(chunk)
+        RawChunk::deserialize(deserializer).map(Chunk::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The guide start the first quest, it must be reachable from the spawn chunk
+    #[test]
+    fn guide_stands_on_the_spawn_chunk() {
+        let text = std::fs::read("assets/map/0,0_TileData.ron").unwrap();
+        let chunk = ron::de::from_bytes::<Chunk>(&text).unwrap();
+        let guide = chunk.npcs.iter().find(|npc| npc.name == "Guide").unwrap();
+        assert!(chunk.is_walkable(guide.cell(), false));
+        assert!(std::path::Path::new("assets")
+            .join(&guide.dialogue)
+            .exists());
+        let written = ron::ser::to_string(&chunk).unwrap();
+        assert_eq!(ron::de::from_str::<Chunk>(&written).unwrap(), chunk);
     }
 }
@@ -2,14 +2,16 @@ use std::ops::{Add, Div, Mul, Sub};
 
 use bevy::{
     asset::{Asset, AssetServer, Handle},
-    math::Vec3,
-    prelude::{Res, ResMut},
+    math::{Vec2, Vec3},
+    prelude::{default, Res, ResMut, Transform},
     reflect::Reflect,
 };
+use serde::{Deserialize, Serialize};
 
 use crate::map;
 
 use super::CurrentChunk;
+pub mod binary;
 pub mod loader;
 
 pub const SIZE_X: usize = 14;
@@ -27,8 +29,8 @@ impl ChunkCoordinate {
     }
 
     pub fn from_tile(tile: map::TileCoordinate<map::tile::AbsoluteSpace>) -> Self {
-        let x = (tile.x as f32 / SIZE_X as f32).floor() as i32;
-        let z = (tile.z as f32 / SIZE_Z as f32).floor() as i32;
+        let x = tile.x.div_euclid(SIZE_X as i32);
+        let z = tile.z.div_euclid(SIZE_Z as i32);
         Self::new(x, z)
     }
 
@@ -102,33 +104,332 @@ impl Div<i32> for ChunkCoordinate {
     }
 }
 
-#[derive(Asset, Reflect, Debug, Clone, Copy)]
-pub struct Chunk(pub [[map::Tile; SIZE_X]; SIZE_Z]);
+/// Border of a chunk, named like the world map neighbours
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Edge {
+    /// Toward `z + 1`
+    Top,
+    /// Toward `z - 1`
+    Bottom,
+    /// Toward `x - 1`
+    Left,
+    /// Toward `x + 1`
+    Right,
+}
+
+impl Edge {
+    pub fn opposite(self) -> Self {
+        match self {
+            Edge::Top => Edge::Bottom,
+            Edge::Bottom => Edge::Top,
+            Edge::Left => Edge::Right,
+            Edge::Right => Edge::Left,
+        }
+    }
+
+    /// Edges a local cell lies on, two for the corners
+    pub fn of(tile: map::TileCoordinate<map::tile::LocalSpace>) -> impl Iterator<Item = Edge> {
+        [
+            (tile.z == SIZE_Z as i32 - 1, Edge::Top),
+            (tile.z == 0, Edge::Bottom),
+            (tile.x == 0, Edge::Left),
+            (tile.x == SIZE_X as i32 - 1, Edge::Right),
+        ]
+        .into_iter()
+        .filter_map(|(on_edge, edge)| on_edge.then_some(edge))
+    }
+
+    /// Every local cell along this edge
+    pub fn cells(self) -> impl Iterator<Item = map::TileCoordinate<map::tile::LocalSpace>> {
+        let (count, cell): (usize, fn(i32) -> (i32, i32)) = match self {
+            Edge::Top => (SIZE_X, |i| (i, SIZE_Z as i32 - 1)),
+            Edge::Bottom => (SIZE_X, |i| (i, 0)),
+            Edge::Left => (SIZE_Z, |i| (0, i)),
+            Edge::Right => (SIZE_Z, |i| (SIZE_X as i32 - 1, i)),
+        };
+        (0..count as i32).map(move |i| {
+            let (x, z) = cell(i);
+            map::TileCoordinate::new(x, z)
+        })
+    }
+
+    /// Step (in tiles, and in chunks) made when crossing this edge
+    pub fn direction(self) -> (i32, i32) {
+        match self {
+            Edge::Top => (0, 1),
+            Edge::Bottom => (0, -1),
+            Edge::Left => (-1, 0),
+            Edge::Right => (1, 0),
+        }
+    }
+}
+
+/// Cell (in local space) from which the player can leave the chunk through `edge`
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exit {
+    pub edge: Edge,
+    pub x: i32,
+    pub z: i32,
+}
+
+impl Exit {
+    pub fn cell(&self) -> map::TileCoordinate<map::tile::LocalSpace> {
+        map::TileCoordinate::new(self.x, self.z)
+    }
+}
+
+/// Properties of a single cell (in local space), cells not listed use the default ones
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CellProperties {
+    pub x: i32,
+    pub z: i32,
+    #[serde(default)]
+    pub properties: map::tile::Properties,
+}
+
+fn default_background_scale() -> Vec2 {
+    Vec2::ONE
+}
+
+/// Where the background image sits under the tiles
+/// By default it cover the chunk exactly, a negative scale flip the image
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Background {
+    /// World units, from the chunk center
+    #[serde(default)]
+    pub offset: Vec2,
+    /// Relative to the chunk size
+    #[serde(default = "default_background_scale")]
+    pub scale: Vec2,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            scale: default_background_scale(),
+        }
+    }
+}
+
+impl Background {
+    pub fn transform(&self, coord: ChunkCoordinate) -> Transform {
+        let sizes = ChunkCoordinate::world_sizes();
+        Transform {
+            // Slightly under the tiles, so the grid is drawn on top
+            translation: coord.world_center() + Vec3::new(self.offset.x, -0.01, self.offset.y),
+            scale: Vec3::new(sizes.x * self.scale.x, 1.0, sizes.z * self.scale.y),
+            ..default()
+        }
+    }
+}
+
+/// What an interactive object does when the player use it
+#[derive(Reflect, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ObjectKind {
+    /// Block its cell while closed
+    Door {
+        #[serde(default)]
+        open: bool,
+    },
+    /// Send the player to a cell (in absolute space), possibly in another chunk
+    Teleporter { x: i32, z: i32 },
+    /// Toggle the objects on the given cells (in local space) of the same chunk
+    Lever {
+        #[serde(default)]
+        targets: Vec<(i32, i32)>,
+    },
+}
+
+/// Interactive object anchored to a cell (in local space)
+#[derive(Reflect, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Object {
+    pub x: i32,
+    pub z: i32,
+    pub kind: ObjectKind,
+}
+
+impl Object {
+    pub fn cell(&self) -> map::TileCoordinate<map::tile::LocalSpace> {
+        map::TileCoordinate::new(self.x, self.z)
+    }
+}
+
+/// Non-player character standing on a cell (in local space)
+#[derive(Reflect, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Npc {
+    pub x: i32,
+    pub z: i32,
+    pub name: String,
+    /// Image relative to the assets folder, a plain figure is drawn without one
+    #[serde(default)]
+    pub sprite: Option<String>,
+    /// Dialogue asset relative to the assets folder
+    pub dialogue: String,
+}
+
+impl Npc {
+    pub fn cell(&self) -> map::TileCoordinate<map::tile::LocalSpace> {
+        map::TileCoordinate::new(self.x, self.z)
+    }
+}
+
+/// What grows on a resource node, and the profession gathering it trains
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Tree,
+    Wheat,
+    Ore,
+}
+
+/// Resource node standing on a cell (in local space), harvested from an adjacent cell
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceNode {
+    pub x: i32,
+    pub z: i32,
+    pub kind: NodeKind,
+}
+
+impl ResourceNode {
+    pub fn cell(&self) -> map::TileCoordinate<map::tile::LocalSpace> {
+        map::TileCoordinate::new(self.x, self.z)
+    }
+}
+
+#[derive(Asset, Reflect, Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub tiles: [[map::Tile; SIZE_X]; SIZE_Z],
+    pub exits: Vec<Exit>,
+    pub cells: Vec<CellProperties>,
+    pub background: Background,
+    pub objects: Vec<Object>,
+    pub npcs: Vec<Npc>,
+    pub nodes: Vec<ResourceNode>,
+}
 
 impl Chunk {
+    pub fn get_properties(
+        &self,
+        tile: map::TileCoordinate<map::tile::LocalSpace>,
+    ) -> map::tile::Properties {
+        self.cells
+            .iter()
+            .find(|cell| cell.x == tile.x && cell.z == tile.z)
+            .map(|cell| cell.properties)
+            .unwrap_or_default()
+    }
+
+    /// Whether a character can stand on `tile`, `in_fight` also exclude the cells blocked during fights
+    pub fn is_walkable(
+        &self,
+        tile: map::TileCoordinate<map::tile::LocalSpace>,
+        in_fight: bool,
+    ) -> bool {
+        self.get_tile(tile)
+            .is_some_and(|tile_type| tile_type.is_walkable())
+            && !(in_fight && self.get_properties(tile).blocked_in_fight)
+    }
+
+    pub fn blocks_line_of_sight(&self, tile: map::TileCoordinate<map::tile::LocalSpace>) -> bool {
+        self.get_tile(tile)
+            .is_some_and(|tile_type| tile_type.blocks_line_of_sight())
+    }
+
+    /// Exits leaving through `edge`
+    /// When the chunk data doesn't define any, every ground cell along the edge is one
+    pub fn exits_on(&self, edge: Edge) -> Vec<Exit> {
+        let defined = self
+            .exits
+            .iter()
+            .filter(|exit| exit.edge == edge)
+            .copied()
+            .collect::<Vec<_>>();
+        if !defined.is_empty() {
+            return defined;
+        }
+        edge.cells()
+            .filter(|cell| self.is_walkable(*cell, false))
+            .map(|cell| Exit {
+                edge,
+                x: cell.x,
+                z: cell.z,
+            })
+            .collect()
+    }
+
+    pub fn exit_at(&self, tile: map::TileCoordinate<map::tile::LocalSpace>) -> Option<Exit> {
+        if let Some(exit) = self.exits.iter().find(|exit| exit.cell() == tile) {
+            return Some(*exit);
+        }
+        Edge::of(tile)
+            .flat_map(|edge| self.exits_on(edge))
+            .find(|exit| exit.cell() == tile)
+    }
+
+    pub fn object_at(&self, tile: map::TileCoordinate<map::tile::LocalSpace>) -> Option<&Object> {
+        self.objects.iter().find(|object| object.cell() == tile)
+    }
+
+    pub fn node_at(
+        &self,
+        tile: map::TileCoordinate<map::tile::LocalSpace>,
+    ) -> Option<&ResourceNode> {
+        self.nodes.iter().find(|node| node.cell() == tile)
+    }
+
     pub fn get_tile(&self, tile: map::TileCoordinate<map::tile::LocalSpace>) -> Option<&map::Tile> {
-        let tile = self.0.get(tile.z as usize)?.get(tile.x as usize)?;
+        let tile = self.tiles.get(tile.z as usize)?.get(tile.x as usize)?;
         Some(tile)
     }
     pub fn get_tile_mut(
         &mut self,
         tile: map::TileCoordinate<map::tile::LocalSpace>,
     ) -> Option<&mut map::Tile> {
-        let tile = self.0.get_mut(tile.z as usize)?.get_mut(tile.x as usize)?;
+        let tile = self
+            .tiles
+            .get_mut(tile.z as usize)?
+            .get_mut(tile.x as usize)?;
         Some(tile)
     }
 }
 
-pub fn load(coord: ChunkCoordinate, asset_server: &Res<AssetServer>) -> Option<CurrentChunk> {
-    let background_path = format!("map/{},{}.png", coord.x, coord.z);
-    let background = asset_server.load(background_path);
-    let grid_data_path = format!("map/{},{}_TileData.ron", coord.x, coord.z);
-    let grid = asset_server.load(grid_data_path);
+/// Path of the chunk tile data, relative to the assets folder
+/// The binary file is loaded when the chunk was converted, the RON one stays the editable source
+pub fn path(coord: ChunkCoordinate) -> String {
+    if binary::exists(coord) {
+        binary::path(coord)
+    } else {
+        ron_path(coord)
+    }
+}
+
+fn ron_path(coord: ChunkCoordinate) -> String {
+    format!("map/{},{}_TileData.ron", coord.x, coord.z)
+}
+
+/// Start loading a chunk and its background, `None` if the world map doesn't list it
+pub fn load(
+    coord: ChunkCoordinate,
+    asset_server: &Res<AssetServer>,
+    world_map: &map::WorldMap,
+) -> Option<CurrentChunk> {
+    let info = world_map.get(coord)?;
+    let background = info
+        .background
+        .as_ref()
+        .map(|background| asset_server.load(background))
+        .unwrap_or_default();
+    let grid = asset_server.load(path(coord));
     Some(CurrentChunk { grid, background })
 }
 
-pub fn save(chunk: &Chunk, coord: ChunkCoordinate) {
-    let path = format!("map/{},{}_TileData.ron", coord.x, coord.z);
-    let file = std::fs::File::create(path).unwrap();
-    ron::ser::to_writer(file, chunk).unwrap();
+/// Write the chunk back into the assets folder, along with its binary copy when it has one
+pub fn save(chunk: &Chunk, coord: ChunkCoordinate) -> std::io::Result<()> {
+    let folder = std::path::Path::new("assets");
+    let text = ron::ser::to_string(chunk).map_err(std::io::Error::other)?;
+    std::fs::write(folder.join(ron_path(coord)), text)?;
+    if binary::exists(coord) {
+        std::fs::write(folder.join(binary::path(coord)), binary::encode(chunk)?)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,905 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    window::PrimaryWindow,
+};
+
+use crate::{
+    map::{
+        self,
+        chunk::{NodeKind, Object, ObjectKind, ResourceNode},
+        streaming::Streamer,
+        Chunk, ChunkCoordinate, Tile, TileCoordinate,
+    },
+    ui, GameMode,
+};
+
+/// # Biggest brush size
+const MAX_BRUSH_SIZE: u32 = 5;
+/// # Amount of edits kept in the undo history
+const HISTORY_SIZE: usize = 100;
+/// # Background scale change per mouse wheel line
+const BACKGROUND_SCALE_STEP: f32 = 0.01;
+
+pub fn init(app: &mut App) {
+    app.init_resource::<Editor>()
+        .init_resource::<EditorTiles>()
+        .add_systems(
+            OnEnter(GameMode::Exploration),
+            spawn_palette.run_if(is_enabled),
+        )
+        .add_systems(
+            Update,
+            (
+                shortcuts.run_if(not(ui::chat::is_typing)),
+                paint,
+                place_objects,
+                align_background,
+                sync_tiles,
+                draw_preview,
+                update_palette,
+            )
+                .chain()
+                .run_if(is_enabled)
+                .run_if(in_state(GameMode::Exploration)),
+        )
+        .add_systems(OnExit(GameMode::Exploration), cleanup.run_if(is_enabled))
+        .add_observer(on_command);
+}
+
+fn is_enabled(args: Option<Res<crate::ProcessArgs>>) -> bool {
+    args.is_some_and(|args| args.map_editor)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    /// Paint every cell under the brush while the button is held
+    Brush,
+    /// Fill the rectangle between where the button was pressed and released
+    Rectangle,
+    /// Replace the area of identical tiles connected to the clicked cell, within its chunk
+    Fill,
+    /// Drag the background of the hovered chunk, and scale it with the mouse wheel
+    /// (Shift only scale it horizontally, Alt only vertically)
+    Background,
+    /// Add an object on the clicked cell, or remove the one already there
+    Object(ObjectType),
+    /// Click a lever then an object to link (or unlink) them,
+    /// or a teleporter then the cell it must lead to
+    Link,
+    /// Add a resource node on the clicked cell, or remove the one already there
+    Node(NodeKind),
+}
+
+/// Objects placeable with `Tool::Object`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectType {
+    Door,
+    Lever,
+    Teleporter,
+}
+
+impl ObjectType {
+    fn object(self, cell: TileCoordinate) -> Object {
+        let local = cell.to_local();
+        let kind = match self {
+            ObjectType::Door => ObjectKind::Door { open: false },
+            ObjectType::Lever => ObjectKind::Lever {
+                targets: Vec::new(),
+            },
+            // Lead to itself until linked
+            ObjectType::Teleporter => ObjectKind::Teleporter {
+                x: cell.x,
+                z: cell.z,
+            },
+        };
+        Object {
+            x: local.x,
+            z: local.z,
+            kind,
+        }
+    }
+}
+
+/// Everything the editor can be asked to do, from the palette or a shortcut
+#[derive(Debug, Event, Clone, Copy)]
+pub enum EditorCommand {
+    SelectTool(Tool),
+    SelectTile(Tile),
+    /// Grow (positive) or shrink (negative) the brush
+    ResizeBrush(i32),
+    Undo,
+    Redo,
+    Save,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    cell: TileCoordinate,
+    before: Tile,
+    after: Tile,
+}
+
+/// Every change made by a single stroke, undone and redone as one
+#[derive(Debug, Default)]
+struct Edit(Vec<Change>);
+
+#[derive(Resource)]
+struct Editor {
+    tool: Tool,
+    tile: Tile,
+    brush_size: u32,
+    /// Cell the current stroke started on, and what it changed so far
+    stroke: Option<(TileCoordinate, Edit)>,
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// Chunks edited since they were last saved, their handles keep the streaming
+    /// from releasing them (and the edits with them) before they are
+    dirty: HashMap<(i32, i32), Handle<Chunk>>,
+    /// Cells whose tile entity must be respawned
+    changed: Vec<TileCoordinate>,
+    /// Chunk whose background is being dragged, and the last point under the cursor
+    drag: Option<(ChunkCoordinate, Vec3)>,
+    /// Lever or teleporter picked by `Tool::Link`, waiting for what it must be linked to
+    link: Option<TileCoordinate>,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self {
+            tool: Tool::Brush,
+            tile: Tile::Ground,
+            brush_size: 1,
+            stroke: None,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            dirty: HashMap::new(),
+            changed: Vec::new(),
+            drag: None,
+            link: None,
+        }
+    }
+}
+
+impl Editor {
+    /// Set a single cell, return the change if the chunk is loaded and the tile differs
+    fn set(
+        &mut self,
+        cell: TileCoordinate,
+        tile: Tile,
+        asset_server: &AssetServer,
+        chunk_assets: &mut Assets<Chunk>,
+    ) -> Option<Change> {
+        let chunk = cell.to_chunk();
+        let handle = asset_server.get_handle::<Chunk>(map::chunk::path(chunk))?;
+        let current = chunk_assets
+            .get_mut(handle.id())?
+            .get_tile_mut(cell.to_local())?;
+        if *current == tile {
+            return None;
+        }
+        let change = Change {
+            cell,
+            before: *current,
+            after: tile,
+        };
+        *current = tile;
+        self.dirty.insert((chunk.x, chunk.z), handle);
+        self.changed.push(cell);
+        Some(change)
+    }
+
+    fn paint(
+        &mut self,
+        cells: impl IntoIterator<Item = TileCoordinate>,
+        asset_server: &AssetServer,
+        chunk_assets: &mut Assets<Chunk>,
+    ) -> Vec<Change> {
+        let tile = self.tile;
+        cells
+            .into_iter()
+            .filter_map(|cell| self.set(cell, tile, asset_server, chunk_assets))
+            .collect()
+    }
+
+    fn push_edit(&mut self, edit: Edit) {
+        if edit.0.is_empty() {
+            return;
+        }
+        self.undo.push(edit);
+        if self.undo.len() > HISTORY_SIZE {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+}
+
+/// Tile entities spawned by the editor, per chunk
+#[derive(Resource, Default)]
+struct EditorTiles(HashMap<(i32, i32), Vec<Entity>>);
+
+#[derive(Component)]
+struct Palette;
+
+#[derive(Component)]
+struct DirtyIndicator;
+
+#[derive(Component)]
+struct ToolLabel;
+
+/// Cells covered by a brush of `size` centered on `center`
+fn brush(center: TileCoordinate, size: u32) -> Vec<TileCoordinate> {
+    let radius = size as i32 - 1;
+    let diagonal = center.to_diagonal();
+    let mut cells = Vec::new();
+    for u in -radius..=radius {
+        for v in -radius..=radius {
+            let cell = map::tile::DiagonalCoordinate::new(diagonal.u + u, diagonal.v + v).to_tile();
+            if cell.distance(center) < size {
+                cells.push(cell);
+            }
+        }
+    }
+    cells
+}
+
+fn rectangle(from: TileCoordinate, to: TileCoordinate) -> Vec<TileCoordinate> {
+    let mut cells = Vec::new();
+    for z in from.z.min(to.z)..=from.z.max(to.z) {
+        for x in from.x.min(to.x)..=from.x.max(to.x) {
+            cells.push(TileCoordinate::new(x, z));
+        }
+    }
+    cells
+}
+
+/// Connected cells (by their edges) with the same tile as `start`, without leaving its chunk
+fn flood(start: TileCoordinate, chunk: &Chunk) -> Vec<TileCoordinate> {
+    let Some(target) = chunk.get_tile(start.to_local()).copied() else {
+        return Vec::new();
+    };
+    let start_chunk = start.to_chunk();
+    let mut visited = HashSet::from([(start.x, start.z)]);
+    let mut open = VecDeque::from([start]);
+    let mut cells = Vec::new();
+    while let Some(cell) = open.pop_front() {
+        cells.push(cell);
+        for neighbour in cell.neighbours() {
+            if neighbour.to_chunk() == start_chunk
+                && chunk.get_tile(neighbour.to_local()) == Some(&target)
+                && visited.insert((neighbour.x, neighbour.z))
+            {
+                open.push_back(neighbour);
+            }
+        }
+    }
+    cells
+}
+
+/// Cell under the cursor, on the ground plane
+fn hovered_cell(
+    q_window: &Query<&Window, With<PrimaryWindow>>,
+    q_camera: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<TileCoordinate> {
+    hovered_point(q_window, q_camera).map(TileCoordinate::from_world)
+}
+
+/// Point under the cursor, on the ground plane
+fn hovered_point(
+    q_window: &Query<&Window, With<PrimaryWindow>>,
+    q_camera: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<Vec3> {
+    let (camera, camera_transform) = q_camera.get_single().ok()?;
+    let ray = camera
+        .viewport_to_world(
+            camera_transform,
+            q_window.get_single().ok()?.cursor_position()?,
+        )
+        .ok()?;
+    let distance = ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))?;
+    Some(ray.get_point(distance))
+}
+
+fn shortcuts(mut commands: Commands, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    let control = keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let command = if control && keyboard_input.just_pressed(KeyCode::KeyZ) {
+        if shift {
+            EditorCommand::Redo
+        } else {
+            EditorCommand::Undo
+        }
+    } else if control && keyboard_input.just_pressed(KeyCode::KeyY) {
+        EditorCommand::Redo
+    } else if control && keyboard_input.just_pressed(KeyCode::KeyS) {
+        EditorCommand::Save
+    } else if keyboard_input.just_pressed(KeyCode::BracketRight) {
+        EditorCommand::ResizeBrush(1)
+    } else if keyboard_input.just_pressed(KeyCode::BracketLeft) {
+        EditorCommand::ResizeBrush(-1)
+    } else if keyboard_input.just_pressed(KeyCode::KeyB) {
+        EditorCommand::SelectTool(Tool::Brush)
+    } else if keyboard_input.just_pressed(KeyCode::KeyR) {
+        EditorCommand::SelectTool(Tool::Rectangle)
+    } else if keyboard_input.just_pressed(KeyCode::KeyF) {
+        EditorCommand::SelectTool(Tool::Fill)
+    } else if keyboard_input.just_pressed(KeyCode::KeyG) {
+        EditorCommand::SelectTool(Tool::Background)
+    } else {
+        return;
+    };
+    commands.trigger(command);
+}
+
+fn on_command(
+    trigger: Trigger<EditorCommand>,
+    mut editor: ResMut<Editor>,
+    asset_server: Res<AssetServer>,
+    mut chunk_assets: ResMut<Assets<Chunk>>,
+) {
+    match *trigger.event() {
+        EditorCommand::SelectTool(tool) => {
+            editor.tool = tool;
+            editor.link = None;
+        }
+        EditorCommand::SelectTile(tile) => editor.tile = tile,
+        EditorCommand::ResizeBrush(delta) => {
+            editor.brush_size = editor
+                .brush_size
+                .saturating_add_signed(delta)
+                .clamp(1, MAX_BRUSH_SIZE);
+        }
+        EditorCommand::Undo => {
+            let Some(edit) = editor.undo.pop() else {
+                return;
+            };
+            for change in edit.0.iter().rev() {
+                editor.set(change.cell, change.before, &asset_server, &mut chunk_assets);
+            }
+            editor.redo.push(edit);
+        }
+        EditorCommand::Redo => {
+            let Some(edit) = editor.redo.pop() else {
+                return;
+            };
+            for change in edit.0.iter() {
+                editor.set(change.cell, change.after, &asset_server, &mut chunk_assets);
+            }
+            editor.undo.push(edit);
+        }
+        EditorCommand::Save => {
+            let dirty = editor.dirty.drain().collect::<Vec<_>>();
+            for ((x, z), handle) in dirty {
+                let coord = ChunkCoordinate::new(x, z);
+                let Some(chunk) = chunk_assets.get(handle.id()) else {
+                    warn!("Chunk {:?} was unloaded before being saved", coord);
+                    continue;
+                };
+                match map::chunk::save(chunk, coord) {
+                    Ok(()) => info!("Saved chunk {:?}", coord),
+                    Err(error) => {
+                        error!("Can't save chunk {:?}: {}", coord, error);
+                        editor.dirty.insert((x, z), handle);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn paint(
+    mut editor: ResMut<Editor>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    q_palette: Query<&Interaction, With<Palette>>,
+    asset_server: Res<AssetServer>,
+    mut chunk_assets: ResMut<Assets<Chunk>>,
+) {
+    let over_palette = q_palette
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    let Some(cell) = hovered_cell(&q_window, &q_camera) else {
+        return;
+    };
+    if mouse_button_input.just_pressed(MouseButton::Left) && !over_palette {
+        editor.stroke = Some((cell, Edit::default()));
+    }
+    let Some((anchor, mut edit)) = editor.stroke.take() else {
+        return;
+    };
+    let released = mouse_button_input.just_released(MouseButton::Left)
+        || !mouse_button_input.pressed(MouseButton::Left);
+    match editor.tool {
+        Tool::Brush => {
+            let size = editor.brush_size;
+            let changes = editor.paint(brush(cell, size), &asset_server, &mut chunk_assets);
+            edit.0.extend(changes);
+        }
+        Tool::Rectangle if released => {
+            let changes = editor.paint(rectangle(anchor, cell), &asset_server, &mut chunk_assets);
+            edit.0.extend(changes);
+        }
+        Tool::Fill if edit.0.is_empty() => {
+            let cells = asset_server
+                .get_handle::<Chunk>(map::chunk::path(anchor.to_chunk()))
+                .and_then(|handle| chunk_assets.get(handle.id()))
+                .map(|chunk| flood(anchor, chunk))
+                .unwrap_or_default();
+            let changes = editor.paint(cells, &asset_server, &mut chunk_assets);
+            edit.0.extend(changes);
+        }
+        _ => {}
+    }
+    if released {
+        editor.push_edit(edit);
+    } else {
+        editor.stroke = Some((anchor, edit));
+    }
+}
+
+/// Object and node edits aren't part of the undo history, clicking again remove what was added
+fn place_objects(
+    mut editor: ResMut<Editor>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    q_palette: Query<&Interaction, With<Palette>>,
+    asset_server: Res<AssetServer>,
+    mut chunk_assets: ResMut<Assets<Chunk>>,
+) {
+    let over_palette = q_palette
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if !mouse_button_input.just_pressed(MouseButton::Left) || over_palette {
+        return;
+    }
+    let Some(cell) = hovered_cell(&q_window, &q_camera) else {
+        return;
+    };
+    match editor.tool {
+        Tool::Object(object_type) => {
+            let chunk = cell.to_chunk();
+            let Some(handle) = asset_server.get_handle::<Chunk>(map::chunk::path(chunk)) else {
+                return;
+            };
+            let Some(data) = chunk_assets.get_mut(handle.id()) else {
+                return;
+            };
+            let local = cell.to_local();
+            if data.object_at(local).is_some() {
+                data.objects.retain(|object| object.cell() != local);
+                for object in data.objects.iter_mut() {
+                    if let ObjectKind::Lever { targets } = &mut object.kind {
+                        targets.retain(|(x, z)| (*x, *z) != (local.x, local.z));
+                    }
+                }
+            } else {
+                data.objects.push(object_type.object(cell));
+            }
+            editor.dirty.insert((chunk.x, chunk.z), handle);
+        }
+        Tool::Node(kind) => {
+            let chunk = cell.to_chunk();
+            let Some(handle) = asset_server.get_handle::<Chunk>(map::chunk::path(chunk)) else {
+                return;
+            };
+            let Some(data) = chunk_assets.get_mut(handle.id()) else {
+                return;
+            };
+            let local = cell.to_local();
+            if data.node_at(local).is_some() {
+                data.nodes.retain(|node| node.cell() != local);
+            } else {
+                data.nodes.push(ResourceNode {
+                    x: local.x,
+                    z: local.z,
+                    kind,
+                });
+            }
+            editor.dirty.insert((chunk.x, chunk.z), handle);
+        }
+        Tool::Link => {
+            let Some(source) = editor.link.take() else {
+                let is_linkable = asset_server
+                    .get_handle::<Chunk>(map::chunk::path(cell.to_chunk()))
+                    .and_then(|handle| chunk_assets.get(handle.id()))
+                    .and_then(|data| data.object_at(cell.to_local()))
+                    .is_some_and(|object| !matches!(object.kind, ObjectKind::Door { .. }));
+                if is_linkable {
+                    editor.link = Some(cell);
+                }
+                return;
+            };
+            let chunk = source.to_chunk();
+            let Some(handle) = asset_server.get_handle::<Chunk>(map::chunk::path(chunk)) else {
+                return;
+            };
+            let Some(data) = chunk_assets.get_mut(handle.id()) else {
+                return;
+            };
+            let target = cell.to_local();
+            let target_exists = cell.to_chunk() == chunk && data.object_at(target).is_some();
+            let Some(object) = data
+                .objects
+                .iter_mut()
+                .find(|object| object.cell() == source.to_local())
+            else {
+                return;
+            };
+            match &mut object.kind {
+                ObjectKind::Teleporter { x, z } => (*x, *z) = (cell.x, cell.z),
+                ObjectKind::Lever { targets } if target_exists && target != source.to_local() => {
+                    if targets.contains(&(target.x, target.z)) {
+                        targets.retain(|linked| *linked != (target.x, target.z));
+                    } else {
+                        targets.push((target.x, target.z));
+                    }
+                }
+                _ => {
+                    warn!("Levers can only be linked to objects of their own chunk");
+                    return;
+                }
+            }
+            editor.dirty.insert((chunk.x, chunk.z), handle);
+        }
+        _ => {}
+    }
+}
+
+fn align_background(
+    mut editor: ResMut<Editor>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    q_palette: Query<&Interaction, With<Palette>>,
+    asset_server: Res<AssetServer>,
+    mut chunk_assets: ResMut<Assets<Chunk>>,
+) {
+    let scroll = mouse_wheel
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 20.0,
+        })
+        .sum::<f32>();
+    if editor.tool != Tool::Background {
+        editor.drag = None;
+        return;
+    }
+    let Some(point) = hovered_point(&q_window, &q_camera) else {
+        return;
+    };
+    let over_palette = q_palette
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if mouse_button_input.just_pressed(MouseButton::Left) && !over_palette {
+        editor.drag = Some((ChunkCoordinate::from_world(point), point));
+    }
+    if !mouse_button_input.pressed(MouseButton::Left) {
+        editor.drag = None;
+    }
+    let (chunk, offset) = match editor.drag {
+        Some((chunk, last_point)) => {
+            editor.drag = Some((chunk, point));
+            (chunk, point - last_point)
+        }
+        None => (ChunkCoordinate::from_world(point), Vec3::ZERO),
+    };
+    if offset == Vec3::ZERO && scroll == 0.0 {
+        return;
+    }
+    let Some(handle) = asset_server.get_handle::<Chunk>(map::chunk::path(chunk)) else {
+        return;
+    };
+    let Some(data) = chunk_assets.get_mut(handle.id()) else {
+        return;
+    };
+    let scale = scroll * BACKGROUND_SCALE_STEP;
+    let (scale_x, scale_z) =
+        if keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+            (scale, 0.0)
+        } else if keyboard_input.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]) {
+            (0.0, scale)
+        } else {
+            (scale, scale)
+        };
+    data.background.offset += Vec2::new(offset.x, offset.z);
+    data.background.scale += Vec2::new(scale_x, scale_z);
+    editor.dirty.insert((chunk.x, chunk.z), handle);
+}
+
+/// Keep a tile entity for every cell of the loaded chunks, respawning the edited ones
+fn sync_tiles(
+    mut commands: Commands,
+    mut editor: ResMut<Editor>,
+    mut editor_tiles: ResMut<EditorTiles>,
+    streamer: Res<Streamer>,
+    chunk_assets: Res<Assets<Chunk>>,
+    map_handles: ResMut<map::Handles>,
+    q_transform: Query<&Transform, With<Tile>>,
+) {
+    let changed = std::mem::take(&mut editor.changed);
+    for cell in changed {
+        let chunk = cell.to_chunk();
+        let Some(entities) = editor_tiles.0.get_mut(&(chunk.x, chunk.z)) else {
+            continue;
+        };
+        entities.retain(|entity| {
+            let same_cell = q_transform
+                .get(*entity)
+                .is_ok_and(|transform| TileCoordinate::from_world(transform.translation) == cell);
+            if same_cell {
+                commands.entity(*entity).despawn_recursive();
+            }
+            !same_cell
+        });
+        let Some((_, current)) = streamer.loaded().find(|(coord, _)| *coord == chunk) else {
+            continue;
+        };
+        let Some(data) = chunk_assets.get(current.grid.id()) else {
+            continue;
+        };
+        let local = cell.to_local();
+        let tile = *data.get_tile(local).unwrap();
+        if let Some(entity) = map::tile::spawn(
+            &mut commands,
+            &map_handles,
+            tile,
+            data.get_properties(local),
+            cell.to_world(),
+        ) {
+            entities.push(entity);
+        }
+    }
+
+    // Chunks released by the streaming
+    editor_tiles.0.retain(|(x, z), entities| {
+        let loaded = streamer.is_loaded(ChunkCoordinate::new(*x, *z));
+        if !loaded {
+            for entity in entities.drain(..) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        loaded
+    });
+    // Chunks newly loaded
+    for (coord, current) in streamer.loaded() {
+        if editor_tiles.0.contains_key(&(coord.x, coord.z)) {
+            continue;
+        }
+        let Some(data) = chunk_assets.get(current.grid.id()) else {
+            continue;
+        };
+        let mut entities = Vec::new();
+        for z in 0..map::chunk::SIZE_Z {
+            for x in 0..map::chunk::SIZE_X {
+                let local = TileCoordinate::<map::tile::LocalSpace>::new(x as i32, z as i32);
+                entities.extend(map::tile::spawn(
+                    &mut commands,
+                    &map_handles,
+                    data.tiles[z][x],
+                    data.get_properties(local),
+                    local.to_absolute(coord).to_world(),
+                ));
+            }
+        }
+        editor_tiles.0.insert((coord.x, coord.z), entities);
+    }
+}
+
+fn draw_cell(gizmos: &mut Gizmos, cell: TileCoordinate, color: Color) {
+    gizmos.rect(
+        Isometry3d::new(
+            cell.to_world() + Vec3::new(0.0, 0.02, 0.0),
+            Quat::from_euler(
+                EulerRot::YXZ,
+                std::f32::consts::FRAC_PI_4,
+                -std::f32::consts::FRAC_PI_2,
+                0.0,
+            ),
+        ),
+        Vec2::new(map::tile::SIZE, map::tile::SIZE),
+        color,
+    );
+}
+
+fn draw_preview(
+    editor: Res<Editor>,
+    mut gizmos: Gizmos,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+) {
+    let Some(cell) = hovered_cell(&q_window, &q_camera) else {
+        return;
+    };
+    let color = Color::hsl(50.0, 1.0, 0.5);
+    let cells = match (editor.tool, &editor.stroke) {
+        (Tool::Rectangle, Some((anchor, _))) => rectangle(*anchor, cell),
+        (Tool::Brush, _) => brush(cell, editor.brush_size),
+        // The whole grid, to match the background against
+        (Tool::Background, _) => {
+            let chunk = cell.to_chunk();
+            (0..map::chunk::SIZE_Z as i32)
+                .flat_map(|z| (0..map::chunk::SIZE_X as i32).map(move |x| (x, z)))
+                .map(|(x, z)| TileCoordinate::<map::tile::LocalSpace>::new(x, z).to_absolute(chunk))
+                .collect()
+        }
+        _ => vec![cell],
+    };
+    for cell in cells {
+        draw_cell(&mut gizmos, cell, color);
+    }
+    if let (Tool::Link, Some(source)) = (editor.tool, editor.link) {
+        draw_cell(&mut gizmos, source, Color::hsl(280.0, 1.0, 0.6));
+        gizmos.line(
+            source.to_world() + Vec3::Y * 0.02,
+            cell.to_world() + Vec3::Y * 0.02,
+            Color::hsl(280.0, 1.0, 0.6),
+        );
+    }
+}
+
+fn palette_button(parent: &mut ChildBuilder, label: &str, command: EditorCommand) {
+    parent
+        .spawn((
+            Node {
+                padding: UiRect::all(Val::Px(4.0)),
+                ..default()
+            },
+            BackgroundColor(Color::hsl(246.0, 0.21, 0.4)),
+        ))
+        .with_child((
+            Text::new(label),
+            TextFont {
+                font_size: 12.0,
+                ..default()
+            },
+        ))
+        .observe(
+            move |mut trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+                trigger.propagate(false);
+                commands.trigger(command);
+            },
+        );
+}
+
+fn spawn_palette(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                padding: UiRect::all(Val::Px(6.0)),
+                ..default()
+            },
+            BackgroundColor(Color::hsl(246.0, 0.21, 0.29)),
+            Interaction::default(),
+            Palette,
+        ))
+        .with_children(|parent| {
+            parent.spawn((Text::new("Map editor"), ToolLabel));
+            let row = Node {
+                display: Display::Flex,
+                flex_direction: FlexDirection::Row,
+                column_gap: Val::Px(4.0),
+                ..default()
+            };
+            parent.spawn(row.clone()).with_children(|parent| {
+                palette_button(parent, "Brush", EditorCommand::SelectTool(Tool::Brush));
+                palette_button(
+                    parent,
+                    "Rectangle",
+                    EditorCommand::SelectTool(Tool::Rectangle),
+                );
+                palette_button(parent, "Fill", EditorCommand::SelectTool(Tool::Fill));
+                palette_button(
+                    parent,
+                    "Background",
+                    EditorCommand::SelectTool(Tool::Background),
+                );
+            });
+            parent.spawn(row.clone()).with_children(|parent| {
+                palette_button(parent, "Ground", EditorCommand::SelectTile(Tile::Ground));
+                palette_button(parent, "Block", EditorCommand::SelectTile(Tile::Block));
+                palette_button(parent, "Water", EditorCommand::SelectTile(Tile::Water));
+                palette_button(parent, "Empty", EditorCommand::SelectTile(Tile::Empty));
+            });
+            parent.spawn(row.clone()).with_children(|parent| {
+                palette_button(
+                    parent,
+                    "Door",
+                    EditorCommand::SelectTool(Tool::Object(ObjectType::Door)),
+                );
+                palette_button(
+                    parent,
+                    "Lever",
+                    EditorCommand::SelectTool(Tool::Object(ObjectType::Lever)),
+                );
+                palette_button(
+                    parent,
+                    "Teleporter",
+                    EditorCommand::SelectTool(Tool::Object(ObjectType::Teleporter)),
+                );
+                palette_button(parent, "Link", EditorCommand::SelectTool(Tool::Link));
+            });
+            parent.spawn(row.clone()).with_children(|parent| {
+                palette_button(
+                    parent,
+                    "Tree",
+                    EditorCommand::SelectTool(Tool::Node(NodeKind::Tree)),
+                );
+                palette_button(
+                    parent,
+                    "Wheat",
+                    EditorCommand::SelectTool(Tool::Node(NodeKind::Wheat)),
+                );
+                palette_button(
+                    parent,
+                    "Ore",
+                    EditorCommand::SelectTool(Tool::Node(NodeKind::Ore)),
+                );
+            });
+            parent.spawn(row.clone()).with_children(|parent| {
+                palette_button(parent, "-", EditorCommand::ResizeBrush(-1));
+                palette_button(parent, "+", EditorCommand::ResizeBrush(1));
+                palette_button(parent, "Undo", EditorCommand::Undo);
+                palette_button(parent, "Redo", EditorCommand::Redo);
+                palette_button(parent, "Save", EditorCommand::Save);
+            });
+            parent.spawn((Text::new(""), DirtyIndicator));
+        });
+}
+
+fn update_palette(
+    editor: Res<Editor>,
+    mut q_tool_label: Query<&mut Text, (With<ToolLabel>, Without<DirtyIndicator>)>,
+    mut q_dirty_indicator: Query<&mut Text, (With<DirtyIndicator>, Without<ToolLabel>)>,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+    for mut text in q_tool_label.iter_mut() {
+        text.0 = format!(
+            "{:?} - {:?} - size {}",
+            editor.tool, editor.tile, editor.brush_size
+        );
+    }
+    for mut text in q_dirty_indicator.iter_mut() {
+        text.0 = if editor.dirty.is_empty() {
+            "All changes saved".to_string()
+        } else {
+            let mut chunks = editor
+                .dirty
+                .keys()
+                .map(|(x, z)| format!("{},{}", x, z))
+                .collect::<Vec<_>>();
+            chunks.sort();
+            format!("* Unsaved: {}", chunks.join(" "))
+        };
+    }
+}
+
+fn cleanup(
+    mut commands: Commands,
+    mut editor: ResMut<Editor>,
+    mut editor_tiles: ResMut<EditorTiles>,
+    q_palette: Query<Entity, With<Palette>>,
+) {
+    editor.stroke = None;
+    editor.link = None;
+    for entity in editor_tiles.0.drain().flat_map(|(_, entities)| entities) {
+        commands.entity(entity).despawn_recursive();
+    }
+    for entity in q_palette.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
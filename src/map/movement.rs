@@ -0,0 +1,283 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::*;
+
+use crate::map;
+
+/// # Fastest a character can walk (in cells per second)
+pub const MAX_SPEED: f32 = 8.0;
+/// # Extra cells tolerated on top of `MAX_SPEED`, absorb network jitter
+pub const SLACK: u32 = 2;
+
+/// Last position of a remote character that passed validation
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Validated {
+    pub cell: map::TileCoordinate,
+    /// Time (in seconds since startup) at which the position was validated
+    pub at: f32,
+}
+
+impl Validated {
+    pub fn new(position: Vec3, at: f32) -> Self {
+        Self {
+            cell: map::TileCoordinate::from_world(position),
+            at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    /// The target chunk isn't loaded, we can't tell whether the move is valid
+    ChunkNotLoaded,
+    NotWalkable(map::Tile),
+    /// Too far away to have been reached in the elapsed time
+    TooFast {
+        distance: u32,
+        allowed: u32,
+    },
+    /// Reachable in straight line but not by walking
+    NoPath {
+        allowed: u32,
+    },
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::ChunkNotLoaded => write!(f, "target chunk isn't loaded"),
+            Violation::NotWalkable(tile) => write!(f, "target tile is {:?}", tile),
+            Violation::TooFast { distance, allowed } => {
+                write!(f, "{} cells away, {} allowed", distance, allowed)
+            }
+            Violation::NoPath { allowed } => write!(f, "no path within {} cells", allowed),
+        }
+    }
+}
+
+/// Amount of cells that can be walked in `elapsed` seconds
+pub fn allowed_steps(elapsed: f32) -> u32 {
+    (elapsed.max(0.0) * MAX_SPEED).ceil() as u32 + SLACK
+}
+
+/// Check that a character standing on `from` could have walked to `to` in `elapsed` seconds
+/// `tile_at` give the tile of any cell, `None` when its chunk isn't loaded
+pub fn validate(
+    from: map::TileCoordinate,
+    to: map::TileCoordinate,
+    elapsed: f32,
+    tile_at: impl Fn(map::TileCoordinate) -> Option<map::Tile>,
+) -> Result<(), Violation> {
+    if from == to {
+        return Ok(());
+    }
+    match tile_at(to) {
+        None => return Err(Violation::ChunkNotLoaded),
+        Some(tile) if tile.is_walkable() => {}
+        Some(tile) => return Err(Violation::NotWalkable(tile)),
+    }
+    let allowed = allowed_steps(elapsed);
+    let distance = from.walk_distance(to);
+    if distance > allowed {
+        return Err(Violation::TooFast { distance, allowed });
+    }
+
+    // Breadth first search limited to the allowed amount of steps
+    let mut visited = HashSet::from([(from.x, from.z)]);
+    let mut open = VecDeque::from([(from, 0)]);
+    while let Some((cell, depth)) = open.pop_front() {
+        if cell == to {
+            return Ok(());
+        }
+        if depth >= allowed {
+            continue;
+        }
+        for neighbour in cell.adjacent() {
+            // Cells too far to still reach the target in time aren't worth visiting
+            if neighbour.walk_distance(to) > allowed - depth - 1
+                || !visited.insert((neighbour.x, neighbour.z))
+            {
+                continue;
+            }
+            if tile_at(neighbour).is_some_and(map::Tile::is_walkable) {
+                open.push_back((neighbour, depth + 1));
+            }
+        }
+    }
+    Err(Violation::NoPath { allowed })
+}
+
+/// Check that a character standing on `from` could have used a teleporter leading to `to`
+/// in `elapsed` seconds, walking next to it first
+/// `chunk_at` give the chunks around `from`, `None` when they aren't loaded
+pub fn teleported<'a>(
+    from: map::TileCoordinate,
+    to: map::TileCoordinate,
+    elapsed: f32,
+    chunk_at: impl Fn(map::ChunkCoordinate) -> Option<&'a map::Chunk>,
+) -> bool {
+    let allowed = allowed_steps(elapsed);
+    let chunk = from.to_chunk();
+    (-1..=1)
+        .flat_map(|z| (-1..=1).map(move |x| map::ChunkCoordinate::new(chunk.x + x, chunk.z + z)))
+        .filter_map(|coord| chunk_at(coord).map(|data| (coord, data)))
+        .flat_map(|(coord, data)| {
+            data.objects
+                .iter()
+                .filter_map(move |object| match object.kind {
+                    map::chunk::ObjectKind::Teleporter { x, z }
+                        if map::TileCoordinate::new(x, z) == to =>
+                    {
+                        Some(object.cell().to_absolute(coord))
+                    }
+                    _ => None,
+                })
+        })
+        // Teleporters are used from a neighbouring cell
+        .any(|teleporter| from.walk_distance(teleporter) <= allowed + 1)
+}
+
+/// Chunk already loaded by the asset server
+pub fn loaded_chunk<'a>(
+    coord: map::ChunkCoordinate,
+    asset_server: &AssetServer,
+    chunk_assets: &'a Assets<map::Chunk>,
+) -> Option<&'a map::Chunk> {
+    let handle = asset_server.get_handle::<map::Chunk>(map::chunk::path(coord))?;
+    chunk_assets.get(handle.id())
+}
+
+/// Tile of `cell`, looked up in the chunks already loaded by the asset server
+/// Closed doors are seen as blocks
+pub fn loaded_tile(
+    cell: map::TileCoordinate,
+    asset_server: &AssetServer,
+    chunk_assets: &Assets<map::Chunk>,
+    objects: &map::objects::ObjectStates,
+) -> Option<map::Tile> {
+    objects.tile(
+        cell,
+        loaded_chunk(cell.to_chunk(), asset_server, chunk_assets)?,
+    )
+}
+
+/// `validate` a move against the chunks already loaded by the asset server,
+/// also accepting the ones made through a teleporter
+pub fn validate_loaded(
+    from: map::TileCoordinate,
+    to: map::TileCoordinate,
+    elapsed: f32,
+    asset_server: &AssetServer,
+    chunk_assets: &Assets<map::Chunk>,
+    objects: &map::objects::ObjectStates,
+) -> Result<(), Violation> {
+    validate(from, to, elapsed, |cell| {
+        loaded_tile(cell, asset_server, chunk_assets, objects)
+    })
+    .or_else(|violation| {
+        if teleported(from, to, elapsed, |coord| {
+            loaded_chunk(coord, asset_server, chunk_assets)
+        }) {
+            Ok(())
+        } else {
+            Err(violation)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{tile::DiagonalCoordinate, Tile, TileCoordinate};
+
+    /// Cells are laid out along the diagonals, so a step on one axis is a single walk step
+    fn cell(u: i32, v: i32) -> TileCoordinate {
+        DiagonalCoordinate::new(u, v).to_tile()
+    }
+
+    fn ground(_: TileCoordinate) -> Option<Tile> {
+        Some(Tile::Ground)
+    }
+
+    /// Wall across the `u = 3` line, only open past `|v| = 10`
+    fn wall(cell: TileCoordinate) -> Option<Tile> {
+        let diagonal = cell.to_diagonal();
+        Some(if diagonal.u == 3 && diagonal.v.abs() <= 10 {
+            Tile::Block
+        } else {
+            Tile::Ground
+        })
+    }
+
+    #[test]
+    fn straight_walk_within_the_allowed_steps() {
+        // 8 steps in a second, plus the slack
+        assert_eq!(allowed_steps(1.0), 8 + SLACK);
+        assert_eq!(validate(cell(0, 0), cell(8, 0), 1.0, ground), Ok(()));
+        assert_eq!(validate(cell(0, 0), cell(10, 0), 1.0, ground), Ok(()));
+        assert_eq!(
+            validate(cell(0, 0), cell(11, 0), 1.0, ground),
+            Err(Violation::TooFast {
+                distance: 11,
+                allowed: 10
+            })
+        );
+        assert_eq!(validate(cell(0, 0), cell(0, 0), 0.0, |_| None), Ok(()));
+    }
+
+    #[test]
+    fn wall_detour_longer_than_allowed() {
+        // Straight through the wall would be 6 steps, going around it takes far more
+        assert_eq!(cell(0, 0).walk_distance(cell(6, 0)), 6);
+        assert_eq!(allowed_steps(0.5), 6);
+        assert_eq!(
+            validate(cell(0, 0), cell(6, 0), 0.5, wall),
+            Err(Violation::NoPath { allowed: 6 })
+        );
+        assert_eq!(validate(cell(0, 0), cell(6, 0), 5.0, wall), Ok(()));
+    }
+
+    #[test]
+    fn target_must_be_walkable_and_loaded() {
+        assert_eq!(
+            validate(cell(0, 0), cell(3, 0), 10.0, wall),
+            Err(Violation::NotWalkable(Tile::Block))
+        );
+        assert_eq!(
+            validate(cell(0, 0), cell(1, 0), 10.0, |_| None),
+            Err(Violation::ChunkNotLoaded)
+        );
+    }
+
+    #[test]
+    fn teleporter_jump() {
+        let mut chunk = ron::de::from_str::<map::Chunk>("([])").unwrap();
+        chunk.objects.push(map::chunk::Object {
+            x: 5,
+            z: 6,
+            kind: map::chunk::ObjectKind::Teleporter { x: 100, z: 300 },
+        });
+        let origin = map::ChunkCoordinate::new(0, 0);
+        let chunk_at = |coord: map::ChunkCoordinate| (coord == origin).then_some(&chunk);
+        let teleporter = TileCoordinate::<map::tile::LocalSpace>::new(5, 6).to_absolute(origin);
+        let destination = TileCoordinate::new(100, 300);
+        let next_to = teleporter + TileCoordinate::new(1, 0);
+
+        assert!(validate(next_to, destination, 0.0, ground).is_err());
+        assert!(teleported(next_to, destination, 0.0, chunk_at));
+        // Walking to the teleporter first
+        let diagonal = teleporter.to_diagonal();
+        let far = DiagonalCoordinate::new(diagonal.u + SLACK as i32 + 1, diagonal.v).to_tile();
+        assert!(teleported(far, destination, 0.0, chunk_at));
+        let too_far = DiagonalCoordinate::new(diagonal.u + SLACK as i32 + 2, diagonal.v).to_tile();
+        assert!(!teleported(too_far, destination, 0.0, chunk_at));
+        // Another destination, or the chunk not loaded
+        assert!(!teleported(
+            next_to,
+            TileCoordinate::new(100, 301),
+            0.0,
+            chunk_at
+        ));
+        assert!(!teleported(next_to, destination, 0.0, |_| None));
+    }
+}
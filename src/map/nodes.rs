@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    map::{
+        self,
+        chunk::NodeKind,
+        streaming::{CatchUpEvent, ChunkEntities, Streamer},
+        Chunk, ChunkCoordinate, TileCoordinate,
+    },
+    player::{MainPlayer, Player},
+    socket, GameMode,
+};
+
+/// # Angle (in degrees) a node sways by while being harvested
+const SWAY_ANGLE: f32 = 8.0;
+
+pub(super) fn init(app: &mut App) {
+    app.init_resource::<Depleted>()
+        .init_resource::<NodeEntities>()
+        .add_systems(Update, tick_respawns)
+        .add_systems(
+            Update,
+            (sync_nodes, animate_nodes)
+                .chain()
+                .run_if(in_state(GameMode::Exploration)),
+        )
+        .add_systems(OnExit(GameMode::Exploration), despawn_all)
+        .add_observer(on_remote_harvest)
+        .add_observer(on_catch_up);
+}
+
+/// What harvesting a kind of node takes and gives
+pub struct Harvest {
+    pub item: &'static str,
+    /// Seconds spent next to the node
+    pub duration: f32,
+    /// Seconds before the node grows back
+    pub respawn: f32,
+    pub experience: u64,
+}
+
+pub fn harvest(kind: NodeKind) -> Harvest {
+    match kind {
+        NodeKind::Tree => Harvest {
+            item: "Wood",
+            duration: 3.0,
+            respawn: 60.0,
+            experience: 10,
+        },
+        NodeKind::Wheat => Harvest {
+            item: "Wheat",
+            duration: 2.0,
+            respawn: 30.0,
+            experience: 5,
+        },
+        NodeKind::Ore => Harvest {
+            item: "Iron ore",
+            duration: 4.0,
+            respawn: 90.0,
+            experience: 15,
+        },
+    }
+}
+
+/// Nodes harvested recently, by absolute cell, with the time left before they grow back
+#[derive(Resource, Default)]
+pub struct Depleted(HashMap<(i32, i32), Timer>);
+
+impl Depleted {
+    pub fn contains(&self, cell: TileCoordinate) -> bool {
+        self.0.contains_key(&(cell.x, cell.z))
+    }
+
+    pub fn deplete(&mut self, cell: TileCoordinate, kind: NodeKind) {
+        self.0.insert(
+            (cell.x, cell.z),
+            Timer::from_seconds(harvest(kind).respawn, TimerMode::Once),
+        );
+    }
+
+    /// Depleted cells of `chunk`
+    pub fn depleted_in(&self, chunk: ChunkCoordinate) -> impl Iterator<Item = TileCoordinate> + '_ {
+        self.0
+            .keys()
+            .map(|(x, z)| TileCoordinate::new(*x, *z))
+            .filter(move |cell| cell.to_chunk() == chunk)
+    }
+}
+
+/// Node entities spawned for every loaded chunk
+#[derive(Resource, Default)]
+struct NodeEntities(ChunkEntities);
+
+/// Visual of the resource node standing on an absolute cell
+#[derive(Component, Debug)]
+pub struct NodeMarker {
+    pub cell: TileCoordinate,
+    /// Height of the mesh, to keep it on the ground once flattened
+    height: f32,
+}
+
+/// Sway played by a node while the main player harvests it
+#[derive(Component, Debug)]
+pub struct Harvesting;
+
+/// The respawn delay isn't sent, it comes from the node found on our own copy of the chunk
+/// With a dedicated server only its relays are trusted, otherwise the harvester
+/// has to stand on the node's chunk or a neighbour one
+fn on_remote_harvest(
+    trigger: Trigger<socket::NodeHarvestedEvent>,
+    mut depleted: ResMut<Depleted>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<Chunk>>,
+    server_id: Option<Res<socket::ServerId>>,
+    q_players: Query<(&Transform, &socket::Id), (With<Player>, Without<MainPlayer>)>,
+) {
+    let event = trigger.event();
+    let cell = TileCoordinate::new(event.x, event.z);
+    let trusted = match server_id {
+        Some(server) => server.0 == event.peer_id,
+        None => q_players
+            .iter()
+            .find(|(_, id)| id.0 == event.peer_id)
+            .is_some_and(|(transform, _)| {
+                let stand = ChunkCoordinate::from_world(transform.translation);
+                let node = cell.to_chunk();
+                (stand.x - node.x).abs() <= 1 && (stand.z - node.z).abs() <= 1
+            }),
+    };
+    if !trusted {
+        warn!(
+            "Ignoring the harvest of {:?} from {}, who can't reach it",
+            cell, event.peer_id
+        );
+        return;
+    }
+    let Some(node) = asset_server
+        .get_handle::<Chunk>(map::chunk::path(cell.to_chunk()))
+        .and_then(|handle| chunk_assets.get(handle.id()))
+        .and_then(|chunk| chunk.node_at(cell.to_local()))
+    else {
+        warn!("{} harvested a node missing from {:?}", event.peer_id, cell);
+        return;
+    };
+    depleted.deplete(cell, node.kind);
+}
+
+/// Tell a peer arriving on our chunk which of its nodes were harvested before they came
+fn on_catch_up(trigger: Trigger<CatchUpEvent>, mut commands: Commands, depleted: Res<Depleted>) {
+    let event = trigger.event();
+    for cell in depleted.depleted_in(event.chunk) {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::NodeHarvested {
+                x: cell.x,
+                z: cell.z,
+            },
+            event.peer_id,
+        ));
+    }
+}
+
+fn tick_respawns(time: Res<Time>, mut depleted: ResMut<Depleted>) {
+    depleted.0.retain(|_, timer| {
+        timer.tick(time.delta());
+        !timer.finished()
+    });
+}
+
+/// Spawn the nodes of the chunks being loaded or edited, and despawn the released ones
+fn sync_nodes(
+    mut commands: Commands,
+    mut node_entities: ResMut<NodeEntities>,
+    mut chunk_events: EventReader<AssetEvent<Chunk>>,
+    streamer: Res<Streamer>,
+    chunk_assets: Res<Assets<Chunk>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    node_entities.0.sync(
+        &mut commands,
+        &mut chunk_events,
+        &streamer,
+        &chunk_assets,
+        |commands, coord, chunk| {
+            chunk
+                .nodes
+                .iter()
+                .map(|node| spawn(commands, &mut meshes, &mut materials, coord, node))
+                .collect()
+        },
+    );
+}
+
+fn spawn(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    coord: ChunkCoordinate,
+    node: &map::chunk::ResourceNode,
+) -> Entity {
+    let (mesh, height, color): (Mesh, f32, Color) = match node.kind {
+        NodeKind::Tree => (
+            Cylinder::new(map::tile::SIZE * 0.3, 1.5).into(),
+            1.5,
+            Color::srgb(0.2, 0.5, 0.2),
+        ),
+        NodeKind::Wheat => (
+            Cuboid::new(map::tile::SIZE * 0.7, 0.5, map::tile::SIZE * 0.7).into(),
+            0.5,
+            Color::srgb(0.9, 0.8, 0.3),
+        ),
+        NodeKind::Ore => (
+            Sphere::new(map::tile::SIZE * 0.35).into(),
+            map::tile::SIZE * 0.7,
+            Color::srgb(0.5, 0.45, 0.5),
+        ),
+    };
+    let cell = node.cell().to_absolute(coord);
+    commands
+        .spawn((
+            Mesh3d(meshes.add(mesh)),
+            MeshMaterial3d(materials.add(color)),
+            Transform::from_translation(cell.to_world()),
+            NodeMarker { cell, height },
+            map::MapComponent,
+        ))
+        .id()
+}
+
+/// Flatten the depleted nodes, and sway the one being harvested
+fn animate_nodes(
+    time: Res<Time>,
+    depleted: Res<Depleted>,
+    mut q_nodes: Query<(&NodeMarker, &mut Transform, Has<Harvesting>)>,
+) {
+    for (marker, mut transform, harvesting) in q_nodes.iter_mut() {
+        let scale = if depleted.contains(marker.cell) {
+            0.1
+        } else {
+            1.0
+        };
+        let angle = if harvesting {
+            SWAY_ANGLE * (time.elapsed_secs() * 10.0).sin()
+        } else {
+            0.0
+        };
+        transform.scale = Vec3::new(1.0, scale, 1.0);
+        transform.rotation = Quat::from_rotation_z(angle.to_radians());
+        transform.translation = marker.cell.to_world() + Vec3::Y * (0.5 * marker.height * scale);
+    }
+}
+
+fn despawn_all(mut commands: Commands, mut node_entities: ResMut<NodeEntities>) {
+    node_entities.0.despawn_all(&mut commands);
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    map::{
+        self,
+        chunk::ObjectKind,
+        streaming::{CatchUpEvent, ChunkEntities, Streamer},
+        Chunk, ChunkCoordinate, Tile, TileCoordinate,
+    },
+    player::{MainPlayer, Player},
+    socket, GameMode,
+};
+
+/// # Duration (in seconds) of the bounce played by an object when it is used
+const PULSE_DURATION: f32 = 0.3;
+/// # Angle (in degrees) of a lever, negated once pulled
+const LEVER_ANGLE: f32 = 30.0;
+
+pub(super) fn init(app: &mut App) {
+    app.init_resource::<ObjectStates>()
+        .init_resource::<ObjectEntities>()
+        .add_systems(
+            Update,
+            (sync_objects, animate_objects)
+                .chain()
+                .run_if(in_state(GameMode::Exploration)),
+        )
+        .add_systems(OnExit(GameMode::Exploration), despawn_all)
+        .add_observer(on_remote_state)
+        .add_observer(on_known_state)
+        .add_observer(on_catch_up);
+}
+
+/// State of the doors and levers changed since the game started, by absolute cell
+/// Objects not listed are in the state given by their chunk data
+#[derive(Resource, Default)]
+pub struct ObjectStates(HashMap<(i32, i32), bool>);
+
+impl ObjectStates {
+    /// Whether the door is open, or the lever pulled
+    pub fn is_active(&self, cell: TileCoordinate, kind: &ObjectKind) -> bool {
+        self.0
+            .get(&(cell.x, cell.z))
+            .copied()
+            .unwrap_or(matches!(kind, ObjectKind::Door { open: true }))
+    }
+
+    pub fn set(&mut self, cell: TileCoordinate, active: bool) {
+        self.0.insert((cell.x, cell.z), active);
+    }
+
+    /// States changed on the objects of `chunk`
+    pub fn changed_in(
+        &self,
+        chunk: ChunkCoordinate,
+    ) -> impl Iterator<Item = (TileCoordinate, bool)> + '_ {
+        self.0
+            .iter()
+            .map(|((x, z), active)| (TileCoordinate::new(*x, *z), *active))
+            .filter(move |(cell, _)| cell.to_chunk() == chunk)
+    }
+
+    /// Whether a closed door stands on `cell`, `chunk` being the chunk it is in
+    pub fn blocks(&self, cell: TileCoordinate, chunk: &Chunk) -> bool {
+        chunk.object_at(cell.to_local()).is_some_and(|object| {
+            matches!(object.kind, ObjectKind::Door { .. }) && !self.is_active(cell, &object.kind)
+        })
+    }
+
+    /// Tile a character see on `cell`, closed doors behaving like blocks
+    pub fn tile(&self, cell: TileCoordinate, chunk: &Chunk) -> Option<Tile> {
+        let tile = *chunk.get_tile(cell.to_local())?;
+        Some(if self.blocks(cell, chunk) {
+            Tile::Block
+        } else {
+            tile
+        })
+    }
+}
+
+/// Object entities spawned for every loaded chunk
+#[derive(Resource, Default)]
+struct ObjectEntities(ChunkEntities);
+
+/// Visual of the interactive object standing on an absolute cell
+#[derive(Component, Debug)]
+pub struct ObjectMarker(pub TileCoordinate);
+
+/// Short bounce played by an object being used
+#[derive(Component, Debug)]
+pub struct Pulse(Timer);
+
+impl Default for Pulse {
+    fn default() -> Self {
+        Self(Timer::from_seconds(PULSE_DURATION, TimerMode::Once))
+    }
+}
+
+/// Whether a character standing on `stand` could have changed the object on `cell`,
+/// by using it or a lever linked to it
+pub fn can_toggle(
+    stand: TileCoordinate,
+    cell: TileCoordinate,
+    asset_server: &AssetServer,
+    chunk_assets: &Assets<Chunk>,
+) -> bool {
+    let chunk = cell.to_chunk();
+    let Some(data) = map::movement::loaded_chunk(chunk, asset_server, chunk_assets) else {
+        return false;
+    };
+    // The last validated position may lag a few cells behind
+    let within_reach =
+        |object: TileCoordinate| stand.walk_distance(object) <= 1 + map::movement::SLACK;
+    let Some(object) = data.object_at(cell.to_local()) else {
+        return false;
+    };
+    if matches!(object.kind, ObjectKind::Teleporter { .. }) {
+        return false;
+    }
+    within_reach(cell)
+        || data.objects.iter().any(|lever| match &lever.kind {
+            ObjectKind::Lever { targets } => {
+                targets.iter().any(|(x, z)| {
+                    TileCoordinate::<map::tile::LocalSpace>::new(*x, *z) == cell.to_local()
+                }) && within_reach(lever.cell().to_absolute(chunk))
+            }
+            _ => false,
+        })
+}
+
+/// With a dedicated server, only the states it validated and relayed are applied,
+/// otherwise the sender has to be within reach of the object
+fn on_remote_state(
+    trigger: Trigger<socket::ObjectStateEvent>,
+    mut states: ResMut<ObjectStates>,
+    server_id: Option<Res<socket::ServerId>>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<Chunk>>,
+    q_players: Query<(&map::movement::Validated, &socket::Id), (With<Player>, Without<MainPlayer>)>,
+) {
+    let event = trigger.event();
+    let cell = TileCoordinate::new(event.x, event.z);
+    let trusted = match server_id {
+        Some(server_id) => server_id.0 == event.peer_id,
+        None => q_players
+            .iter()
+            .find(|(_, id)| id.0 == event.peer_id)
+            .is_some_and(|(validated, _)| {
+                can_toggle(validated.cell, cell, &asset_server, &chunk_assets)
+            }),
+    };
+    if !trusted {
+        warn!(
+            "Ignoring the state of the object on {} from {}, who can't reach it",
+            cell, event.peer_id
+        );
+        return;
+    }
+    states.set(cell, event.active);
+}
+
+/// Catch up sent by a player already on the object's chunk, only without a dedicated server
+fn on_known_state(
+    trigger: Trigger<socket::KnownObjectStateEvent>,
+    mut states: ResMut<ObjectStates>,
+    server_id: Option<Res<socket::ServerId>>,
+    q_players: Query<(&map::movement::Validated, &socket::Id), (With<Player>, Without<MainPlayer>)>,
+) {
+    let event = trigger.event();
+    let cell = TileCoordinate::new(event.x, event.z);
+    let on_chunk = q_players
+        .iter()
+        .find(|(_, id)| id.0 == event.peer_id)
+        .is_some_and(|(validated, _)| validated.cell.to_chunk() == cell.to_chunk());
+    if server_id.is_some() || !on_chunk {
+        warn!(
+            "Ignoring the known state of the object on {} from {}",
+            cell, event.peer_id
+        );
+        return;
+    }
+    states.set(cell, event.active);
+}
+
+/// Tell a peer arriving on our chunk about the objects changed before they came
+fn on_catch_up(trigger: Trigger<CatchUpEvent>, mut commands: Commands, states: Res<ObjectStates>) {
+    let event = trigger.event();
+    for (cell, active) in states.changed_in(event.chunk) {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::KnownObjectState {
+                x: cell.x,
+                z: cell.z,
+                active,
+            },
+            event.peer_id,
+        ));
+    }
+}
+
+/// Spawn the objects of the chunks being loaded or edited, and despawn the released ones
+fn sync_objects(
+    mut commands: Commands,
+    mut object_entities: ResMut<ObjectEntities>,
+    mut chunk_events: EventReader<AssetEvent<Chunk>>,
+    streamer: Res<Streamer>,
+    chunk_assets: Res<Assets<Chunk>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    object_entities.0.sync(
+        &mut commands,
+        &mut chunk_events,
+        &streamer,
+        &chunk_assets,
+        |commands, coord, chunk| {
+            chunk
+                .objects
+                .iter()
+                .map(|object| spawn(commands, &mut meshes, &mut materials, coord, object))
+                .collect()
+        },
+    );
+}
+
+fn spawn(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    coord: ChunkCoordinate,
+    object: &map::chunk::Object,
+) -> Entity {
+    let (mesh, color) = match object.kind {
+        ObjectKind::Door { .. } => (
+            Cuboid::new(map::tile::SIZE, 1.0, map::tile::SIZE * 0.25),
+            Color::srgb(0.45, 0.3, 0.15),
+        ),
+        ObjectKind::Teleporter { .. } => (
+            Cuboid::new(map::tile::SIZE * 0.8, 0.05, map::tile::SIZE * 0.8),
+            Color::srgb(0.4, 0.2, 0.9),
+        ),
+        ObjectKind::Lever { .. } => (Cuboid::new(0.08, 0.5, 0.08), Color::srgb(0.6, 0.6, 0.6)),
+    };
+    let cell = object.cell().to_absolute(coord);
+    commands
+        .spawn((
+            Mesh3d(meshes.add(mesh)),
+            MeshMaterial3d(materials.add(color)),
+            Transform::from_translation(cell.to_world()),
+            ObjectMarker(cell),
+            map::MapComponent,
+        ))
+        .id()
+}
+
+/// Place every object according to its state, bouncing the ones being used
+fn animate_objects(
+    mut commands: Commands,
+    time: Res<Time>,
+    states: Res<ObjectStates>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<Chunk>>,
+    mut q_objects: Query<(Entity, &ObjectMarker, &mut Transform, Option<&mut Pulse>)>,
+) {
+    for (entity, marker, mut transform, pulse) in q_objects.iter_mut() {
+        let Some(object) = asset_server
+            .get_handle::<Chunk>(map::chunk::path(marker.0.to_chunk()))
+            .and_then(|handle| chunk_assets.get(handle.id()))
+            .and_then(|chunk| chunk.object_at(marker.0.to_local()))
+        else {
+            continue;
+        };
+        let active = states.is_active(marker.0, &object.kind);
+        let (height, angle) = match object.kind {
+            // An open door is laid flat on the ground
+            ObjectKind::Door { .. } if active => (0.05, 0.0),
+            ObjectKind::Door { .. } => (1.0, 0.0),
+            ObjectKind::Teleporter { .. } => (1.0, 0.0),
+            ObjectKind::Lever { .. } if active => (1.0, -LEVER_ANGLE),
+            ObjectKind::Lever { .. } => (1.0, LEVER_ANGLE),
+        };
+        let bounce = match pulse {
+            Some(mut pulse) => {
+                pulse.0.tick(time.delta());
+                if pulse.0.finished() {
+                    commands.entity(entity).remove::<Pulse>();
+                }
+                1.0 + 0.3 * (pulse.0.fraction() * std::f32::consts::PI).sin()
+            }
+            None => 1.0,
+        };
+        transform.scale = Vec3::new(bounce, height * bounce, bounce);
+        transform.rotation = Quat::from_rotation_z(angle.to_radians());
+        transform.translation = marker.0.to_world() + Vec3::Y * (0.5 * height * bounce);
+    }
+}
+
+fn despawn_all(mut commands: Commands, mut object_entities: ResMut<ObjectEntities>) {
+    object_entities.0.despawn_all(&mut commands);
+}
@@ -0,0 +1,256 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::{asset::LoadState, prelude::*};
+use bevy_matchbox::prelude::PeerId;
+
+use crate::{
+    map::{self, world::CurrentWorldMap, Chunk, ChunkCoordinate, CurrentChunk, WorldMap},
+    player::MainPlayer,
+    GameMode,
+};
+
+pub(super) fn init(app: &mut App) {
+    app.init_resource::<Settings>()
+        .init_resource::<Streamer>()
+        .add_systems(
+            Update,
+            (follow_main_player, drop_missing_chunks).run_if(in_state(GameMode::Exploration)),
+        )
+        .add_observer(on_transition);
+}
+
+/// How many chunks are kept loaded around the current one
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Settings {
+    /// Amount of world map links walked from the current chunk to find the chunks to preload
+    pub preload_radius: u32,
+    /// Loaded chunks further away than this (in chunks, on any axis) are released
+    pub evict_distance: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            preload_radius: 1,
+            evict_distance: 2,
+        }
+    }
+}
+
+/// Make a chunk the current one, preloading its neighbours and releasing distant chunks
+#[derive(Debug, Event)]
+pub struct TransitionEvent(pub ChunkCoordinate);
+
+/// A peer arrived on `chunk` while we were on it, without a dedicated server
+/// to tell them what changed there before they came
+#[derive(Debug, Event)]
+pub struct CatchUpEvent {
+    pub peer_id: PeerId,
+    pub chunk: ChunkCoordinate,
+}
+
+/// Every chunk currently loaded, keeping their handles alive
+#[derive(Resource, Default)]
+pub struct Streamer {
+    centre: Option<ChunkCoordinate>,
+    loaded: HashMap<(i32, i32), CurrentChunk>,
+    /// Chunks not listed in the world map or whose files failed to load, not requested again
+    missing: HashSet<(i32, i32)>,
+}
+
+impl Streamer {
+    pub fn centre(&self) -> Option<ChunkCoordinate> {
+        self.centre
+    }
+
+    pub fn is_loaded(&self, coord: ChunkCoordinate) -> bool {
+        self.loaded.contains_key(&(coord.x, coord.z))
+    }
+
+    pub fn loaded(&self) -> impl Iterator<Item = (ChunkCoordinate, &CurrentChunk)> {
+        self.loaded
+            .iter()
+            .map(|((x, z), chunk)| (ChunkCoordinate::new(*x, *z), chunk))
+    }
+
+    fn request(
+        &mut self,
+        coord: ChunkCoordinate,
+        asset_server: &Res<AssetServer>,
+        world_map: &WorldMap,
+    ) -> Option<CurrentChunk> {
+        let key = (coord.x, coord.z);
+        if self.missing.contains(&key) {
+            return None;
+        }
+        if let Some(chunk) = self.loaded.get(&key) {
+            return Some(chunk.clone());
+        }
+        let Some(chunk) = map::chunk::load(coord, asset_server, world_map) else {
+            self.missing.insert(key);
+            return None;
+        };
+        self.loaded.insert(key, chunk.clone());
+        Some(chunk)
+    }
+
+    /// Make `coord` the current chunk, return `None` (and change nothing)
+    /// if the world map doesn't list it or its files are missing
+    pub fn enter(
+        &mut self,
+        commands: &mut Commands,
+        coord: ChunkCoordinate,
+        asset_server: &Res<AssetServer>,
+        world_map: &WorldMap,
+        settings: &Settings,
+    ) -> Option<CurrentChunk> {
+        let Some(current_chunk) = self.request(coord, asset_server, world_map) else {
+            warn!("Chunk {:?} isn't available, staying where we are", coord);
+            return None;
+        };
+        self.centre = Some(coord);
+        commands.insert_resource(current_chunk.clone());
+
+        // Walk the world map links, so only reachable chunks are preloaded
+        let mut visited = HashSet::from([(coord.x, coord.z)]);
+        let mut queue = VecDeque::from([(coord, 0)]);
+        while let Some((chunk, depth)) = queue.pop_front() {
+            if depth == settings.preload_radius {
+                continue;
+            }
+            let Some(info) = world_map.get(chunk) else {
+                continue;
+            };
+            for neighbour in info.neighbours.iter() {
+                if visited.insert((neighbour.x, neighbour.z)) {
+                    self.request(neighbour, asset_server, world_map);
+                    queue.push_back((neighbour, depth + 1));
+                }
+            }
+        }
+
+        let evict_distance = settings.evict_distance.max(settings.preload_radius) as i32;
+        self.loaded.retain(|(x, z), _| {
+            (x - coord.x).abs() <= evict_distance && (z - coord.z).abs() <= evict_distance
+        });
+        Some(current_chunk)
+    }
+}
+/// Entities spawned from the data of every loaded chunk, like its NPCs or objects
+#[derive(Debug, Default)]
+pub struct ChunkEntities(HashMap<(i32, i32), Vec<Entity>>);
+
+impl ChunkEntities {
+    /// Despawn the entities of the released and modified chunks,
+    /// then `spawn` those of the loaded chunks that have none
+    pub fn sync(
+        &mut self,
+        commands: &mut Commands,
+        chunk_events: &mut EventReader<AssetEvent<Chunk>>,
+        streamer: &Streamer,
+        chunk_assets: &Assets<Chunk>,
+        mut spawn: impl FnMut(&mut Commands, ChunkCoordinate, &Chunk) -> Vec<Entity>,
+    ) {
+        let modified = chunk_events
+            .read()
+            .filter_map(|event| match event {
+                AssetEvent::Modified { id } => Some(*id),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        self.0.retain(|key, entities| {
+            let keep = streamer
+                .loaded
+                .get(key)
+                .is_some_and(|current| !modified.contains(&current.grid.id()));
+            if !keep {
+                for entity in entities.drain(..) {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            keep
+        });
+        for (coord, current) in streamer.loaded() {
+            if self.0.contains_key(&(coord.x, coord.z)) {
+                continue;
+            }
+            let Some(chunk) = chunk_assets.get(current.grid.id()) else {
+                continue;
+            };
+            self.0
+                .insert((coord.x, coord.z), spawn(commands, coord, chunk));
+        }
+    }
+
+    pub fn despawn_all(&mut self, commands: &mut Commands) {
+        for entity in self.0.drain().flat_map(|(_, entities)| entities) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn on_transition(
+    trigger: Trigger<TransitionEvent>,
+    mut commands: Commands,
+    mut streamer: ResMut<Streamer>,
+    settings: Res<Settings>,
+    asset_server: Res<AssetServer>,
+    current_world_map: Res<CurrentWorldMap>,
+    world_map_assets: Res<Assets<WorldMap>>,
+) {
+    let Some(world_map) = world_map_assets.get(current_world_map.0.id()) else {
+        warn!("World map isn't loaded, can't enter chunk {:?}", trigger.0);
+        return;
+    };
+    if streamer
+        .enter(
+            &mut commands,
+            trigger.0,
+            &asset_server,
+            world_map,
+            &settings,
+        )
+        .is_some()
+    {
+        info!("Entering chunk {:?}", trigger.0);
+    }
+}
+
+fn follow_main_player(
+    mut commands: Commands,
+    streamer: Res<Streamer>,
+    q_player: Query<&Transform, With<MainPlayer>>,
+) {
+    let Ok(transform) = q_player.get_single() else {
+        return;
+    };
+    let coord = ChunkCoordinate::from_world(transform.translation);
+    if streamer.centre.is_some_and(|centre| centre != coord)
+        && !streamer.missing.contains(&(coord.x, coord.z))
+    {
+        commands.trigger(TransitionEvent(coord));
+    }
+}
+
+/// Forget about chunks whose files can't be loaded, instead of waiting on them forever
+fn drop_missing_chunks(mut streamer: ResMut<Streamer>, asset_server: Res<AssetServer>) {
+    let failed = streamer
+        .loaded
+        .iter()
+        .filter(|(_, chunk)| {
+            matches!(
+                asset_server.get_load_state(chunk.grid.id()),
+                Some(LoadState::Failed(_))
+            )
+        })
+        .map(|(key, _)| *key)
+        .collect::<Vec<_>>();
+    for key in failed {
+        warn!(
+            "Chunk {:?} failed to load, it won't be requested again",
+            key
+        );
+        streamer.loaded.remove(&key);
+        streamer.missing.insert(key);
+    }
+}
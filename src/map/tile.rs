@@ -18,6 +18,8 @@ pub const SPACING_X: f32 = 2.0;
 pub const SPACING_Z: f32 = SPACING_X / 2.0;
 /// # Tile mesh diameter
 pub const SIZE: f32 = SPACING_X / std::f32::consts::SQRT_2;
+/// # Height of a single elevation level
+pub const LEVEL_HEIGHT: f32 = 0.25;
 
 /// Relative to the chunk
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -58,12 +60,13 @@ impl<State> TileCoordinate<State> {
 impl TileCoordinate<AbsoluteSpace> {
     pub fn from_world(world_position: Vec3) -> Self {
         let z = (world_position.z / SPACING_X * 2.0).round() as i32;
-        let x = ((world_position.x - (z % 2) as f32 * SPACING_X / 2.0) / SPACING_X).round() as i32;
+        let x = ((world_position.x - z.rem_euclid(2) as f32 * SPACING_X / 2.0) / SPACING_X).round()
+            as i32;
         Self::new(x, z)
     }
 
     pub fn to_world(self) -> Vec3 {
-        let x = self.x as f32 * SPACING_X + (self.z % 2) as f32 * (SPACING_X / 2.0);
+        let x = self.x as f32 * SPACING_X + self.z.rem_euclid(2) as f32 * (SPACING_X / 2.0);
         let z = self.z as f32 * SPACING_Z;
         Vec3::new(x, 0.0, z)
     }
@@ -72,25 +75,132 @@ impl TileCoordinate<AbsoluteSpace> {
         map::ChunkCoordinate::from_tile(self)
     }
 
+    /// Euclidean remainder, so negative tiles stay in the chunk they belong to
+    /// (tile -1 is the last tile of chunk -1, not the second of chunk 0)
     pub fn to_local(self) -> TileCoordinate<LocalSpace> {
-        let local_x = self.x.abs() % map::chunk::SIZE_X as i32;
-        let local_z = self.z.abs() % map::chunk::SIZE_Z as i32;
-        debug_assert!(
-            local_x >= 0
-                && local_x < map::chunk::SIZE_X as i32
-                && local_z >= 0
-                && local_z < map::chunk::SIZE_Z as i32,
-            "Out of bounds tile coordinate: from ({}, {}) to ({}, {})",
-            self.x,
-            self.z,
-            local_x,
-            local_z
-        );
+        let local_x = self.x.rem_euclid(map::chunk::SIZE_X as i32);
+        let local_z = self.z.rem_euclid(map::chunk::SIZE_Z as i32);
         TileCoordinate::new(local_x, local_z)
     }
 
     pub fn on_odd_row(self) -> bool {
-        self.z % 2 == 1
+        self.z.rem_euclid(2) == 1
+    }
+}
+
+/// The 8 cells around a tile
+/// Tiles are diamonds, the first 4 directions share an edge with the tile, the last 4 only a corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    UpRight,
+    DownRight,
+    DownLeft,
+    UpLeft,
+    Up,
+    Right,
+    Down,
+    Left,
+}
+
+impl Direction {
+    /// Directions sharing an edge, the only ones allowed in fights
+    pub const EDGES: [Direction; 4] = [
+        Direction::UpRight,
+        Direction::DownRight,
+        Direction::DownLeft,
+        Direction::UpLeft,
+    ];
+    pub const ALL: [Direction; 8] = [
+        Direction::UpRight,
+        Direction::DownRight,
+        Direction::DownLeft,
+        Direction::UpLeft,
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ];
+
+    pub fn is_diagonal(self) -> bool {
+        !Self::EDGES.contains(&self)
+    }
+
+    /// Offset in the diagonal coordinate system
+    fn offset(self) -> (i32, i32) {
+        match self {
+            Direction::UpRight => (1, 0),
+            Direction::DownRight => (0, -1),
+            Direction::DownLeft => (-1, 0),
+            Direction::UpLeft => (0, 1),
+            Direction::Up => (1, 1),
+            Direction::Right => (1, -1),
+            Direction::Down => (-1, -1),
+            Direction::Left => (-1, 1),
+        }
+    }
+}
+
+/// Tile coordinate along the two diagonals of the grid
+/// The staggered rows become a regular square grid, where tiles sharing an edge differ by one on a single axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DiagonalCoordinate {
+    /// Toward `Direction::UpRight`
+    pub u: i32,
+    /// Toward `Direction::UpLeft`
+    pub v: i32,
+}
+
+impl DiagonalCoordinate {
+    pub fn new(u: i32, v: i32) -> Self {
+        Self { u, v }
+    }
+
+    pub fn to_tile(self) -> TileCoordinate {
+        // Position in half tiles, the odd rows being shifted by one
+        let half_x = self.u - self.v;
+        let z = self.u + self.v;
+        TileCoordinate::new((half_x - z.rem_euclid(2)).div_euclid(2), z)
+    }
+}
+
+impl TileCoordinate<AbsoluteSpace> {
+    pub fn to_diagonal(self) -> DiagonalCoordinate {
+        let half_x = self.x * 2 + self.z.rem_euclid(2);
+        DiagonalCoordinate::new((half_x + self.z) / 2, (self.z - half_x) / 2)
+    }
+
+    pub fn neighbour(self, direction: Direction) -> Self {
+        let diagonal = self.to_diagonal();
+        let (u, v) = direction.offset();
+        DiagonalCoordinate::new(diagonal.u + u, diagonal.v + v).to_tile()
+    }
+
+    /// The 4 tiles sharing an edge with this one
+    pub fn neighbours(self) -> impl Iterator<Item = Self> {
+        Direction::EDGES
+            .into_iter()
+            .map(move |direction| self.neighbour(direction))
+    }
+
+    /// The 8 tiles touching this one, by an edge or a corner
+    pub fn adjacent(self) -> impl Iterator<Item = Self> {
+        Direction::ALL
+            .into_iter()
+            .map(move |direction| self.neighbour(direction))
+    }
+
+    /// Amount of steps between two tiles moving only through edges, used for fight ranges and areas
+    pub fn distance(self, other: Self) -> u32 {
+        let a = self.to_diagonal();
+        let b = other.to_diagonal();
+        (a.u - b.u).unsigned_abs() + (a.v - b.v).unsigned_abs()
+    }
+
+    /// Amount of steps between two tiles when corners can also be crossed, like when exploring
+    pub fn walk_distance(self, other: Self) -> u32 {
+        let a = self.to_diagonal();
+        let b = other.to_diagonal();
+        (a.u - b.u).unsigned_abs().max((a.v - b.v).unsigned_abs())
     }
 }
 
@@ -148,48 +258,209 @@ impl Div<i32> for TileCoordinate {
     Component, Reflect, Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq,
 )]
 pub enum Tile {
+    /// Can't be walked on nor seen through
     Block,
     #[default]
     Ground,
+    /// Hole in the map, can't be walked on but can be seen through
     Empty,
+    /// Can't be walked on but can be seen through
+    Water,
+}
+
+impl Tile {
+    pub fn is_walkable(self) -> bool {
+        self == Tile::Ground
+    }
+
+    pub fn blocks_line_of_sight(self) -> bool {
+        self == Tile::Block
+    }
+}
+
+fn default_cost() -> u8 {
+    1
+}
+
+/// Everything about a cell that isn't given by its tile
+#[derive(Component, Reflect, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Properties {
+    /// Elevation, 0 being the ground level
+    #[serde(default)]
+    pub level: i8,
+    /// Movement points spent to walk on the cell
+    #[serde(default = "default_cost")]
+    pub cost: u8,
+    /// Walkable while exploring, but not during fights
+    #[serde(default)]
+    pub blocked_in_fight: bool,
+}
+
+impl Default for Properties {
+    fn default() -> Self {
+        Self {
+            level: 0,
+            cost: default_cost(),
+            blocked_in_fight: false,
+        }
+    }
 }
 
+/// Spawn the entity representing a tile, `None` for tiles that aren't drawn
 pub fn spawn(
     commands: &mut Commands,
     map_handles: &ResMut<map::Handles>,
     tile: Tile,
+    properties: Properties,
     coordinate: Vec3,
-) {
+) -> Option<Entity> {
+    let coordinate = coordinate + Vec3::Y * (properties.level as f32 * LEVEL_HEIGHT);
     match tile {
-        Tile::Ground => {
-            commands.spawn((
-                Mesh3d(map_handles.ground_mesh.clone()),
-                MeshMaterial3d(map_handles.ground_material.clone()),
-                Transform {
-                    translation: coordinate,
-                    rotation: Quat::from_rotation_y(-std::f32::consts::FRAC_PI_4),
-                    scale: Vec3::new(1.025, 1.025, 1.025),
-                },
-                RenderLayers::layer(0),
-                Tile::Ground,
-                map::MapComponent,
-                NotShadowCaster,
-            ));
+        Tile::Ground => Some(
+            commands
+                .spawn((
+                    Mesh3d(map_handles.ground_mesh.clone()),
+                    MeshMaterial3d(map_handles.ground_material.clone()),
+                    Transform {
+                        translation: coordinate,
+                        rotation: Quat::from_rotation_y(-std::f32::consts::FRAC_PI_4),
+                        scale: Vec3::new(1.025, 1.025, 1.025),
+                    },
+                    RenderLayers::layer(0),
+                    Tile::Ground,
+                    properties,
+                    map::MapComponent,
+                    NotShadowCaster,
+                ))
+                .id(),
+        ),
+        Tile::Block => Some(
+            commands
+                .spawn((
+                    Mesh3d(map_handles.block_mesh.clone()),
+                    MeshMaterial3d(map_handles.block_material.clone()),
+                    Transform {
+                        translation: coordinate + Vec3::new(0.0, 0.25, 0.0),
+                        rotation: Quat::from_rotation_y(-std::f32::consts::FRAC_PI_4),
+                        scale: Vec3::ONE,
+                    },
+                    RenderLayers::layer(0),
+                    Tile::Block,
+                    properties,
+                    map::MapComponent,
+                ))
+                .id(),
+        ),
+        Tile::Water => Some(
+            commands
+                .spawn((
+                    Mesh3d(map_handles.ground_mesh.clone()),
+                    MeshMaterial3d(map_handles.water_material.clone()),
+                    Transform {
+                        translation: coordinate,
+                        rotation: Quat::from_rotation_y(-std::f32::consts::FRAC_PI_4),
+                        scale: Vec3::new(1.025, 1.025, 1.025),
+                    },
+                    RenderLayers::layer(0),
+                    Tile::Water,
+                    properties,
+                    map::MapComponent,
+                    NotShadowCaster,
+                ))
+                .id(),
+        ),
+        Tile::Empty => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const CHUNK_X: i32 = map::chunk::SIZE_X as i32;
+    const CHUNK_Z: i32 = map::chunk::SIZE_Z as i32;
+    /// Tiles further away don't have an exact world position in `f32`
+    const WORLD_LIMIT: i32 = 4_000_000;
+
+    /// Any tile with an exact world position, far negative chunks included
+    fn world_tile() -> impl Strategy<Value = TileCoordinate> {
+        (-WORLD_LIMIT..WORLD_LIMIT, -WORLD_LIMIT..WORLD_LIMIT)
+            .prop_map(|(x, z)| TileCoordinate::new(x, z))
+    }
+
+    proptest! {
+        #[test]
+        fn world_round_trip(tile in world_tile()) {
+            let world = tile.to_world();
+            prop_assert_eq!(TileCoordinate::from_world(world), tile);
+            prop_assert_eq!(map::ChunkCoordinate::from_world(world), tile.to_chunk());
+        }
+
+        #[test]
+        fn absolute_round_trip(x in any::<i32>(), z in any::<i32>()) {
+            let tile = TileCoordinate::new(x, z);
+            let local = tile.to_local();
+            prop_assert!((0..CHUNK_X).contains(&local.x) && (0..CHUNK_Z).contains(&local.z));
+            prop_assert_eq!(local.to_absolute(tile.to_chunk()), tile);
+        }
+
+        #[test]
+        fn local_round_trip(
+            chunk_x in i32::MIN / CHUNK_X..i32::MAX / CHUNK_X,
+            chunk_z in i32::MIN / CHUNK_Z..i32::MAX / CHUNK_Z,
+            x in 0..CHUNK_X,
+            z in 0..CHUNK_Z,
+        ) {
+            let chunk = map::ChunkCoordinate::new(chunk_x, chunk_z);
+            let local = TileCoordinate::<LocalSpace>::new(x, z);
+            let absolute = local.to_absolute(chunk);
+            prop_assert_eq!(absolute.to_chunk(), chunk);
+            prop_assert_eq!(absolute.to_local(), local);
+        }
+
+        #[test]
+        fn diagonal_round_trip(tile in world_tile()) {
+            prop_assert_eq!(tile.to_diagonal().to_tile(), tile);
+        }
+
+        #[test]
+        fn neighbours_are_one_step_away(tile in world_tile()) {
+            for direction in Direction::ALL {
+                let neighbour = tile.neighbour(direction);
+                let expected = if direction.is_diagonal() { 2 } else { 1 };
+                prop_assert_eq!(tile.distance(neighbour), expected);
+                prop_assert_eq!(neighbour.distance(tile), expected);
+                prop_assert_eq!(tile.walk_distance(neighbour), 1);
+            }
+            prop_assert_eq!(tile.distance(tile), 0);
+        }
+    }
+
+    /// `distance` matches the amount of steps found by walking through edges only
+    #[test]
+    fn distance_matches_breadth_first_search() {
+        const RADIUS: u32 = 12;
+        let origin = TileCoordinate::new(-1, -1);
+        let mut steps = HashMap::from([((origin.x, origin.z), 0)]);
+        let mut queue = VecDeque::from([origin]);
+        while let Some(tile) = queue.pop_front() {
+            let step = steps[&(tile.x, tile.z)];
+            if step == RADIUS {
+                continue;
+            }
+            for neighbour in tile.neighbours() {
+                steps.entry((neighbour.x, neighbour.z)).or_insert_with(|| {
+                    queue.push_back(neighbour);
+                    step + 1
+                });
+            }
         }
-        Tile::Block => {
-            commands.spawn((
-                Mesh3d(map_handles.block_mesh.clone()),
-                MeshMaterial3d(map_handles.block_material.clone()),
-                Transform {
-                    translation: coordinate + Vec3::new(0.0, 0.25, 0.0),
-                    rotation: Quat::from_rotation_y(-std::f32::consts::FRAC_PI_4),
-                    scale: Vec3::ONE,
-                },
-                RenderLayers::layer(0),
-                Tile::Block,
-                map::MapComponent,
-            ));
+        for ((x, z), step) in steps {
+            assert_eq!(origin.distance(TileCoordinate::new(x, z)), step);
         }
-        Tile::Empty => {}
     }
 }
@@ -0,0 +1,408 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::map::{
+    self,
+    chunk::{loader::RawChunk, Chunk, Edge, ObjectKind, SIZE_X, SIZE_Z},
+    world::{self, WorldMap},
+    ChunkCoordinate,
+};
+
+/// # Folder the paths of the map data are relative to
+const ASSETS: &str = "assets";
+
+const EDGES: [Edge; 4] = [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right];
+
+/// Something wrong in a map file, `cell` is in the chunk local space
+struct Problem {
+    file: PathBuf,
+    cell: Option<(i32, i32)>,
+    message: String,
+}
+
+impl Problem {
+    fn new(file: &Path, cell: Option<(i32, i32)>, message: String) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            cell,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell {
+            Some((x, z)) => write!(
+                f,
+                "{} ({}, {}): {}",
+                self.file.display(),
+                x,
+                z,
+                self.message
+            ),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+/// Check every chunk file of `assets/map`, print the problems found
+/// and return the process exit code (non zero when something is wrong)
+/// Warnings, such as chunks still waiting for their background, are printed but don't fail the check
+pub fn run() -> i32 {
+    let mut problems = Vec::new();
+    let mut warnings = Vec::new();
+    let world_map = load_world_map(&mut problems);
+    let chunks = load_chunks(&mut problems);
+
+    let world_map_path = Path::new(ASSETS).join(world::PATH);
+    for info in &world_map.chunks {
+        if !chunks.contains_key(&(info.x, info.z)) {
+            problems.push(Problem::new(
+                &world_map_path,
+                None,
+                format!("chunk ({}, {}) has no tile data file", info.x, info.z),
+            ));
+        }
+    }
+    let spawn = world_map.spawn_chunk();
+    if !world_map.contains(spawn) {
+        problems.push(Problem::new(
+            &world_map_path,
+            None,
+            format!("spawn chunk ({}, {}) isn't listed", spawn.x, spawn.z),
+        ));
+    }
+    for ((x, z), (file, chunk)) in &chunks {
+        let coord = ChunkCoordinate::new(*x, *z);
+        check_background(coord, file, &world_map, &mut problems, &mut warnings);
+        check_exits(coord, file, chunk, &world_map, &chunks, &mut problems);
+        check_connected(coord, file, chunk, &mut problems);
+    }
+
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!(
+            "{} chunks checked, no problem found ({} warning(s))",
+            chunks.len(),
+            warnings.len()
+        );
+        0
+    } else {
+        eprintln!(
+            "{} problem(s) and {} warning(s) found while checking {} chunks",
+            problems.len(),
+            warnings.len(),
+            chunks.len()
+        );
+        1
+    }
+}
+
+fn load_world_map(problems: &mut Vec<Problem>) -> WorldMap {
+    let path = Path::new(ASSETS).join(world::PATH);
+    let parsed = std::fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| ron::de::from_str::<WorldMap>(&text).map_err(|error| error.to_string()));
+    match parsed {
+        Ok(world_map) => world_map,
+        Err(error) => {
+            problems.push(Problem::new(&path, None, error));
+            WorldMap::default()
+        }
+    }
+}
+
+/// Parse every `{x},{z}_TileData.ron`, reporting the grids that don't have the chunk sizes
+fn load_chunks(problems: &mut Vec<Problem>) -> BTreeMap<(i32, i32), (PathBuf, Chunk)> {
+    let mut chunks = BTreeMap::new();
+    let folder = Path::new(ASSETS).join("map");
+    let entries = match std::fs::read_dir(&folder) {
+        Ok(entries) => entries,
+        Err(error) => {
+            problems.push(Problem::new(&folder, None, error.to_string()));
+            return chunks;
+        }
+    };
+    let mut files = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with("_TileData.ron"))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+
+    for file in files {
+        let Some(coord) = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix("_TileData.ron"))
+            .and_then(|name| name.split_once(','))
+            .and_then(|(x, z)| Some((x.parse::<i32>().ok()?, z.parse::<i32>().ok()?)))
+        else {
+            problems.push(Problem::new(
+                &file,
+                None,
+                "file name isn't a chunk coordinate".to_string(),
+            ));
+            continue;
+        };
+        let raw = match std::fs::read_to_string(&file)
+            .map_err(|error| error.to_string())
+            .and_then(|text| {
+                ron::de::from_str::<RawChunk>(&text).map_err(|error| error.to_string())
+            }) {
+            Ok(raw) => raw,
+            Err(error) => {
+                problems.push(Problem::new(&file, None, error));
+                continue;
+            }
+        };
+        check_sizes(&file, &raw, problems);
+        chunks.insert(coord, (file, Chunk::from(raw)));
+    }
+    chunks
+}
+
+fn check_sizes(file: &Path, raw: &RawChunk, problems: &mut Vec<Problem>) {
+    let rows = raw.0.len();
+    if rows != SIZE_Z {
+        problems.push(Problem::new(
+            file,
+            Some((0, rows.min(SIZE_Z) as i32)),
+            format!("{} rows, expected {}", rows, SIZE_Z),
+        ));
+    }
+    for (z, row) in raw.0.iter().enumerate().take(SIZE_Z) {
+        if row.len() != SIZE_X {
+            problems.push(Problem::new(
+                file,
+                Some((row.len().min(SIZE_X) as i32, z as i32)),
+                format!("row has {} tiles, expected {}", row.len(), SIZE_X),
+            ));
+        }
+    }
+    let in_chunk =
+        |x: i32, z: i32| (0..SIZE_X as i32).contains(&x) && (0..SIZE_Z as i32).contains(&z);
+    for exit in &raw.1 {
+        if !Edge::of(exit.cell()).any(|edge| edge == exit.edge) {
+            problems.push(Problem::new(
+                file,
+                Some((exit.x, exit.z)),
+                format!("exit isn't on the {:?} edge", exit.edge),
+            ));
+        }
+    }
+    for cell in &raw.2 {
+        if !in_chunk(cell.x, cell.z) {
+            problems.push(Problem::new(
+                file,
+                Some((cell.x, cell.z)),
+                "cell properties outside of the chunk".to_string(),
+            ));
+        }
+    }
+    for object in &raw.4 {
+        if !in_chunk(object.x, object.z) {
+            problems.push(Problem::new(
+                file,
+                Some((object.x, object.z)),
+                "object outside of the chunk".to_string(),
+            ));
+        }
+        if let ObjectKind::Lever { targets } = &object.kind {
+            for (x, z) in targets {
+                if !raw.4.iter().any(|target| target.x == *x && target.z == *z) {
+                    problems.push(Problem::new(
+                        file,
+                        Some((object.x, object.z)),
+                        format!("lever linked to ({}, {}) where there is no object", x, z),
+                    ));
+                }
+            }
+        }
+    }
+    for npc in &raw.5 {
+        if !in_chunk(npc.x, npc.z) {
+            problems.push(Problem::new(
+                file,
+                Some((npc.x, npc.z)),
+                format!("{} is outside of the chunk", npc.name),
+            ));
+        }
+        if !Path::new(ASSETS).join(&npc.dialogue).is_file() {
+            problems.push(Problem::new(
+                file,
+                Some((npc.x, npc.z)),
+                format!("dialogue {} of {} doesn't exist", npc.dialogue, npc.name),
+            ));
+        }
+    }
+    for node in &raw.6 {
+        if !in_chunk(node.x, node.z) {
+            problems.push(Problem::new(
+                file,
+                Some((node.x, node.z)),
+                format!("{:?} node outside of the chunk", node.kind),
+            ));
+        }
+        if raw.4.iter().any(|object| object.cell() == node.cell()) {
+            problems.push(Problem::new(
+                file,
+                Some((node.x, node.z)),
+                format!("{:?} node on the same cell as an object", node.kind),
+            ));
+        }
+    }
+}
+
+/// A missing background is only a warning, the chunk is still playable on the plain grid
+fn check_background(
+    coord: ChunkCoordinate,
+    file: &Path,
+    world_map: &WorldMap,
+    problems: &mut Vec<Problem>,
+    warnings: &mut Vec<Problem>,
+) {
+    let Some(info) = world_map.get(coord) else {
+        problems.push(Problem::new(
+            file,
+            None,
+            "chunk isn't listed in the world map".to_string(),
+        ));
+        return;
+    };
+    match &info.background {
+        None => warnings.push(Problem::new(
+            file,
+            None,
+            "chunk has no background".to_string(),
+        )),
+        Some(background) if !Path::new(ASSETS).join(background).is_file() => {
+            problems.push(Problem::new(
+                file,
+                None,
+                format!("background {} doesn't exist", background),
+            ));
+        }
+        Some(_) => {}
+    }
+}
+
+/// Every exit must lead to a walkable cell of the chunk linked by the world map on its edge
+fn check_exits(
+    coord: ChunkCoordinate,
+    file: &Path,
+    chunk: &Chunk,
+    world_map: &WorldMap,
+    chunks: &BTreeMap<(i32, i32), (PathBuf, Chunk)>,
+    problems: &mut Vec<Problem>,
+) {
+    let Some(info) = world_map.get(coord) else {
+        return;
+    };
+    for edge in EDGES {
+        let Some(neighbour) = info.neighbours.get(edge) else {
+            for exit in chunk.exits.iter().filter(|exit| exit.edge == edge) {
+                problems.push(Problem::new(
+                    file,
+                    Some((exit.x, exit.z)),
+                    format!("exit leads nowhere, no neighbour on the {:?} edge", edge),
+                ));
+            }
+            continue;
+        };
+        let (x, z) = edge.direction();
+        if neighbour != coord + ChunkCoordinate::new(x, z) {
+            problems.push(Problem::new(
+                file,
+                None,
+                format!(
+                    "{:?} neighbour ({}, {}) isn't next to the chunk",
+                    edge, neighbour.x, neighbour.z
+                ),
+            ));
+            continue;
+        }
+        let Some((_, next)) = chunks.get(&(neighbour.x, neighbour.z)) else {
+            continue;
+        };
+        let exits = chunk.exits_on(edge);
+        if exits.is_empty() {
+            problems.push(Problem::new(
+                file,
+                None,
+                format!(
+                    "no exit toward the {:?} neighbour ({}, {})",
+                    edge, neighbour.x, neighbour.z
+                ),
+            ));
+        }
+        for exit in exits {
+            let across = exit.cell().to_absolute(coord) + map::TileCoordinate::new(x, z);
+            let across = across.to_local();
+            if !next.is_walkable(across, false) {
+                problems.push(Problem::new(
+                    file,
+                    Some((exit.x, exit.z)),
+                    format!(
+                        "exit doesn't line up, cell ({}, {}) of chunk ({}, {}) isn't walkable",
+                        across.x, across.z, neighbour.x, neighbour.z
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Every walkable cell must be reachable from the others
+fn check_connected(
+    coord: ChunkCoordinate,
+    file: &Path,
+    chunk: &Chunk,
+    problems: &mut Vec<Problem>,
+) {
+    let mut visited = HashSet::new();
+    let mut areas = 0;
+    for z in 0..SIZE_Z as i32 {
+        for x in 0..SIZE_X as i32 {
+            let start = map::TileCoordinate::new(x, z);
+            if !chunk.is_walkable(start, false) || !visited.insert((x, z)) {
+                continue;
+            }
+            areas += 1;
+            let mut size = 0;
+            let mut queue = VecDeque::from([start.to_absolute(coord)]);
+            while let Some(cell) = queue.pop_front() {
+                size += 1;
+                for next in cell.adjacent() {
+                    if next.to_chunk() != coord {
+                        continue;
+                    }
+                    let local = next.to_local();
+                    if chunk.is_walkable(local, false) && visited.insert((local.x, local.z)) {
+                        queue.push_back(next);
+                    }
+                }
+            }
+            // The first area found is the reference, report the ones cut from it
+            if areas > 1 {
+                problems.push(Problem::new(
+                    file,
+                    Some((x, z)),
+                    format!("walkable area of {} cells cut from the rest", size),
+                ));
+            }
+        }
+    }
+}
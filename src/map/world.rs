@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::map::{
+    chunk::{Edge, SIZE_X, SIZE_Z},
+    tile::LocalSpace,
+    Chunk, ChunkCoordinate, TileCoordinate,
+};
+
+/// # Path of the world map, relative to the assets folder
+pub const PATH: &str = "world_map.ron";
+
+/// Chunks reachable by walking off each edge of a chunk
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Neighbours {
+    #[serde(default)]
+    pub top: Option<(i32, i32)>,
+    #[serde(default)]
+    pub bottom: Option<(i32, i32)>,
+    #[serde(default)]
+    pub left: Option<(i32, i32)>,
+    #[serde(default)]
+    pub right: Option<(i32, i32)>,
+}
+
+impl Neighbours {
+    /// Chunk reached by walking off `edge`
+    pub fn get(&self, edge: Edge) -> Option<ChunkCoordinate> {
+        match edge {
+            Edge::Top => self.top,
+            Edge::Bottom => self.bottom,
+            Edge::Left => self.left,
+            Edge::Right => self.right,
+        }
+        .map(|(x, z)| ChunkCoordinate::new(x, z))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ChunkCoordinate> {
+        [self.top, self.bottom, self.left, self.right]
+            .into_iter()
+            .flatten()
+            .map(|(x, z)| ChunkCoordinate::new(x, z))
+    }
+}
+
+/// Everything the world map knows about a single chunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub x: i32,
+    pub z: i32,
+    pub region: String,
+    #[serde(default)]
+    pub subarea: Option<String>,
+    /// Background image, relative to the assets folder
+    #[serde(default)]
+    pub background: Option<String>,
+    #[serde(default)]
+    pub neighbours: Neighbours,
+}
+
+impl ChunkInfo {
+    pub fn coordinate(&self) -> ChunkCoordinate {
+        ChunkCoordinate::new(self.x, self.z)
+    }
+
+    /// Name displayed to the player, "Region - Subarea" when the chunk has a subarea
+    pub fn area_name(&self) -> String {
+        match &self.subarea {
+            Some(subarea) => format!("{} - {}", self.region, subarea),
+            None => self.region.clone(),
+        }
+    }
+}
+
+/// List of every chunk of the world, replace the hard-coded bounds
+#[derive(Asset, TypePath, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorldMap {
+    /// Chunk players are sent to when their saved position isn't on the map
+    #[serde(default)]
+    pub spawn: (i32, i32),
+    pub chunks: Vec<ChunkInfo>,
+}
+
+impl WorldMap {
+    pub fn spawn_chunk(&self) -> ChunkCoordinate {
+        ChunkCoordinate::new(self.spawn.0, self.spawn.1)
+    }
+
+    pub fn get(&self, coord: ChunkCoordinate) -> Option<&ChunkInfo> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.x == coord.x && chunk.z == coord.z)
+    }
+
+    pub fn contains(&self, coord: ChunkCoordinate) -> bool {
+        self.get(coord).is_some()
+    }
+
+    /// Smallest and biggest chunk coordinates, `None` if the map is empty
+    pub fn bounds(&self) -> Option<(ChunkCoordinate, ChunkCoordinate)> {
+        let first = self.chunks.first()?.coordinate();
+        Some(
+            self.chunks
+                .iter()
+                .fold((first, first), |(min, max), chunk| {
+                    (
+                        ChunkCoordinate::new(min.x.min(chunk.x), min.z.min(chunk.z)),
+                        ChunkCoordinate::new(max.x.max(chunk.x), max.z.max(chunk.z)),
+                    )
+                }),
+        )
+    }
+}
+
+/// Walkable cell closest to the centre of the chunk, the centre itself if the chunk didn't load
+pub fn spawn_cell(coord: ChunkCoordinate, chunk: Option<&Chunk>) -> TileCoordinate {
+    let centre = TileCoordinate::from_world(coord.world_center());
+    chunk
+        .and_then(|chunk| {
+            (0..SIZE_Z as i32)
+                .flat_map(|z| {
+                    (0..SIZE_X as i32).map(move |x| TileCoordinate::<LocalSpace>::new(x, z))
+                })
+                .filter(|cell| chunk.is_walkable(*cell, false))
+                .map(|cell| cell.to_absolute(coord))
+                .min_by_key(|cell| cell.walk_distance(centre))
+        })
+        .unwrap_or(centre)
+}
+
+/// Handle of the world map, loaded on startup
+#[derive(Resource)]
+pub struct CurrentWorldMap(pub Handle<WorldMap>);
+
+pub(super) fn load(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(CurrentWorldMap(asset_server.load(PATH)));
+}
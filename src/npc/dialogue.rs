@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::{
+    quest::{self, QuestState},
+    save, ui, ProcessArgs,
+};
+
+pub fn init(app: &mut App) {
+    app.init_asset::<Dialogue>()
+        .init_asset_loader::<Loader>()
+        .add_observer(on_open);
+}
+
+/// Requirement for a reply to be offered
+#[derive(Debug, Clone, Deserialize)]
+pub enum Condition {
+    /// The character is at least this level
+    Level(u32),
+    /// The character owns at least this amount of an item
+    Item(String, u32),
+    /// The quest is in this state
+    Quest(String, QuestState),
+}
+
+impl Condition {
+    pub fn is_met(&self, data: &save::Data) -> bool {
+        match self {
+            Condition::Level(level) => data.player_info.level >= *level,
+            Condition::Item(item, amount) => data.inventory.count(item) >= *amount,
+            Condition::Quest(id, state) => {
+                data.quests
+                    .get(id)
+                    .map(quest::Progress::state)
+                    .unwrap_or_default()
+                    == *state
+            }
+        }
+    }
+}
+
+/// Effect of picking a reply
+#[derive(Debug, Clone, Deserialize)]
+pub enum Action {
+    StartQuest(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Reply {
+    pub text: String,
+    /// Every condition must be met for the reply to be offered
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    /// Line said after this reply, the dialogue end without one
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// Something the NPC say, and what can be answered
+#[derive(Debug, Clone, Deserialize)]
+pub struct Line {
+    pub text: String,
+    #[serde(default)]
+    pub replies: Vec<Reply>,
+}
+
+/// Conversation tree of a NPC
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct Dialogue {
+    /// Line said when the dialogue is opened
+    pub start: String,
+    pub lines: HashMap<String, Line>,
+}
+
+#[derive(Default)]
+pub struct Loader;
+
+impl AssetLoader for Loader {
+    type Asset = Dialogue;
+    type Settings = ();
+    type Error = ron::de::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let dialogue = ron::de::from_bytes::<Dialogue>(&bytes)?;
+        Ok(dialogue)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+/// Show a line of a NPC dialogue, the start line when `line` is `None`
+#[derive(Debug, Event)]
+pub struct OpenEvent {
+    pub npc: String,
+    pub dialogue: Handle<Dialogue>,
+    pub line: Option<String>,
+}
+
+fn on_open(
+    trigger: Trigger<OpenEvent>,
+    mut commands: Commands,
+    dialogue_assets: Res<Assets<Dialogue>>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    let event = trigger.event();
+    let Some(dialogue) = dialogue_assets.get(event.dialogue.id()) else {
+        warn!("Dialogue of {} isn't loaded", event.npc);
+        return;
+    };
+    let Some(save_data) = save_data_assets.get(save_handle.0.id()) else {
+        return;
+    };
+    if event.line.is_none() {
+        commands.trigger(quest::ProgressEvent::Talked(event.npc.clone()));
+    }
+    let id = event.line.clone().unwrap_or_else(|| dialogue.start.clone());
+    let Some(line) = dialogue.lines.get(&id) else {
+        warn!("Dialogue of {} has no line {:?}", event.npc, id);
+        return;
+    };
+
+    let replies = line
+        .replies
+        .iter()
+        .filter(|reply| {
+            reply
+                .conditions
+                .iter()
+                .all(|condition| condition.is_met(save_data))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut texts = replies
+        .iter()
+        .map(|reply| reply.text.clone())
+        .collect::<Vec<_>>();
+    // Always leave a way out
+    if texts.is_empty() {
+        texts.push("Goodbye".to_string());
+    }
+    let npc = event.npc.clone();
+    let handle = event.dialogue.clone();
+    ui::popup::spawn_with_replies(&mut commands, format!("{}: {}", npc, line.text), texts).observe(
+        move |trigger: Trigger<ui::popup::ReplyEvent>,
+              mut commands: Commands,
+              mut save_data_assets: ResMut<Assets<save::Data>>,
+              save_handle: Res<save::ResHandle>,
+              args: Res<ProcessArgs>| {
+            let Some(reply) = replies.get(trigger.0) else {
+                return;
+            };
+            if !reply.actions.is_empty() {
+                let Some(save_data) = save_data_assets.get_mut(save_handle.0.id()) else {
+                    return;
+                };
+                for action in reply.actions.iter() {
+                    match action {
+                        Action::StartQuest(id) => {
+                            if !save_data.quests.contains_key(id) {
+                                info!("Quest {} started", id);
+                                save_data
+                                    .quests
+                                    .insert(id.clone(), quest::Progress::default());
+                            }
+                        }
+                    }
+                }
+                save::persist(save_data, &args);
+            }
+            if let Some(next) = &reply.next {
+                commands.trigger(OpenEvent {
+                    npc: npc.clone(),
+                    dialogue: handle.clone(),
+                    line: Some(next.clone()),
+                });
+            }
+        },
+    );
+}
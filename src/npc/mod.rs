@@ -0,0 +1,154 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_mod_raycast::prelude::{Raycast, RaycastMesh};
+
+use crate::{
+    map::{
+        self,
+        streaming::{ChunkEntities, Streamer},
+        Chunk,
+    },
+    ui, GameMode,
+};
+
+pub mod dialogue;
+
+/// # Width and height (in world units) of a NPC figure
+const SIZES: Vec2 = Vec2::new(0.8, 1.6);
+
+pub fn init(app: &mut App) {
+    dialogue::init(app);
+    app.init_resource::<NpcEntities>()
+        .add_systems(
+            Update,
+            (sync_npcs, click_npc.run_if(not(ui::chat::is_typing)))
+                .run_if(in_state(GameMode::Exploration)),
+        )
+        .add_systems(OnExit(GameMode::Exploration), despawn_all);
+}
+
+/// Non-player character spawned from the chunk data
+#[derive(Component, Debug)]
+pub struct Npc {
+    pub name: String,
+    pub dialogue: Handle<dialogue::Dialogue>,
+}
+
+/// NPC entities spawned for every loaded chunk
+#[derive(Resource, Default)]
+struct NpcEntities(ChunkEntities);
+
+/// Spawn the NPCs of the chunks being loaded or edited, and despawn the released ones
+fn sync_npcs(
+    mut commands: Commands,
+    mut npc_entities: ResMut<NpcEntities>,
+    mut chunk_events: EventReader<AssetEvent<Chunk>>,
+    streamer: Res<Streamer>,
+    chunk_assets: Res<Assets<Chunk>>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    npc_entities.0.sync(
+        &mut commands,
+        &mut chunk_events,
+        &streamer,
+        &chunk_assets,
+        |commands, coord, chunk| {
+            let mut entities = Vec::new();
+            for npc in chunk.npcs.iter() {
+                let material = match &npc.sprite {
+                    Some(sprite) => StandardMaterial {
+                        base_color_texture: Some(asset_server.load(sprite)),
+                        alpha_mode: AlphaMode::Blend,
+                        unlit: true,
+                        double_sided: true,
+                        cull_mode: None,
+                        ..default()
+                    },
+                    None => Color::srgb(0.9, 0.8, 0.3).into(),
+                };
+                let cell = npc.cell().to_absolute(coord);
+                let entity = commands
+                    .spawn((
+                        Mesh3d(meshes.add(Rectangle::from_size(SIZES))),
+                        MeshMaterial3d(materials.add(material)),
+                        Transform::from_translation(cell.to_world() + Vec3::Y * (SIZES.y / 2.0)),
+                        Npc {
+                            name: npc.name.clone(),
+                            dialogue: asset_server.load(&npc.dialogue),
+                        },
+                        RaycastMesh::<()>::default(),
+                        map::MapComponent,
+                    ))
+                    .id();
+                entities.push(entity);
+            }
+            entities
+        },
+    );
+}
+
+/// Talk to the NPC under the cursor, or open its context menu with a right click
+fn click_npc(
+    mut commands: Commands,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    mut raycast: Raycast,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    q_npc: Query<(&Npc, &Transform)>,
+) {
+    let left = mouse_button_input.just_pressed(MouseButton::Left);
+    let right = mouse_button_input.just_pressed(MouseButton::Right);
+    if !left && !right {
+        return;
+    }
+    let Ok((camera, camera_transform)) = q_camera.get_single() else {
+        return;
+    };
+    let Some(ray) = q_window
+        .single()
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+    else {
+        return;
+    };
+    let Some((npc, transform)) = raycast
+        .cast_ray(ray, &default())
+        .iter()
+        .find_map(|(entity, _)| q_npc.get(*entity).ok())
+    else {
+        return;
+    };
+    let open = dialogue::OpenEvent {
+        npc: npc.name.clone(),
+        dialogue: npc.dialogue.clone(),
+        line: None,
+    };
+    if left {
+        commands.trigger(open);
+        return;
+    }
+    let Ok(viewport_position) = camera.world_to_viewport(camera_transform, transform.translation)
+    else {
+        return;
+    };
+    let name = npc.name.clone();
+    let dialogue = npc.dialogue.clone();
+    ui::context_menu::spawn(&mut commands, viewport_position, |commands, entity| {
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn(Text::new("Talk")).observe(
+                move |_trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+                    commands.trigger(dialogue::OpenEvent {
+                        npc: name.clone(),
+                        dialogue: dialogue.clone(),
+                        line: None,
+                    });
+                },
+            );
+        });
+    });
+}
+
+fn despawn_all(mut commands: Commands, mut npc_entities: ResMut<NpcEntities>) {
+    npc_entities.0.despawn_all(&mut commands);
+}
@@ -0,0 +1,604 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_matchbox::prelude::PeerId;
+
+use crate::{
+    combat, exploration, map,
+    player::{self, MainPlayer, Player},
+    socket, ui, GameMode,
+};
+
+/// # Maximum amount of players in a group (leader included)
+pub const MAX_MEMBERS: usize = 8;
+/// # Interval (in seconds) between two status updates sent to the group
+const STATUS_INTERVAL: f32 = 1.0;
+/// # Distance (in cells) from the leader at which a follower start walking
+const FOLLOW_DISTANCE: u32 = 2;
+
+pub fn init(app: &mut App) {
+    app.init_resource::<MemberStatuses>()
+        .init_resource::<PendingInvites>()
+        .insert_resource(StatusTimer(Timer::from_seconds(
+            STATUS_INTERVAL,
+            TimerMode::Repeating,
+        )))
+        .add_systems(
+            Update,
+            (
+                send_status.run_if(resource_exists::<Party>),
+                follow_leader.run_if(resource_exists::<Following>),
+                update_panel,
+            )
+                .run_if(socket::is_connected),
+        )
+        .add_systems(
+            OnEnter(GameMode::Combat),
+            bring_group_into_fight.run_if(resource_equals(combat::Kind::Monsters)),
+        )
+        .add_observer(on_invite)
+        .add_observer(on_invite_received)
+        .add_observer(on_invite_answered)
+        .add_observer(on_party_updated)
+        .add_observer(on_member_left)
+        .add_observer(on_member_status)
+        .add_observer(on_join_fight)
+        .add_observer(on_peer_deconection);
+}
+
+/// The group the main player is part of, absent when playing solo
+#[derive(Resource, Debug, Clone)]
+pub struct Party {
+    pub leader: PeerId,
+    /// Every member, leader included, in joining order
+    pub members: Vec<PeerId>,
+}
+
+impl Party {
+    pub fn is_leader(&self, peer_id: PeerId) -> bool {
+        self.leader == peer_id
+    }
+
+    pub fn contains(&self, peer_id: PeerId) -> bool {
+        self.members.contains(&peer_id)
+    }
+
+    /// Everyone but `peer_id`
+    pub fn others(&self, peer_id: PeerId) -> impl Iterator<Item = PeerId> + '_ {
+        self.members
+            .iter()
+            .copied()
+            .filter(move |id| *id != peer_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemberStatus {
+    pub health: u32,
+    pub max_health: u32,
+    pub chunk: map::ChunkCoordinate,
+}
+
+/// Last status received from every group member
+#[derive(Resource, Default)]
+pub struct MemberStatuses(pub HashMap<PeerId, MemberStatus>);
+
+#[derive(Resource)]
+struct StatusTimer(Timer);
+
+/// Peers we invited and that did not answer yet
+#[derive(Resource, Default)]
+struct PendingInvites(HashSet<PeerId>);
+
+/// Leader whose invite we accepted, the only one who can bring us into a group
+#[derive(Resource)]
+struct AcceptedInvite(PeerId);
+
+/// Present while the main player walk behind the group leader
+#[derive(Resource)]
+pub struct Following;
+
+#[derive(Component)]
+struct PartyPanel;
+
+/// Ask a peer to join our group
+#[derive(Debug, Event)]
+pub struct InviteEvent(pub PeerId);
+
+fn on_invite(
+    trigger: Trigger<InviteEvent>,
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    my_id: Res<socket::MyId>,
+    mut pending_invites: ResMut<PendingInvites>,
+) {
+    if let Some(party) = party {
+        if !party.is_leader(my_id.0) {
+            ui::popup::spawn(&mut commands, "Only the leader can invite".to_string());
+            return;
+        }
+        if party.members.len() >= MAX_MEMBERS {
+            ui::popup::spawn(&mut commands, "Your group is full".to_string());
+            return;
+        }
+        if party.contains(trigger.0) {
+            return;
+        }
+    }
+    info!("Inviting {} to the group", trigger.0);
+    pending_invites.0.insert(trigger.0);
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::PartyInvite,
+        trigger.0,
+    ));
+}
+
+fn on_invite_received(
+    trigger: Trigger<socket::PartyInviteReceivedEvent>,
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    q_player: Query<(&Player, &socket::Id)>,
+) {
+    let sender = trigger.0;
+    if party.is_some() {
+        info!("Declining group invite from {}: already in a group", sender);
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::PartyInviteAnswer(false),
+            sender,
+        ));
+        return;
+    }
+    let Some((player, _)) = q_player.iter().find(|(_, id)| id.0 == sender) else {
+        return;
+    };
+    ui::popup::spawn_with_choices(
+        &mut commands,
+        format!("{} invites you to their group", player.name),
+        "Join",
+        "Decline",
+    )
+    .observe(
+        move |trigger: Trigger<ui::popup::ButtonEvent>, mut commands: Commands| {
+            let accepted = matches!(trigger.event(), ui::popup::ButtonEvent::Primary);
+            commands.trigger(socket::SendMessageEvent::ToPeer(
+                socket::Message::PartyInviteAnswer(accepted),
+                sender,
+            ));
+            if accepted {
+                commands.insert_resource(AcceptedInvite(sender));
+            }
+        },
+    );
+}
+
+fn on_invite_answered(
+    trigger: Trigger<socket::PartyInviteAnsweredEvent>,
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    my_id: Res<socket::MyId>,
+    mut pending_invites: ResMut<PendingInvites>,
+) {
+    // Ignore answers to invites we never sent
+    if !pending_invites.0.remove(&trigger.peer_id) {
+        warn!("Unexpected group invite answer from {}", trigger.peer_id);
+        return;
+    }
+    if !trigger.accepted {
+        info!("{} declined the group invite", trigger.peer_id);
+        return;
+    }
+    let mut party = match party {
+        Some(party) if party.is_leader(my_id.0) => party.clone(),
+        Some(_) => return,
+        None => Party {
+            leader: my_id.0,
+            members: vec![my_id.0],
+        },
+    };
+    if party.members.len() >= MAX_MEMBERS || party.contains(trigger.peer_id) {
+        return;
+    }
+    info!("{} joined the group", trigger.peer_id);
+    party.members.push(trigger.peer_id);
+    broadcast_party(&mut commands, &party, my_id.0);
+    commands.insert_resource(party);
+}
+
+/// Send the group composition to every member, only called by the leader
+fn broadcast_party(commands: &mut Commands, party: &Party, my_id: PeerId) {
+    for member in party.others(my_id) {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::PartyUpdate {
+                leader: party.leader,
+                members: party.members.clone(),
+            },
+            member,
+        ));
+    }
+}
+
+fn on_party_updated(
+    trigger: Trigger<socket::PartyUpdatedEvent>,
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    accepted_invite: Option<Res<AcceptedInvite>>,
+    my_id: Res<socket::MyId>,
+) {
+    // Only the current leader can change the group, or the one we accepted to join
+    let allowed = match party.as_ref() {
+        Some(party) => party.is_leader(trigger.peer_id),
+        None => accepted_invite
+            .is_some_and(|invite| invite.0 == trigger.peer_id && trigger.leader == trigger.peer_id),
+    };
+    if !allowed {
+        warn!(
+            "Ignoring group update from {}: not the leader",
+            trigger.peer_id
+        );
+        return;
+    }
+    commands.remove_resource::<AcceptedInvite>();
+    if !trigger.members.contains(&my_id.0) {
+        info!("You left the group");
+        leave_locally(&mut commands);
+        return;
+    }
+    commands.insert_resource(Party {
+        leader: trigger.leader,
+        members: trigger.members.clone(),
+    });
+}
+
+fn leave_locally(commands: &mut Commands) {
+    commands.remove_resource::<Party>();
+    commands.remove_resource::<Following>();
+    commands.insert_resource(MemberStatuses::default());
+}
+
+/// Leave the group, handing the lead over to the oldest member if needed
+pub fn leave(commands: &mut Commands, party: &Party, my_id: PeerId) {
+    if party.is_leader(my_id) {
+        let remaining = party.others(my_id).collect::<Vec<_>>();
+        if let Some(new_leader) = remaining.first() {
+            broadcast_party(
+                commands,
+                &Party {
+                    leader: *new_leader,
+                    members: remaining.clone(),
+                },
+                my_id,
+            );
+        }
+    } else {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::PartyLeave,
+            party.leader,
+        ));
+    }
+    leave_locally(commands);
+}
+
+fn remove_member(commands: &mut Commands, party: &Party, peer_id: PeerId, my_id: PeerId) {
+    let members = party
+        .members
+        .iter()
+        .copied()
+        .filter(|id| *id != peer_id)
+        .collect::<Vec<_>>();
+    if members.len() < 2 {
+        info!("The group has been disbanded");
+        leave_locally(commands);
+        return;
+    }
+    // Everyone elect the same leader, no need to wait for a message
+    let leader = if party.is_leader(peer_id) {
+        members[0]
+    } else {
+        party.leader
+    };
+    let party = Party { leader, members };
+    if party.is_leader(my_id) {
+        broadcast_party(commands, &party, my_id);
+    }
+    commands.insert_resource(party);
+}
+
+fn on_member_left(
+    trigger: Trigger<socket::PartyLeftEvent>,
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    my_id: Res<socket::MyId>,
+) {
+    let Some(party) = party else {
+        return;
+    };
+    if !party.is_leader(my_id.0) || !party.contains(trigger.0) {
+        return;
+    }
+    remove_member(&mut commands, &party, trigger.0, my_id.0);
+}
+
+fn on_peer_deconection(
+    trigger: Trigger<socket::PeerDeconectionEvent>,
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    my_id: Option<Res<socket::MyId>>,
+    mut statuses: ResMut<MemberStatuses>,
+) {
+    statuses.0.remove(&trigger.0);
+    let (Some(party), Some(my_id)) = (party, my_id) else {
+        return;
+    };
+    if party.contains(trigger.0) {
+        remove_member(&mut commands, &party, trigger.0, my_id.0);
+    }
+}
+
+fn send_status(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut timer: ResMut<StatusTimer>,
+    party: Res<Party>,
+    my_id: Res<socket::MyId>,
+    player_info: Option<Res<player::Info>>,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+    mut statuses: ResMut<MemberStatuses>,
+) {
+    timer.0.tick(time.delta());
+    if !timer.0.just_finished() {
+        return;
+    }
+    let (Some(player_info), Ok(transform)) = (player_info, q_main_player.get_single()) else {
+        return;
+    };
+    let chunk = map::ChunkCoordinate::from_world(transform.translation);
+    statuses.0.insert(
+        my_id.0,
+        MemberStatus {
+            health: player_info.health(),
+            max_health: player_info.max_health,
+            chunk,
+        },
+    );
+    for member in party.others(my_id.0) {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::PartyMemberStatus {
+                health: player_info.health(),
+                max_health: player_info.max_health,
+                chunk_x: chunk.x,
+                chunk_z: chunk.z,
+            },
+            member,
+        ));
+    }
+}
+
+fn on_member_status(
+    trigger: Trigger<socket::PartyMemberStatusEvent>,
+    party: Option<Res<Party>>,
+    mut statuses: ResMut<MemberStatuses>,
+) {
+    if !party.is_some_and(|party| party.contains(trigger.peer_id)) {
+        return;
+    }
+    statuses.0.insert(
+        trigger.peer_id,
+        MemberStatus {
+            health: trigger.health.min(trigger.max_health),
+            max_health: trigger.max_health,
+            chunk: map::ChunkCoordinate::new(trigger.chunk_x, trigger.chunk_z),
+        },
+    );
+}
+
+/// Walk toward the leader whenever we are too far behind
+fn follow_leader(
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    game_mode: Res<State<GameMode>>,
+    q_main_player: Query<(Entity, &Transform, Option<&player::Walk>), With<MainPlayer>>,
+    q_players: Query<(&Transform, &socket::Id), Without<MainPlayer>>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    objects: Res<map::objects::ObjectStates>,
+) {
+    let Some(party) = party else {
+        commands.remove_resource::<Following>();
+        return;
+    };
+    if *game_mode.get() != GameMode::Exploration {
+        return;
+    }
+    let Some((leader_transform, _)) = q_players.iter().find(|(_, id)| id.0 == party.leader) else {
+        return;
+    };
+    let Ok((entity, transform, walk)) = q_main_player.get_single() else {
+        return;
+    };
+    let leader_cell = map::TileCoordinate::from_world(leader_transform.translation);
+    let cell = map::TileCoordinate::from_world(transform.translation);
+    if leader_cell.walk_distance(cell) <= FOLLOW_DISTANCE
+        || walk.is_some_and(|walk| walk.destination() == leader_cell)
+    {
+        return;
+    }
+    // Around walls and closed doors, nothing is done when the leader can't be reached
+    let Some(walk) = exploration::path_finding::find(cell, leader_cell, |cell| {
+        map::movement::loaded_tile(cell, &asset_server, &chunk_assets, &objects)
+    })
+    .and_then(player::Walk::new) else {
+        return;
+    };
+    commands.entity(entity).insert(walk);
+}
+
+/// When the leader start a fight against monsters, the whole group follow as one team
+fn bring_group_into_fight(
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    my_id: Option<Res<socket::MyId>>,
+    fighters: Option<ResMut<combat::Fighters>>,
+    spectator: Option<Res<combat::Spectator>>,
+) {
+    let (Some(party), Some(my_id)) = (party, my_id) else {
+        return;
+    };
+    if spectator.is_some() {
+        return;
+    }
+    commands.insert_resource(combat::Allies(party.members.clone()));
+    if let Some(mut fighters) = fighters {
+        for member in party.members.iter() {
+            if !fighters.0.contains(member) {
+                fighters.0.push(*member);
+            }
+        }
+    }
+    if !party.is_leader(my_id.0) {
+        return;
+    }
+    info!("Bringing the group into the fight");
+    for member in party.others(my_id.0) {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::PartyJoinFight,
+            member,
+        ));
+    }
+}
+
+fn on_join_fight(
+    trigger: Trigger<socket::PartyJoinFightEvent>,
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    game_mode: Res<State<GameMode>>,
+    mut next_game_mode: ResMut<NextState<GameMode>>,
+) {
+    let Some(party) = party else {
+        return;
+    };
+    if !party.is_leader(trigger.0) || *game_mode.get() != GameMode::Exploration {
+        return;
+    }
+    info!("Joining the group leader fight");
+    commands.remove_resource::<Following>();
+    commands.insert_resource(combat::Owner(party.leader));
+    commands.insert_resource(combat::Kind::Monsters);
+    next_game_mode.set(GameMode::Combat);
+}
+
+fn update_panel(
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    statuses: Res<MemberStatuses>,
+    following: Option<Res<Following>>,
+    my_id: Option<Res<socket::MyId>>,
+    q_panel: Query<Entity, With<PartyPanel>>,
+    q_players: Query<(&Player, Option<&socket::Id>)>,
+    mut was_following: Local<bool>,
+) {
+    let party_changed = party.as_ref().is_some_and(|party| party.is_changed());
+    let following_changed = following.is_some() != *was_following;
+    *was_following = following.is_some();
+    let disbanded = party.is_none() && !q_panel.is_empty();
+    if !party_changed && !statuses.is_changed() && !following_changed && !disbanded {
+        return;
+    }
+    for entity in q_panel.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let (Some(party), Some(my_id)) = (party, my_id) else {
+        return;
+    };
+    let panel = commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                top: Val::Percent(20.0),
+                left: Val::Px(10.0),
+                width: Val::Px(140.0),
+                padding: UiRect::all(Val::Px(5.0)),
+                ..default()
+            },
+            BorderRadius::all(Val::Px(5.0)),
+            BackgroundColor(Color::hsl(246.0, 0.21, 0.29)),
+            PartyPanel,
+        ))
+        .id();
+    for member in party.members.iter() {
+        let name = q_players
+            .iter()
+            .find(|(_, id)| match id {
+                Some(id) => id.0 == *member,
+                None => *member == my_id.0,
+            })
+            .map(|(player, _)| player.name.clone())
+            .unwrap_or_else(|| member.to_string());
+        let status = statuses
+            .0
+            .get(member)
+            .map(|status| {
+                format!(
+                    "{}/{} HP - {}, {}",
+                    status.health, status.max_health, status.chunk.x, status.chunk.z
+                )
+            })
+            .unwrap_or_default();
+        let crown = if party.is_leader(*member) { "* " } else { "" };
+        commands
+            .spawn((
+                Text::new(format!("{}{}\n{}", crown, name, status)),
+                TextColor(Color::hsl(242.0, 0.15, 0.57)),
+                TextFont {
+                    font_size: 10.0,
+                    ..Default::default()
+                },
+            ))
+            .set_parent(panel);
+    }
+    if !party.is_leader(my_id.0) {
+        let label = if following.is_some() {
+            "Stop following"
+        } else {
+            "Follow leader"
+        };
+        commands
+            .spawn((
+                Text::new(label),
+                BackgroundColor(Color::hsl(209.0, 0.59, 0.61)),
+            ))
+            .set_parent(panel)
+            .observe(
+                |_trigger: Trigger<Pointer<Down>>,
+                 mut commands: Commands,
+                 following: Option<Res<Following>>,
+                 q_main_player: Query<Entity, With<MainPlayer>>| {
+                    if following.is_some() {
+                        commands.remove_resource::<Following>();
+                        for entity in q_main_player.iter() {
+                            commands.entity(entity).remove::<player::Walk>();
+                        }
+                    } else {
+                        commands.insert_resource(Following);
+                    }
+                },
+            );
+    }
+    commands
+        .spawn((
+            Text::new("Leave group"),
+            BackgroundColor(Color::hsl(111.0, 0.52, 0.65)),
+        ))
+        .set_parent(panel)
+        .observe(
+            |_trigger: Trigger<Pointer<Down>>,
+             mut commands: Commands,
+             party: Option<Res<Party>>,
+             my_id: Res<socket::MyId>| {
+                if let Some(party) = party {
+                    leave(&mut commands, &party, my_id.0);
+                }
+            },
+        );
+}
@@ -1,19 +1,73 @@
+use std::collections::VecDeque;
+
 use bevy::{math::u16, prelude::*};
 use bevy_mod_raycast::prelude::RaycastMesh;
 use serde::{Deserialize, Serialize};
 
-use crate::{materials::player_shadow::PlayerShadowMaterial, save};
+use crate::{map, materials::player_shadow::PlayerShadowMaterial, save};
+
+pub fn init(app: &mut App) {
+    app.add_systems(Update, (interpolate_remote_players, walk_main_player));
+}
 
-#[derive(Resource, Component, Default, Deserialize, Serialize, Clone, Debug)]
+#[derive(Resource, Component, Deserialize, Serialize, Clone, Debug)]
 pub struct Info {
     pub name: String,
     pub max_health: u32,
+    /// Health left, full when the save doesn't have it
+    #[serde(default)]
+    pub health: Option<u32>,
     pub action_points: u8,
     pub movement_points: u8,
+    /// Character level, starting at 1
+    #[serde(default = "default_level")]
+    pub level: u32,
+    /// Experience earned since the character creation
+    #[serde(default)]
+    pub experience: u64,
     #[serde(skip)]
     pub stats: PlayerStats,
 }
 
+fn default_level() -> u32 {
+    1
+}
+
+impl Info {
+    pub fn health(&self) -> u32 {
+        self.health.unwrap_or(self.max_health).min(self.max_health)
+    }
+}
+
+/// # Experience needed to go from level 1 to 2, each level needing this much more than the previous one
+const EXPERIENCE_STEP: u64 = 100;
+
+/// Level reached with an amount of experience
+pub fn level_for(experience: u64) -> u32 {
+    let mut level = 1;
+    let mut needed = EXPERIENCE_STEP;
+    while experience >= needed {
+        level += 1;
+        needed += EXPERIENCE_STEP * level as u64;
+    }
+    level
+}
+
+impl Default for Info {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            max_health: 0,
+            health: None,
+            action_points: 0,
+            movement_points: 0,
+            level: default_level(),
+            experience: 0,
+            stats: PlayerStats::default(),
+        }
+    }
+}
+
 #[derive(Default, Clone, Debug)]
 #[allow(dead_code)]
 pub struct PlayerStats {
@@ -92,3 +146,162 @@ pub fn spawn_player_character<'a>(
 
     commands.entity(root)
 }
+
+/// # Speed at which remote characters walk toward their replicated position (world units/s)
+pub const WALK_SPEED: f32 = map::tile::SPACING_X * 2.5;
+/// # Distance above which a remote character is teleported instead of walking
+/// Network corrections larger than that are considered desync, not movement
+pub const SNAP_DISTANCE: f32 = map::tile::SPACING_X * 6.0;
+/// # Height of the bobbing applied while walking
+const WALK_BOB_HEIGHT: f32 = 0.08;
+/// # Bobbing cycles per world unit walked
+const WALK_BOB_FREQUENCY: f32 = 2.0;
+
+/// Smooth the movement of a character driven by the network
+/// Instead of teleporting, the character walk cell by cell toward `target`
+#[derive(Component, Debug)]
+pub struct Interpolation {
+    target: map::TileCoordinate,
+    walked_distance: f32,
+}
+
+impl Interpolation {
+    pub fn new(position: Vec3) -> Self {
+        Self {
+            target: map::TileCoordinate::from_world(position),
+            walked_distance: 0.0,
+        }
+    }
+
+    pub fn target(&self) -> map::TileCoordinate {
+        self.target
+    }
+
+    pub fn set_target(&mut self, position: Vec3) {
+        self.target = map::TileCoordinate::from_world(position);
+    }
+
+    pub fn is_walking(&self, transform: &Transform) -> bool {
+        ground_position(transform.translation).distance(ground_position(self.target.to_world()))
+            > f32::EPSILON
+    }
+}
+
+fn ground_position(position: Vec3) -> Vec3 {
+    Vec3::new(position.x, PLAYER_SIZES.y / 2.0, position.z)
+}
+
+/// Cell adjacent to `from` that bring us the closest to `to`
+fn next_cell(from: map::TileCoordinate, to: map::TileCoordinate) -> map::TileCoordinate {
+    if from == to {
+        return to;
+    }
+    let goal = to.to_world();
+    let mut best = from;
+    let mut best_distance = from.to_world().distance_squared(goal);
+    for candidate in from.adjacent() {
+        let distance = candidate.to_world().distance_squared(goal);
+        if distance < best_distance {
+            best = candidate;
+            best_distance = distance;
+        }
+    }
+    best
+}
+
+fn interpolate_remote_players(
+    time: Res<Time>,
+    mut q_players: Query<(&mut Transform, &mut Interpolation), Without<MainPlayer>>,
+) {
+    for (mut transform, mut interpolation) in q_players.iter_mut() {
+        let target = ground_position(interpolation.target.to_world());
+        let position = ground_position(transform.translation);
+        if position.distance(target) > SNAP_DISTANCE || !interpolation.is_walking(&transform) {
+            transform.translation = target;
+            interpolation.walked_distance = 0.0;
+            continue;
+        }
+
+        // Walk toward the center of the next cell, this keep the character on the grid
+        let current_cell = map::TileCoordinate::from_world(position);
+        let waypoint = ground_position(next_cell(current_cell, interpolation.target).to_world());
+        let waypoint = if waypoint.distance_squared(position) <= f32::EPSILON {
+            target
+        } else {
+            waypoint
+        };
+        let step = WALK_SPEED * time.delta_secs();
+        let direction = (waypoint - position).normalize_or_zero();
+        let new_position = if position.distance(waypoint) <= step {
+            waypoint
+        } else {
+            position + direction * step
+        };
+        interpolation.walked_distance += position.distance(new_position);
+
+        if direction != Vec3::ZERO {
+            transform.look_to(direction, Vec3::Y);
+        }
+        let bob = (interpolation.walked_distance * WALK_BOB_FREQUENCY * std::f32::consts::TAU)
+            .sin()
+            .abs()
+            * WALK_BOB_HEIGHT;
+        transform.translation = new_position + Vec3::Y * bob;
+    }
+}
+
+/// Path the main player walks along, cell by cell, removed once the last cell is reached
+#[derive(Component, Debug)]
+pub struct Walk {
+    path: VecDeque<map::TileCoordinate>,
+    destination: map::TileCoordinate,
+    walked_distance: f32,
+}
+
+impl Walk {
+    /// `path` is made of adjacent cells, as found by `exploration::path_finding`, `None` when empty
+    pub fn new(path: Vec<map::TileCoordinate>) -> Option<Self> {
+        Some(Self {
+            destination: *path.last()?,
+            path: path.into(),
+            walked_distance: 0.0,
+        })
+    }
+
+    pub fn destination(&self) -> map::TileCoordinate {
+        self.destination
+    }
+}
+
+fn walk_main_player(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut q_main_player: Query<(Entity, &mut Transform, &mut Walk), With<MainPlayer>>,
+) {
+    for (entity, mut transform, mut walk) in q_main_player.iter_mut() {
+        let Some(next) = walk.path.front().copied() else {
+            commands.entity(entity).remove::<Walk>();
+            continue;
+        };
+        let position = ground_position(transform.translation);
+        let waypoint = ground_position(next.to_world());
+        let step = WALK_SPEED * time.delta_secs();
+        let direction = (waypoint - position).normalize_or_zero();
+        let new_position = if position.distance(waypoint) <= step {
+            walk.path.pop_front();
+            waypoint
+        } else {
+            position + direction * step
+        };
+        walk.walked_distance += position.distance(new_position);
+
+        if direction != Vec3::ZERO {
+            transform.look_to(direction, Vec3::Y);
+        }
+        let bob = (walk.walked_distance * WALK_BOB_FREQUENCY * std::f32::consts::TAU)
+            .sin()
+            .abs()
+            * WALK_BOB_HEIGHT;
+        transform.translation = new_position + Vec3::Y * bob;
+    }
+}
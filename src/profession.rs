@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{map::chunk::NodeKind, player};
+
+/// Trade trained by gathering resource nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Profession {
+    Lumberjack,
+    Farmer,
+    Miner,
+}
+
+impl Profession {
+    pub fn of(kind: NodeKind) -> Self {
+        match kind {
+            NodeKind::Tree => Profession::Lumberjack,
+            NodeKind::Wheat => Profession::Farmer,
+            NodeKind::Ore => Profession::Miner,
+        }
+    }
+}
+
+/// Level of a profession, saved with the character
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    pub level: u32,
+    pub experience: u64,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            level: 1,
+            experience: 0,
+        }
+    }
+}
+
+impl Progress {
+    /// Add experience, returning whether a new level was reached
+    /// Professions follow the same curve as the character level
+    pub fn gain(&mut self, experience: u64) -> bool {
+        self.experience += experience;
+        let level = player::level_for(self.experience);
+        let level_up = level > self.level;
+        self.level = self.level.max(level);
+        level_up
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{inventory::Inventory, map, player, save, socket, ui, GameMode, ProcessArgs};
+
+/// # Key opening and closing the quest journal
+const JOURNAL_KEY: KeyCode = KeyCode::KeyJ;
+
+pub fn init(app: &mut App) {
+    app.init_asset::<Quest>()
+        .init_asset_loader::<Loader>()
+        .init_resource::<QuestHandles>()
+        .add_systems(
+            Update,
+            (
+                load_quests,
+                refresh_progress,
+                toggle_journal.run_if(not(ui::chat::is_typing)),
+                update_journal,
+            )
+                .chain()
+                .run_if(in_state(GameMode::Exploration)),
+        )
+        .add_systems(OnExit(GameMode::Exploration), close_journal)
+        .add_observer(on_progress)
+        .add_observer(on_transition);
+}
+
+/// Path of a quest asset, relative to the assets folder
+pub fn path(id: &str) -> String {
+    format!("quests/{}.ron", id)
+}
+
+/// Progress of a quest, as seen by the dialogues
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuestState {
+    #[default]
+    NotStarted,
+    InProgress,
+    Completed,
+}
+
+/// Something to do for a quest step to be done
+#[derive(Debug, Clone, Deserialize)]
+pub enum Objective {
+    /// Open the dialogue of the NPC with this name
+    Talk(String),
+    /// Defeat this amount of monsters of a type
+    Kill(String, u32),
+    /// Enter the chunk at these coordinates
+    Reach(i32, i32),
+    /// Own at least this amount of an item
+    Collect(String, u32),
+}
+
+impl Objective {
+    /// Counter value at which the objective is done
+    pub fn goal(&self) -> u32 {
+        match self {
+            Objective::Kill(_, amount) | Objective::Collect(_, amount) => *amount,
+            Objective::Talk(_) | Objective::Reach(..) => 1,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Objective::Talk(npc) => format!("Talk to {}", npc),
+            Objective::Kill(monster, _) => format!("Defeat {}", monster),
+            Objective::Reach(x, z) => format!("Go to ({}, {})", x, z),
+            Objective::Collect(item, _) => format!("Collect {}", item),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    pub description: String,
+    /// Every objective must be done to go to the next step
+    pub objectives: Vec<Objective>,
+    /// Items given away when the step is done, like the ones brought back to an NPC
+    /// The step can't be done without them
+    #[serde(default)]
+    pub turn_in: Inventory,
+}
+
+/// Given once the last step is done
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Rewards {
+    #[serde(default)]
+    pub experience: u64,
+    #[serde(default)]
+    pub inventory: Inventory,
+}
+
+/// Quest definition, `assets/quests/{id}.ron`, the id being the name used by the dialogues
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct Quest {
+    pub name: String,
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub rewards: Rewards,
+}
+
+#[derive(Default)]
+pub struct Loader;
+
+impl AssetLoader for Loader {
+    type Asset = Quest;
+    type Settings = ();
+    type Error = ron::de::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let quest = ron::de::from_bytes::<Quest>(&bytes)?;
+        Ok(quest)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+/// Progress of a started quest, saved with the character
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    /// Index of the current step
+    pub step: usize,
+    /// Counter of every objective of the current step
+    #[serde(default)]
+    pub counters: Vec<u32>,
+    #[serde(default)]
+    pub completed: bool,
+}
+
+impl Progress {
+    pub fn state(&self) -> QuestState {
+        if self.completed {
+            QuestState::Completed
+        } else {
+            QuestState::InProgress
+        }
+    }
+
+    /// Apply `event` to the current step, then go through every step whose objectives are done
+    /// Collect objectives are always recomputed from the inventory
+    /// Returns the items turned in by the steps done
+    fn advance(
+        &mut self,
+        quest: &Quest,
+        event: &ProgressEvent,
+        inventory: &Inventory,
+    ) -> Inventory {
+        let mut inventory = inventory.clone();
+        let mut turned_in = Inventory::default();
+        // Only the step current when the event happened can use it
+        let mut event = Some(event);
+        while let Some(step) = quest.steps.get(self.step) {
+            self.counters.resize(step.objectives.len(), 0);
+            for (objective, counter) in step.objectives.iter().zip(self.counters.iter_mut()) {
+                let goal = objective.goal();
+                *counter = match (objective, event) {
+                    (Objective::Collect(item, _), _) => inventory.count(item).min(goal),
+                    (Objective::Talk(npc), Some(ProgressEvent::Talked(name))) if npc == name => {
+                        goal
+                    }
+                    (Objective::Kill(monster, _), Some(ProgressEvent::Killed(name)))
+                        if monster == name =>
+                    {
+                        (*counter + 1).min(goal)
+                    }
+                    (Objective::Reach(x, z), Some(ProgressEvent::Reached(chunk)))
+                        if chunk.x == *x && chunk.z == *z =>
+                    {
+                        goal
+                    }
+                    _ => *counter,
+                };
+            }
+            let done = step
+                .objectives
+                .iter()
+                .zip(self.counters.iter())
+                .all(|(objective, counter)| *counter >= objective.goal());
+            if !done || !inventory.exchange(&step.turn_in, &Inventory::default()) {
+                break;
+            }
+            turned_in.exchange(&Inventory::default(), &step.turn_in);
+            self.step += 1;
+            self.counters.clear();
+            event = None;
+        }
+        self.completed = self.step >= quest.steps.len();
+        turned_in
+    }
+}
+
+/// Handles of the quests started by the character, by id
+#[derive(Resource, Default)]
+pub struct QuestHandles(HashMap<String, Handle<Quest>>);
+
+/// Something the character did that may move its quests forward
+#[derive(Debug, Event)]
+pub enum ProgressEvent {
+    /// Opened the dialogue of this NPC
+    Talked(String),
+    /// Defeated a monster of this type
+    Killed(String),
+    /// Entered this chunk
+    Reached(map::ChunkCoordinate),
+    /// Recheck the objectives depending on what the character owns
+    Refresh,
+}
+
+fn load_quests(
+    mut quest_handles: ResMut<QuestHandles>,
+    asset_server: Res<AssetServer>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    let Some(save_data) = save_data_assets.get(save_handle.0.id()) else {
+        return;
+    };
+    for id in save_data.quests.keys() {
+        if !quest_handles.0.contains_key(id) {
+            quest_handles
+                .0
+                .insert(id.clone(), asset_server.load::<Quest>(path(id)));
+        }
+    }
+}
+
+/// Items can be won or lost from everywhere, recheck the quests whenever the save changes
+fn refresh_progress(
+    mut commands: Commands,
+    mut save_events: EventReader<AssetEvent<save::Data>>,
+    mut quest_events: EventReader<AssetEvent<Quest>>,
+) {
+    let save_changed = save_events
+        .read()
+        .filter(|event| matches!(event, AssetEvent::Modified { .. }))
+        .count()
+        > 0;
+    let quest_loaded = quest_events
+        .read()
+        .filter(|event| matches!(event, AssetEvent::LoadedWithDependencies { .. }))
+        .count()
+        > 0;
+    if save_changed || quest_loaded {
+        commands.trigger(ProgressEvent::Refresh);
+    }
+}
+
+fn on_transition(trigger: Trigger<map::streaming::TransitionEvent>, mut commands: Commands) {
+    commands.trigger(ProgressEvent::Reached(trigger.0));
+}
+
+/// Move the quests in progress forward, giving the rewards of the ones completed
+fn on_progress(
+    trigger: Trigger<ProgressEvent>,
+    mut commands: Commands,
+    quest_handles: Res<QuestHandles>,
+    quest_assets: Res<Assets<Quest>>,
+    mut save_data_assets: ResMut<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+    player_info: Option<ResMut<player::Info>>,
+    args: Res<ProcessArgs>,
+) {
+    let Some(save_data) = save_data_assets.get(save_handle.0.id()) else {
+        return;
+    };
+    // Compute the changes first, the save is only touched when something moved
+    // since modifying it triggers a refresh
+    let changes = save_data
+        .quests
+        .iter()
+        .filter(|(_, progress)| !progress.completed)
+        .filter_map(|(id, progress)| {
+            let quest = quest_handles
+                .0
+                .get(id)
+                .and_then(|handle| quest_assets.get(handle.id()))?;
+            let mut next = progress.clone();
+            let turned_in = next.advance(quest, trigger.event(), &save_data.inventory);
+            (next != *progress).then(|| (id.clone(), quest, next, turned_in))
+        })
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return;
+    }
+    let Some(save_data) = save_data_assets.get_mut(save_handle.0.id()) else {
+        return;
+    };
+    let level = save_data.player_info.level;
+    for (id, quest, progress, turned_in) in changes {
+        save_data
+            .inventory
+            .exchange(&turned_in, &Inventory::default());
+        let text = if progress.completed {
+            info!("Quest {} completed", id);
+            save_data.player_info.experience += quest.rewards.experience;
+            save_data
+                .inventory
+                .exchange(&Inventory::default(), &quest.rewards.inventory);
+            format!(
+                "Quest completed: {} (+{} XP)",
+                quest.name, quest.rewards.experience
+            )
+        } else {
+            match quest.steps.get(progress.step) {
+                Some(step) if progress.step != save_data.quests[&id].step => {
+                    format!("{}: {}", quest.name, step.description)
+                }
+                _ => format!("{}: objective updated", quest.name),
+            }
+        };
+        save_data.quests.insert(id, progress);
+        commands.trigger(ui::chat::ChatLineEvent {
+            channel: socket::ChatChannel::System,
+            author: None,
+            text,
+        });
+    }
+    save_data.player_info.level = level.max(player::level_for(save_data.player_info.experience));
+    if save_data.player_info.level > level {
+        commands.trigger(ui::chat::ChatLineEvent {
+            channel: socket::ChatChannel::System,
+            author: None,
+            text: format!("You reached level {}", save_data.player_info.level),
+        });
+    }
+    if let Some(mut player_info) = player_info {
+        player_info.experience = save_data.player_info.experience;
+        player_info.level = save_data.player_info.level;
+    }
+    save::persist(save_data, &args);
+}
+
+/// Window listing the quests started, with the objectives of their current step
+#[derive(Component)]
+struct Journal;
+
+fn toggle_journal(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    q_journal: Query<Entity, With<Journal>>,
+    quest_handles: Res<QuestHandles>,
+    quest_assets: Res<Assets<Quest>>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    if !keyboard_input.just_pressed(JOURNAL_KEY) {
+        return;
+    }
+    if let Ok(entity) = q_journal.get_single() {
+        commands.entity(entity).despawn_recursive();
+        return;
+    }
+    let Some(save_data) = save_data_assets.get(save_handle.0.id()) else {
+        return;
+    };
+    spawn_journal(&mut commands, save_data, &quest_handles, &quest_assets);
+}
+
+/// Rebuild the opened journal when the progress changed
+fn update_journal(
+    mut commands: Commands,
+    mut save_events: EventReader<AssetEvent<save::Data>>,
+    mut quest_events: EventReader<AssetEvent<Quest>>,
+    q_journal: Query<Entity, With<Journal>>,
+    quest_handles: Res<QuestHandles>,
+    quest_assets: Res<Assets<Quest>>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    let changed = save_events.read().count() + quest_events.read().count() > 0;
+    let Ok(entity) = q_journal.get_single() else {
+        return;
+    };
+    if !changed {
+        return;
+    }
+    let Some(save_data) = save_data_assets.get(save_handle.0.id()) else {
+        return;
+    };
+    commands.entity(entity).despawn_recursive();
+    spawn_journal(&mut commands, save_data, &quest_handles, &quest_assets);
+}
+
+fn spawn_journal(
+    commands: &mut Commands,
+    save_data: &save::Data,
+    quest_handles: &QuestHandles,
+    quest_assets: &Assets<Quest>,
+) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                top: Val::Percent(15.0),
+                right: Val::Percent(5.0),
+                width: Val::Percent(25.0),
+                padding: UiRect::all(Val::Px(5.0)),
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            BorderRadius::all(Val::Px(5.0)),
+            BackgroundColor(Color::hsl(246.0, 0.21, 0.29)),
+            ZIndex(40),
+            Journal,
+        ))
+        .with_children(|parent| {
+            spawn_line(parent, "Quests", 14.0, Color::WHITE);
+            if save_data.quests.is_empty() {
+                spawn_line(
+                    parent,
+                    "No quest started",
+                    10.0,
+                    Color::hsl(242.0, 0.15, 0.57),
+                );
+            }
+            // In progress first
+            let mut quests = save_data.quests.iter().collect::<Vec<_>>();
+            quests.sort_by_key(|(_, progress)| progress.completed);
+            for (id, progress) in quests {
+                let Some(quest) = quest_handles
+                    .0
+                    .get(id)
+                    .and_then(|handle| quest_assets.get(handle.id()))
+                else {
+                    spawn_line(parent, id.clone(), 12.0, Color::hsl(242.0, 0.15, 0.57));
+                    continue;
+                };
+                if progress.completed {
+                    spawn_line(
+                        parent,
+                        format!("{} (completed)", quest.name),
+                        12.0,
+                        Color::hsl(242.0, 0.15, 0.57),
+                    );
+                    continue;
+                }
+                spawn_line(parent, quest.name.clone(), 12.0, Color::WHITE);
+                let Some(step) = quest.steps.get(progress.step) else {
+                    continue;
+                };
+                spawn_line(
+                    parent,
+                    format!(
+                        "Step {}/{}: {}",
+                        progress.step + 1,
+                        quest.steps.len(),
+                        step.description
+                    ),
+                    10.0,
+                    Color::hsl(209.0, 0.59, 0.61),
+                );
+                for (index, objective) in step.objectives.iter().enumerate() {
+                    let counter = progress.counters.get(index).copied().unwrap_or(0);
+                    spawn_line(
+                        parent,
+                        format!(
+                            "- {} {}/{}",
+                            objective.describe(),
+                            counter.min(objective.goal()),
+                            objective.goal()
+                        ),
+                        10.0,
+                        Color::hsl(242.0, 0.15, 0.57),
+                    );
+                }
+            }
+        });
+}
+
+fn spawn_line(parent: &mut ChildBuilder, text: impl Into<String>, size: f32, color: Color) {
+    parent.spawn((
+        Text::new(text.into()),
+        TextColor(color),
+        TextFont {
+            font_size: size,
+            ..Default::default()
+        },
+    ));
+}
+
+fn close_journal(mut commands: Commands, q_journal: Query<Entity, With<Journal>>) {
+    for entity in q_journal.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path};
 
 use bevy::{
     asset::{io::Reader, AssetLoader, AssetPath, LoadContext},
@@ -6,12 +6,32 @@ use bevy::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::player::Info;
+use crate::{inventory::Inventory, player::Info, profession, quest, ProcessArgs};
+
+/// # Save file used when none is given in the process arguments
+pub const DEFAULT_FILE: &str = "player_info.ron";
 
 #[derive(Asset, TypePath, Default, Deserialize, Serialize)]
 pub struct Data {
     pub player_info: Info,
     pub player_position: Vec3,
+    #[serde(default)]
+    pub inventory: Inventory,
+    /// Progress of every quest started, by quest id
+    #[serde(default)]
+    pub quests: BTreeMap<String, quest::Progress>,
+    /// Level of every profession trained
+    #[serde(default)]
+    pub professions: BTreeMap<profession::Profession, profession::Progress>,
+}
+
+/// Name of the save file, relative to the assets folder
+pub fn file_name(args: &ProcessArgs) -> String {
+    #[cfg(debug_assertions)]
+    if let Some(file) = &args.player_info_file {
+        return file.clone();
+    }
+    DEFAULT_FILE.to_string()
 }
 
 impl AssetLoader for Data {
@@ -56,7 +76,29 @@ pub fn save(
     file_path: impl AsRef<Path>,
 ) {
     // info!("Save");
-    let data = data_assets.get(&res_handle.0).unwrap();
-    let bytes = ron::ser::to_string(data).unwrap().into_bytes();
-    std::fs::write(file_path, bytes).unwrap();
+    let Some(data) = data_assets.get(&res_handle.0) else {
+        warn!("Nothing to save, the save data isn't loaded");
+        return;
+    };
+    write(data, file_path);
+}
+
+/// Errors are only logged, a save that can't be written mustn't stop the game
+pub fn write(data: &Data, file_path: impl AsRef<Path>) {
+    let file_path = file_path.as_ref();
+    let text = match ron::ser::to_string(data) {
+        Ok(text) => text,
+        Err(error) => {
+            error!("Can't serialize the save data: {}", error);
+            return;
+        }
+    };
+    if let Err(error) = std::fs::write(file_path, text) {
+        error!("Can't write {}: {}", file_path.display(), error);
+    }
+}
+
+/// Write the save data back into the assets folder
+pub fn persist(data: &Data, args: &ProcessArgs) {
+    write(data, Path::new("assets").join(file_name(args)));
 }
@@ -0,0 +1,522 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{app::ScheduleRunnerPlugin, log::LogPlugin, prelude::*, state::app::StatesPlugin};
+use bevy_matchbox::prelude::*;
+
+use crate::{combat, map, player, save, socket, ProcessArgs};
+
+/// # Simulation rate of the headless server
+const TICK_RATE: f64 = 30.0;
+/// # Interval (in seconds) between two writes of the characters to disk
+const PERSIST_INTERVAL: f32 = 30.0;
+/// # Folder the server save the characters in
+const SAVE_FOLDER: &str = "saves";
+/// # Longest character name accepted, names are also used as save file names
+const MAX_NAME_LENGTH: usize = 32;
+
+/// Run the game without any window, owning the world state
+/// Clients still talk peer to peer, but movement, object states and harvests are only trusted
+/// once validated here
+/// Fights are played by the fighters' clients, the server checks who takes part
+/// and where they are placed, from the seed the fight owner sends it
+pub fn run(args: ProcessArgs) {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+            1.0 / TICK_RATE,
+        ))),
+        AssetPlugin::default(),
+        LogPlugin::default(),
+        StatesPlugin,
+    ))
+    .insert_resource(args)
+    .init_resource::<WorldState>()
+    .insert_resource(PersistTimer(Timer::from_seconds(
+        PERSIST_INTERVAL,
+        TimerMode::Repeating,
+    )))
+    .add_systems(Startup, socket::start_connection)
+    .add_systems(
+        Update,
+        (poll_messages, persist_characters).run_if(socket::is_connected),
+    )
+    .add_systems(Update, announce_id.run_if(resource_added::<socket::MyId>));
+
+    map::init(&mut app);
+    socket::init_headless(&mut app);
+
+    info!("Starting headless server");
+    app.run();
+}
+
+/// Clients only trust the server they are given the id of
+fn announce_id(my_id: Res<socket::MyId>) {
+    info!(
+        "Server peer id: {}, start the clients with --server-peer {}",
+        my_id.0, my_id.0
+    );
+}
+
+/// What the server knows about a connected character
+#[derive(Debug)]
+struct Character {
+    name: String,
+    last_valid: map::movement::Validated,
+}
+
+/// Fight reported by its owner
+#[derive(Debug)]
+struct Fight {
+    seed: u64,
+    fighters: Vec<PeerId>,
+    /// Cells drawn from the seed, the only ones fighters can be placed on
+    placements: Vec<map::TileCoordinate>,
+    /// Placement is over, fighters can't move anymore
+    started: bool,
+}
+
+#[derive(Resource, Default)]
+struct WorldState {
+    characters: HashMap<PeerId, Character>,
+    chunks: HashMap<(i32, i32), Handle<map::Chunk>>,
+    /// By owner
+    fights: HashMap<PeerId, Fight>,
+}
+
+impl WorldState {
+    /// Start loading the chunk `cell` is in and its neighbours,
+    /// so a move to a neighbour chunk isn't rejected while it loads
+    fn preload_around(&mut self, cell: map::TileCoordinate, asset_server: &AssetServer) {
+        let chunk = cell.to_chunk();
+        for x in -1..=1 {
+            for z in -1..=1 {
+                let neighbour = map::ChunkCoordinate::new(chunk.x + x, chunk.z + z);
+                self.chunks
+                    .entry((neighbour.x, neighbour.z))
+                    .or_insert_with(|| asset_server.load(map::chunk::path(neighbour)));
+            }
+        }
+    }
+
+    fn fight_of(&self, peer_id: PeerId) -> Option<&Fight> {
+        self.fights
+            .values()
+            .find(|fight| fight.fighters.contains(&peer_id))
+    }
+
+    /// Chunk of the fight `owner` started with `fighters`,
+    /// or why it can't take place
+    fn check_fight(
+        &self,
+        owner: PeerId,
+        fighters: &[PeerId],
+    ) -> Result<map::ChunkCoordinate, String> {
+        if !fighters.contains(&owner) {
+            return Err("the owner doesn't fight".to_string());
+        }
+        let chunk = self
+            .characters
+            .get(&owner)
+            .ok_or("the owner never joined")?
+            .last_valid
+            .cell
+            .to_chunk();
+        for fighter in fighters.iter() {
+            let Some(character) = self.characters.get(fighter) else {
+                return Err(format!("{} never joined", fighter));
+            };
+            if character.last_valid.cell.to_chunk() != chunk {
+                return Err(format!("{} isn't on {}", character.name, chunk));
+            }
+            let elsewhere = self
+                .fights
+                .iter()
+                .any(|(other, fight)| *other != owner && fight.fighters.contains(fighter));
+            if elsewhere {
+                return Err(format!("{} is already fighting", character.name));
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+#[derive(Resource)]
+struct PersistTimer(Timer);
+
+fn poll_messages(
+    mut commands: Commands,
+    mut socket: ResMut<socket::Socket>,
+    mut world: ResMut<WorldState>,
+    asset_server: Res<AssetServer>,
+    chunk_assets: Res<Assets<map::Chunk>>,
+    current_world_map: Res<map::world::CurrentWorldMap>,
+    world_map_assets: Res<Assets<map::WorldMap>>,
+    mut objects: ResMut<map::objects::ObjectStates>,
+    mut depleted: ResMut<map::nodes::Depleted>,
+    time: Res<Time>,
+) {
+    for (peer_id, state) in socket.0.update_peers() {
+        match state {
+            PeerState::Connected => {
+                info!("Client connected: {}", peer_id);
+                commands.trigger(socket::SendMessageEvent::ToPeer(
+                    socket::Message::ServerHello,
+                    peer_id,
+                ));
+            }
+            PeerState::Disconnected => {
+                info!("Client disconnected: {}", peer_id);
+                if let Some(character) = world.characters.remove(&peer_id) {
+                    persist(&character);
+                }
+                world.fights.remove(&peer_id);
+                for fight in world.fights.values_mut() {
+                    fight.fighters.retain(|fighter| *fighter != peer_id);
+                }
+            }
+        }
+    }
+
+    for (peer_id, packet) in socket.0.receive() {
+        let Ok(message) = bincode::deserialize::<socket::Message>(&packet) else {
+            warn!("Dropping malformed packet from {}", peer_id);
+            continue;
+        };
+        match message {
+            // The position the client claims is ignored, characters start where they were saved
+            socket::Message::PlayerInitInfo { name, .. } => {
+                let Some(path) = save_path(&name) else {
+                    warn!(
+                        "Rejecting {} joining with the invalid name {:?}",
+                        peer_id, name
+                    );
+                    continue;
+                };
+                let world_map = world_map_assets.get(current_world_map.0.id());
+                let saved = read_save(&path)
+                    .map(|data| map::TileCoordinate::from_world(data.player_position))
+                    .filter(|cell| world_map.map_or(true, |map| map.contains(cell.to_chunk())));
+                let cell = saved.unwrap_or_else(|| {
+                    let spawn = world_map
+                        .map(|map| map.spawn_chunk())
+                        .unwrap_or(map::ChunkCoordinate::new(0, 0));
+                    map::world::spawn_cell(
+                        spawn,
+                        map::movement::loaded_chunk(spawn, &asset_server, &chunk_assets),
+                    )
+                });
+                info!("{} joined as {} on {}", peer_id, name, cell);
+                world.preload_around(cell, &asset_server);
+                let position = cell.to_world();
+                commands.trigger(socket::SendMessageEvent::Broadcast(
+                    socket::Message::AuthoritativePosition {
+                        id: peer_id,
+                        x: position.x,
+                        z: position.z,
+                    },
+                ));
+                send_object_states(&mut commands, &objects, cell.to_chunk(), peer_id);
+                send_depleted_nodes(&mut commands, &depleted, cell.to_chunk(), peer_id);
+                world.characters.insert(
+                    peer_id,
+                    Character {
+                        name,
+                        last_valid: map::movement::Validated {
+                            cell,
+                            at: time.elapsed_secs(),
+                        },
+                    },
+                );
+            }
+            socket::Message::UpdatePlayerPosition(position) => {
+                let target =
+                    map::TileCoordinate::from_world(Vec3::new(position.x, 0.0, position.z));
+                let now = time.elapsed_secs();
+                // Fighters only move to place themselves on the cells drawn for the fight
+                let placed = world
+                    .fight_of(peer_id)
+                    .map(|fight| !fight.started && fight.placements.contains(&target));
+                let Some(character) = world.characters.get_mut(&peer_id) else {
+                    continue;
+                };
+                if let Some(placed) = placed {
+                    if placed {
+                        character.last_valid = map::movement::Validated {
+                            cell: target,
+                            at: now,
+                        };
+                    } else {
+                        warn!("Rejected placement of {} on {}", character.name, target);
+                    }
+                    let position = character.last_valid.cell.to_world();
+                    commands.trigger(socket::SendMessageEvent::Broadcast(
+                        socket::Message::AuthoritativePosition {
+                            id: peer_id,
+                            x: position.x,
+                            z: position.z,
+                        },
+                    ));
+                    continue;
+                }
+                let last_valid = character.last_valid;
+                let validation = map::movement::validate_loaded(
+                    last_valid.cell,
+                    target,
+                    now - last_valid.at,
+                    &asset_server,
+                    &chunk_assets,
+                    &objects,
+                );
+                let accepted = match validation {
+                    Ok(()) => {
+                        // Standing still doesn't earn extra steps for later
+                        character.last_valid = map::movement::Validated {
+                            cell: target,
+                            at: now,
+                        };
+                        if last_valid.cell == target {
+                            continue;
+                        }
+                        true
+                    }
+                    Err(violation) => {
+                        warn!(
+                            "Rejected move of {} from {} to {}: {}",
+                            character.name, last_valid.cell, target, violation
+                        );
+                        false
+                    }
+                };
+                let position = character.last_valid.cell.to_world();
+                if accepted {
+                    world.preload_around(target, &asset_server);
+                    if target.to_chunk() != last_valid.cell.to_chunk() {
+                        send_object_states(&mut commands, &objects, target.to_chunk(), peer_id);
+                        send_depleted_nodes(&mut commands, &depleted, target.to_chunk(), peer_id);
+                    }
+                }
+                commands.trigger(socket::SendMessageEvent::Broadcast(
+                    socket::Message::AuthoritativePosition {
+                        id: peer_id,
+                        x: position.x,
+                        z: position.z,
+                    },
+                ));
+            }
+            socket::Message::ObjectState { x, z, active } => {
+                let cell = map::TileCoordinate::new(x, z);
+                let Some(character) = world.characters.get(&peer_id) else {
+                    continue;
+                };
+                if !map::objects::can_toggle(
+                    character.last_valid.cell,
+                    cell,
+                    &asset_server,
+                    &chunk_assets,
+                ) {
+                    warn!(
+                        "Rejected state of the object on {} from {}, standing on {}",
+                        cell, character.name, character.last_valid.cell
+                    );
+                    continue;
+                }
+                objects.set(cell, active);
+                // Relay to everyone else on the chunk
+                let chunk = cell.to_chunk();
+                for (other, _) in world.characters.iter().filter(|(other, character)| {
+                    **other != peer_id && character.last_valid.cell.to_chunk() == chunk
+                }) {
+                    commands.trigger(socket::SendMessageEvent::ToPeer(
+                        socket::Message::ObjectState { x, z, active },
+                        *other,
+                    ));
+                }
+            }
+            socket::Message::NodeHarvested { x, z } => {
+                let cell = map::TileCoordinate::new(x, z);
+                let Some(character) = world.characters.get(&peer_id) else {
+                    continue;
+                };
+                let node =
+                    map::movement::loaded_chunk(cell.to_chunk(), &asset_server, &chunk_assets)
+                        .and_then(|data| data.node_at(cell.to_local()));
+                let Some(node) = node else {
+                    warn!("{} harvested a node missing from {}", character.name, cell);
+                    continue;
+                };
+                // The last validated position may lag a few cells behind
+                let within_reach =
+                    character.last_valid.cell.walk_distance(cell) <= 1 + map::movement::SLACK;
+                if !within_reach || depleted.contains(cell) {
+                    warn!(
+                        "Rejected the harvest of {} from {}, standing on {}",
+                        cell, character.name, character.last_valid.cell
+                    );
+                    continue;
+                }
+                depleted.deplete(cell, node.kind);
+                let chunk = cell.to_chunk();
+                for (other, _) in world.characters.iter().filter(|(other, character)| {
+                    **other != peer_id && character.last_valid.cell.to_chunk() == chunk
+                }) {
+                    commands.trigger(socket::SendMessageEvent::ToPeer(
+                        socket::Message::NodeHarvested { x, z },
+                        *other,
+                    ));
+                }
+            }
+            socket::Message::FightSeed { seed, fighters } => {
+                let chunk = world.check_fight(peer_id, &fighters).and_then(|chunk| {
+                    map::movement::loaded_chunk(chunk, &asset_server, &chunk_assets)
+                        .map(|data| (chunk, data))
+                        .ok_or_else(|| format!("{} isn't loaded", chunk))
+                });
+                let (chunk, data) = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(reason) => {
+                        warn!("Rejecting the fight of {}: {}", peer_id, reason);
+                        world.fights.remove(&peer_id);
+                        for fighter in fighters.iter() {
+                            commands.trigger(socket::SendMessageEvent::ToPeer(
+                                socket::Message::FightRejected { owner: peer_id },
+                                *fighter,
+                            ));
+                        }
+                        continue;
+                    }
+                };
+                // Sent again with the group members who joined since
+                if let Some(fight) = world
+                    .fights
+                    .get_mut(&peer_id)
+                    .filter(|fight| fight.seed == seed)
+                {
+                    fight.fighters = fighters;
+                    continue;
+                }
+                info!("{} started a fight on {}", peer_id, chunk);
+                let placements =
+                    combat::placement_cells(data, chunk, &mut combat::FightRng::new(seed));
+                world.fights.insert(
+                    peer_id,
+                    Fight {
+                        seed,
+                        fighters,
+                        placements,
+                        started: false,
+                    },
+                );
+            }
+            socket::Message::CombatStart => {
+                if let Some(fight) = world.fights.get_mut(&peer_id) {
+                    fight.started = true;
+                }
+            }
+            socket::Message::FightOver => {
+                let Some(fight) = world.fights.remove(&peer_id) else {
+                    continue;
+                };
+                // The fight doesn't count as time to walk further afterwards
+                let now = time.elapsed_secs();
+                for fighter in fight.fighters.iter() {
+                    if let Some(character) = world.characters.get_mut(fighter) {
+                        character.last_valid.at = now;
+                    }
+                }
+            }
+            // Duels, spells and everything else are only exchanged between the peers involved
+            _ => {}
+        }
+    }
+}
+
+/// Tell a character entering `chunk` about the objects changed there before they arrived
+fn send_object_states(
+    commands: &mut Commands,
+    objects: &map::objects::ObjectStates,
+    chunk: map::ChunkCoordinate,
+    peer_id: PeerId,
+) {
+    for (cell, active) in objects.changed_in(chunk) {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::ObjectState {
+                x: cell.x,
+                z: cell.z,
+                active,
+            },
+            peer_id,
+        ));
+    }
+}
+
+/// Tell a character entering `chunk` about the nodes harvested there before they arrived
+fn send_depleted_nodes(
+    commands: &mut Commands,
+    depleted: &map::nodes::Depleted,
+    chunk: map::ChunkCoordinate,
+    peer_id: PeerId,
+) {
+    for cell in depleted.depleted_in(chunk) {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::NodeHarvested {
+                x: cell.x,
+                z: cell.z,
+            },
+            peer_id,
+        ));
+    }
+}
+
+/// Save file of a character, `None` when the name isn't made of plain `[A-Za-z0-9_-]`
+/// so a client can't write outside of the save folder
+fn save_path(name: &str) -> Option<PathBuf> {
+    let is_plain = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    is_plain.then(|| PathBuf::from(SAVE_FOLDER).join(format!("{}.ron", name)))
+}
+
+/// Character saved by a previous session, `None` for a new one
+fn read_save(path: &Path) -> Option<save::Data> {
+    let bytes = std::fs::read(path).ok()?;
+    ron::de::from_bytes::<save::Data>(&bytes)
+        .map_err(|error| warn!("Can't read {}: {}", path.display(), error))
+        .ok()
+}
+
+/// Write the character position, keeping whatever else was already saved
+fn persist(character: &Character) {
+    let Some(path) = save_path(&character.name) else {
+        return;
+    };
+    let mut data = read_save(&path).unwrap_or_else(|| save::Data {
+        player_info: player::Info {
+            name: character.name.clone(),
+            ..default()
+        },
+        ..default()
+    });
+    data.player_position =
+        character.last_valid.cell.to_world() + Vec3::Y * (player::PLAYER_SIZES.y / 2.0);
+    if let Err(error) = std::fs::create_dir_all(SAVE_FOLDER) {
+        error!("Can't create the save folder: {}", error);
+        return;
+    }
+    save::write(&data, path);
+}
+
+fn persist_characters(time: Res<Time>, mut timer: ResMut<PersistTimer>, world: Res<WorldState>) {
+    timer.0.tick(time.delta());
+    if !timer.0.just_finished() {
+        return;
+    }
+    for character in world.characters.values() {
+        persist(character);
+    }
+}
@@ -0,0 +1,804 @@
+use bevy::{math::u8, prelude::*};
+use bevy_matchbox::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    inventory::Inventory,
+    map,
+    materials::player_shadow::PlayerShadowMaterial,
+    player::{self, spawn_player_character},
+};
+
+/// In-process network used to test the multiplayer flows
+#[cfg(test)]
+pub mod simulation;
+
+pub const SS_ADDRESS: &str = "ws://localhost:3536";
+
+pub fn init(app: &mut App) {
+    init_headless(app);
+    app.add_systems(Update, poll_messages.run_if(is_connected))
+        .add_observer(on_server_hello);
+}
+
+/// Connection handling without any message processing, used by the dedicated server
+pub fn init_headless(app: &mut App) {
+    app.add_systems(
+        Update,
+        poll_id
+            .run_if(is_connected)
+            .run_if(not(resource_exists::<MyId>)),
+    )
+    .add_observer(send_queued_messages);
+}
+
+/// Everything the game need to exchange packets with other peers
+/// Implemented by the real WebRTC socket and by `simulation::SimulatedSocket`
+pub trait Transport: Send + Sync + 'static {
+    fn id(&mut self) -> Option<PeerId>;
+    fn is_closed(&self) -> bool;
+    fn update_peers(&mut self) -> Vec<(PeerId, PeerState)>;
+    fn receive(&mut self) -> Vec<(PeerId, Box<[u8]>)>;
+    fn send(&mut self, packet: Box<[u8]>, peer: PeerId);
+    fn connected_peers(&self) -> Vec<PeerId>;
+}
+
+impl Transport for MatchboxSocket<SingleChannel> {
+    fn id(&mut self) -> Option<PeerId> {
+        (**self).id()
+    }
+
+    fn is_closed(&self) -> bool {
+        (**self).is_closed()
+    }
+
+    fn update_peers(&mut self) -> Vec<(PeerId, PeerState)> {
+        (**self).update_peers()
+    }
+
+    fn receive(&mut self) -> Vec<(PeerId, Box<[u8]>)> {
+        (**self).receive()
+    }
+
+    fn send(&mut self, packet: Box<[u8]>, peer: PeerId) {
+        (**self).send(packet, peer)
+    }
+
+    fn connected_peers(&self) -> Vec<PeerId> {
+        (**self).connected_peers().collect()
+    }
+}
+
+/// The connection every network system go through
+#[derive(Resource)]
+pub struct Socket(pub Box<dyn Transport>);
+
+pub fn start_connection(mut commands: Commands) {
+    let socket = MatchboxSocket::new_reliable(SS_ADDRESS);
+    info!("Socket created at: {}", SS_ADDRESS);
+    commands.insert_resource(Socket(Box::new(socket)));
+}
+
+#[derive(Debug, Resource)]
+pub struct MyId(pub PeerId);
+
+fn poll_id(mut commands: Commands, mut socket: ResMut<Socket>) {
+    if let Some(id) = socket.0.id() {
+        commands.insert_resource(MyId(id));
+    }
+}
+
+pub fn is_connected(socket: Option<Res<Socket>>) -> bool {
+    if let Some(socket) = socket {
+        !socket.0.is_closed()
+    } else {
+        false
+    }
+}
+
+#[derive(Component)]
+pub struct Id(pub PeerId);
+
+/// Peer running the dedicated server, when one is pinned in the process arguments
+/// Once known, player positions are only trusted when coming from it
+#[derive(Debug, Resource)]
+pub struct ServerId(pub PeerId);
+
+/// Only the pinned server is trusted, any peer can claim to be one
+/// so without a pinned server we stay peer to peer
+fn on_server_hello(
+    trigger: Trigger<ServerHelloEvent>,
+    mut commands: Commands,
+    server_id: Option<Res<ServerId>>,
+    args: Option<Res<crate::ProcessArgs>>,
+) {
+    let peer = trigger.0;
+    let Some(pinned) = args.as_ref().and_then(|args| args.server_peer) else {
+        warn!(
+            "Ignoring server hello from {}, no server is pinned with --server-peer",
+            peer
+        );
+        return;
+    };
+    if peer.0 != pinned {
+        warn!(
+            "Ignoring server hello from {}, the server is {}",
+            peer, pinned
+        );
+        return;
+    }
+    if let Some(server_id) = server_id {
+        if server_id.0 != peer {
+            warn!(
+                "Ignoring server hello from {}, the server is {}",
+                peer, server_id.0
+            );
+        }
+        return;
+    }
+    info!("Dedicated server found: {}", peer);
+    commands.insert_resource(ServerId(peer));
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Message {
+    PlayerInitInfo {
+        id: PeerId,
+        name: String,
+        x: f32,
+        z: f32,
+    },
+    UpdatePlayerPosition(UpdatePlayerPosition),
+    DuelDemand,
+    DuelAccepted,
+    DuelRefused,
+    DuelCancelled,
+    /// Automatic refusal, the receiver can't fight right now
+    DuelBusy,
+    CombatPlayerJoined {
+        stats: player::Info,
+        position: Vec3,
+    },
+    CombatStart,
+    CombatReadyStateChanged(bool),
+    Chat {
+        channel: ChatChannel,
+        text: String,
+    },
+    PartyInvite,
+    PartyInviteAnswer(bool),
+    /// Sent by the group leader whenever the group composition change
+    PartyUpdate {
+        leader: PeerId,
+        members: Vec<PeerId>,
+    },
+    PartyLeave,
+    PartyMemberStatus {
+        health: u32,
+        max_health: u32,
+        chunk_x: i32,
+        chunk_z: i32,
+    },
+    PartyJoinFight,
+    TradeRequest,
+    TradeAccepted,
+    TradeRefused,
+    /// Replace the sender offer, `version` increase with every change
+    TradeOffer {
+        version: u32,
+        offer: Inventory,
+    },
+    /// Accept the exchange of the two offers at the given versions
+    TradeConfirm {
+        offer_version: u32,
+        partner_offer_version: u32,
+    },
+    TradeCancelled,
+    /// Sent by the player who asked for the trade once both confirmed these versions,
+    /// asking the partner to check its offer is still in its inventory
+    TradePrepare {
+        offer_version: u32,
+        partner_offer_version: u32,
+    },
+    /// Answer to a prepare, the partner vouches for its offer and can't change it anymore
+    TradeReady {
+        offer_version: u32,
+        partner_offer_version: u32,
+    },
+    /// Sent by the player who asked for the trade once it applied the exchange
+    /// of the offers at these versions, the partner applies it on reception
+    TradeCommit {
+        offer_version: u32,
+        partner_offer_version: u32,
+    },
+    /// Sent by the dedicated server to every client that connect
+    ServerHello,
+    /// Position of a player as validated by the dedicated server
+    AuthoritativePosition {
+        id: PeerId,
+        x: f32,
+        z: f32,
+    },
+    /// Sent by the fight owner to everyone, so players on the chunk can watch it
+    FightStarted {
+        chunk_x: i32,
+        chunk_z: i32,
+        fighters: Vec<PeerId>,
+    },
+    FightOver,
+    /// Ask the fight owner to watch the fight
+    SpectateRequest,
+    SpectateRefused,
+    SpectateLeave,
+    /// Full state of the fight, sent by the owner to its spectators
+    FightSnapshot(FightSnapshot),
+    /// Turn event of the fight, sent by the owner to its spectators as it is recorded
+    FightInput(crate::combat::replay::Input),
+    /// Seed of the fight randomness and placement order, sent by the fight owner to the fighters
+    FightSeed {
+        seed: u64,
+        fighters: Vec<PeerId>,
+    },
+    /// Ask the fight owner for its seed, sent by fighters once they entered the fight
+    FightSeedRequest,
+    /// Sent by the dedicated server to the fighters of a fight it refused to run,
+    /// like one with fighters on another chunk or already fighting
+    FightRejected {
+        owner: PeerId,
+    },
+    /// New state of the interactive object on an absolute cell, sent to the players on its chunk
+    /// or to the dedicated server, which relays it once validated
+    ObjectState {
+        x: i32,
+        z: i32,
+        active: bool,
+    },
+    /// The resource node on an absolute cell was harvested, sent to the players on its chunk
+    /// or to the dedicated server, which relays it once validated
+    NodeHarvested {
+        x: i32,
+        z: i32,
+    },
+    /// State an object on an absolute cell was left in before the receiver entered its chunk,
+    /// sent by the players already there when there is no dedicated server
+    KnownObjectState {
+        x: i32,
+        z: i32,
+        active: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FighterSnapshot {
+    pub id: PeerId,
+    pub name: String,
+    pub health: u32,
+    pub max_health: u32,
+    pub x: f32,
+    pub z: f32,
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FightSnapshot {
+    /// In turn order
+    pub fighters: Vec<FighterSnapshot>,
+    /// Still placing characters, no turn has been played yet
+    pub preparation: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatChannel {
+    /// Only players on the same chunk
+    Local {
+        chunk_x: i32,
+        chunk_z: i32,
+    },
+    Global,
+    /// Whisper, sent to a single peer
+    Private,
+    /// Players fighting in the same fight
+    Team,
+    /// Players watching the fight owned by `owner`
+    Spectator {
+        owner: PeerId,
+    },
+    /// Feedback generated locally, never sent
+    System,
+}
+
+#[derive(Debug, Event)]
+pub struct NewPeerConnectionEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+#[allow(dead_code)]
+pub struct PeerDeconectionEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct CombatReadyStateChangedEvent {
+    pub peer_id: PeerId,
+    pub is_ready: bool,
+}
+
+#[derive(Debug, Event)]
+pub struct CombatStartedEvent;
+
+#[derive(Debug, Event)]
+pub struct CombatPlayerJoinedEvent {
+    pub stats: player::Info,
+    pub position: Vec3,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct ChatMessageReceivedEvent {
+    pub channel: ChatChannel,
+    pub text: String,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct PartyInviteReceivedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct PartyInviteAnsweredEvent {
+    pub peer_id: PeerId,
+    pub accepted: bool,
+}
+
+#[derive(Debug, Event)]
+pub struct PartyUpdatedEvent {
+    pub leader: PeerId,
+    pub members: Vec<PeerId>,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct PartyLeftEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct PartyMemberStatusEvent {
+    pub health: u32,
+    pub max_health: u32,
+    pub chunk_x: i32,
+    pub chunk_z: i32,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct PartyJoinFightEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct TradeRequestedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct TradeAcceptedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct TradeRefusedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct TradeOfferChangedEvent {
+    pub version: u32,
+    pub offer: Inventory,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct TradeConfirmedEvent {
+    pub offer_version: u32,
+    pub partner_offer_version: u32,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct TradeCancelledEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct TradePreparedEvent {
+    pub offer_version: u32,
+    pub partner_offer_version: u32,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct TradeReadyEvent {
+    pub offer_version: u32,
+    pub partner_offer_version: u32,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct TradeCommittedEvent {
+    pub offer_version: u32,
+    pub partner_offer_version: u32,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct ServerHelloEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct AuthoritativePositionEvent {
+    pub id: PeerId,
+    pub position: UpdatePlayerPosition,
+}
+
+#[derive(Debug, Event)]
+pub struct FightStartedEvent {
+    pub chunk_x: i32,
+    pub chunk_z: i32,
+    pub fighters: Vec<PeerId>,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct FightOverEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct SpectateRequestedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct SpectateRefusedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct SpectateLeftEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct FightSnapshotEvent(pub FightSnapshot, pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct FightInputEvent(pub crate::combat::replay::Input, pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct FightSeedEvent {
+    pub seed: u64,
+    pub fighters: Vec<PeerId>,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct FightSeedRequestedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct FightRejectedEvent {
+    pub owner: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct ObjectStateEvent {
+    pub x: i32,
+    pub z: i32,
+    pub active: bool,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct KnownObjectStateEvent {
+    pub x: i32,
+    pub z: i32,
+    pub active: bool,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct NodeHarvestedEvent {
+    pub x: i32,
+    pub z: i32,
+    pub peer_id: PeerId,
+}
+
+#[derive(Debug, Event)]
+pub struct DuelDemandReceivedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct DuelRefusedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct DuelBusyEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct DuelAcceptedEvent(pub PeerId);
+
+#[derive(Debug, Event)]
+pub struct DuelCancelEvent(pub PeerId);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdatePlayerPosition {
+    pub x: f32,
+    pub z: f32,
+}
+
+#[derive(Debug, Event)]
+pub struct UpdatePlayerPositionEvent(pub UpdatePlayerPosition, pub PeerId);
+
+pub fn poll_messages(
+    mut socket: ResMut<Socket>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut player_shadow_materials: ResMut<Assets<PlayerShadowMaterial>>,
+    server_id: Option<Res<ServerId>>,
+    time: Res<Time>,
+) {
+    let peer_changes = socket.0.update_peers();
+    for peer_change in peer_changes {
+        match peer_change.1 {
+            PeerState::Connected => commands.trigger(NewPeerConnectionEvent(peer_change.0)),
+            PeerState::Disconnected => commands.trigger(PeerDeconectionEvent(peer_change.0)),
+        }
+    }
+
+    for received in socket.0.receive() {
+        let message = bincode::deserialize::<Message>(&*received.1).unwrap();
+        match message {
+            Message::PlayerInitInfo { id, name, x, z } => {
+                let position = Vec3::new(x, player::PLAYER_SIZES.y / 2.0, z);
+                spawn_player_character(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    &mut player_shadow_materials,
+                    crate::player::Player { name: name.clone() },
+                    position,
+                )
+                .insert((
+                    Id(id),
+                    player::Interpolation::new(position),
+                    map::movement::Validated::new(position, time.elapsed_secs()),
+                ));
+            }
+            Message::UpdatePlayerPosition(new_position) => {
+                commands.trigger(UpdatePlayerPositionEvent(new_position, received.0));
+            }
+            Message::DuelDemand => {
+                commands.trigger(DuelDemandReceivedEvent(received.0));
+            }
+            Message::DuelRefused => {
+                commands.trigger(DuelRefusedEvent(received.0));
+            }
+            Message::DuelBusy => {
+                commands.trigger(DuelBusyEvent(received.0));
+            }
+            Message::DuelAccepted => {
+                commands.trigger(DuelAcceptedEvent(received.0));
+            }
+            Message::DuelCancelled => {
+                commands.trigger(DuelCancelEvent(received.0));
+            }
+            Message::CombatReadyStateChanged(is_ready) => {
+                commands.trigger(CombatReadyStateChangedEvent {
+                    peer_id: received.0,
+                    is_ready,
+                });
+            }
+            Message::CombatStart => {
+                commands.trigger(CombatStartedEvent);
+            }
+            Message::CombatPlayerJoined { stats, position } => {
+                commands.trigger(CombatPlayerJoinedEvent {
+                    stats,
+                    position,
+                    peer_id: received.0,
+                });
+            }
+            Message::Chat { channel, text } => {
+                commands.trigger(ChatMessageReceivedEvent {
+                    channel,
+                    text,
+                    peer_id: received.0,
+                });
+            }
+            Message::PartyInvite => {
+                commands.trigger(PartyInviteReceivedEvent(received.0));
+            }
+            Message::PartyInviteAnswer(accepted) => {
+                commands.trigger(PartyInviteAnsweredEvent {
+                    peer_id: received.0,
+                    accepted,
+                });
+            }
+            Message::PartyUpdate { leader, members } => {
+                commands.trigger(PartyUpdatedEvent {
+                    leader,
+                    members,
+                    peer_id: received.0,
+                });
+            }
+            Message::PartyLeave => {
+                commands.trigger(PartyLeftEvent(received.0));
+            }
+            Message::PartyMemberStatus {
+                health,
+                max_health,
+                chunk_x,
+                chunk_z,
+            } => {
+                commands.trigger(PartyMemberStatusEvent {
+                    health,
+                    max_health,
+                    chunk_x,
+                    chunk_z,
+                    peer_id: received.0,
+                });
+            }
+            Message::PartyJoinFight => {
+                commands.trigger(PartyJoinFightEvent(received.0));
+            }
+            Message::TradeRequest => {
+                commands.trigger(TradeRequestedEvent(received.0));
+            }
+            Message::TradeAccepted => {
+                commands.trigger(TradeAcceptedEvent(received.0));
+            }
+            Message::TradeRefused => {
+                commands.trigger(TradeRefusedEvent(received.0));
+            }
+            Message::TradeOffer { version, offer } => {
+                commands.trigger(TradeOfferChangedEvent {
+                    version,
+                    offer,
+                    peer_id: received.0,
+                });
+            }
+            Message::TradeConfirm {
+                offer_version,
+                partner_offer_version,
+            } => {
+                commands.trigger(TradeConfirmedEvent {
+                    offer_version,
+                    partner_offer_version,
+                    peer_id: received.0,
+                });
+            }
+            Message::TradeCancelled => {
+                commands.trigger(TradeCancelledEvent(received.0));
+            }
+            Message::TradePrepare {
+                offer_version,
+                partner_offer_version,
+            } => {
+                commands.trigger(TradePreparedEvent {
+                    offer_version,
+                    partner_offer_version,
+                    peer_id: received.0,
+                });
+            }
+            Message::TradeReady {
+                offer_version,
+                partner_offer_version,
+            } => {
+                commands.trigger(TradeReadyEvent {
+                    offer_version,
+                    partner_offer_version,
+                    peer_id: received.0,
+                });
+            }
+            Message::TradeCommit {
+                offer_version,
+                partner_offer_version,
+            } => {
+                commands.trigger(TradeCommittedEvent {
+                    offer_version,
+                    partner_offer_version,
+                    peer_id: received.0,
+                });
+            }
+            Message::ServerHello => {
+                commands.trigger(ServerHelloEvent(received.0));
+            }
+            Message::AuthoritativePosition { id, x, z } => {
+                if server_id
+                    .as_ref()
+                    .is_some_and(|server| server.0 == received.0)
+                {
+                    commands.trigger(AuthoritativePositionEvent {
+                        id,
+                        position: UpdatePlayerPosition { x, z },
+                    });
+                } else {
+                    warn!("Ignoring authoritative position from {}", received.0);
+                }
+            }
+            Message::FightStarted {
+                chunk_x,
+                chunk_z,
+                fighters,
+            } => {
+                commands.trigger(FightStartedEvent {
+                    chunk_x,
+                    chunk_z,
+                    fighters,
+                    peer_id: received.0,
+                });
+            }
+            Message::FightOver => {
+                commands.trigger(FightOverEvent(received.0));
+            }
+            Message::SpectateRequest => {
+                commands.trigger(SpectateRequestedEvent(received.0));
+            }
+            Message::SpectateRefused => {
+                commands.trigger(SpectateRefusedEvent(received.0));
+            }
+            Message::SpectateLeave => {
+                commands.trigger(SpectateLeftEvent(received.0));
+            }
+            Message::FightSnapshot(snapshot) => {
+                commands.trigger(FightSnapshotEvent(snapshot, received.0));
+            }
+            Message::FightInput(input) => {
+                commands.trigger(FightInputEvent(input, received.0));
+            }
+            Message::FightSeed { seed, fighters } => {
+                commands.trigger(FightSeedEvent {
+                    seed,
+                    fighters,
+                    peer_id: received.0,
+                });
+            }
+            Message::FightSeedRequest => {
+                commands.trigger(FightSeedRequestedEvent(received.0));
+            }
+            Message::FightRejected { owner } => {
+                if server_id
+                    .as_ref()
+                    .is_some_and(|server| server.0 == received.0)
+                {
+                    commands.trigger(FightRejectedEvent { owner });
+                } else {
+                    warn!("Ignoring fight rejection from {}", received.0);
+                }
+            }
+            Message::ObjectState { x, z, active } => {
+                commands.trigger(ObjectStateEvent {
+                    x,
+                    z,
+                    active,
+                    peer_id: received.0,
+                });
+            }
+            Message::KnownObjectState { x, z, active } => {
+                commands.trigger(KnownObjectStateEvent {
+                    x,
+                    z,
+                    active,
+                    peer_id: received.0,
+                });
+            }
+            Message::NodeHarvested { x, z } => {
+                commands.trigger(NodeHarvestedEvent {
+                    x,
+                    z,
+                    peer_id: received.0,
+                });
+            }
+            _ => {
+                warn!("Received unknown message: {:?}", message);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Event)]
+pub enum SendMessageEvent {
+    ToPeer(Message, PeerId),
+    Broadcast(Message),
+}
+
+pub fn send_queued_messages(trigger: Trigger<SendMessageEvent>, mut socket: ResMut<Socket>) {
+    let event: &SendMessageEvent = trigger.event();
+    match event {
+        SendMessageEvent::ToPeer(message, peer_id) => {
+            let message = bincode::serialize(&message).unwrap().into_boxed_slice();
+            socket.0.send(message, *peer_id);
+        }
+        SendMessageEvent::Broadcast(message) => {
+            let message = bincode::serialize(&message).unwrap().into_boxed_slice();
+            let peers = socket.0.connected_peers();
+            for peer in peers.iter() {
+                socket.0.send(message.clone(), *peer);
+            }
+        }
+    };
+}
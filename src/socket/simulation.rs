@@ -0,0 +1,384 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::{prelude::*, state::app::StatesPlugin, time::TimeUpdateStrategy};
+use bevy_matchbox::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use uuid::Uuid;
+
+use crate::socket::{self, Socket, Transport};
+
+/// How the simulated network treat every packet
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkConditions {
+    /// Delay before any packet is delivered
+    pub latency: Duration,
+    /// Random extra delay (up to this value) added on top of the latency,
+    /// packets sent close to each other can arrive out of order
+    pub jitter: Duration,
+    /// Probability (between 0 and 1) a packet is lost
+    pub loss: f64,
+    /// Probability (between 0 and 1) a packet is delivered twice
+    pub duplication: f64,
+}
+
+#[derive(Debug)]
+struct InFlight {
+    deliver_at: Duration,
+    /// Send order, break ties between packets delivered at the same time
+    sequence: u64,
+    from: PeerId,
+    to: PeerId,
+    packet: Box<[u8]>,
+}
+
+struct Hub {
+    /// Simulated clock, only moved by `SimulatedNetwork::advance`
+    now: Duration,
+    conditions: LinkConditions,
+    rng: StdRng,
+    sequence: u64,
+    /// Amount of sockets ever created, used to give unique ids
+    created: u128,
+    peers: Vec<PeerId>,
+    closed: HashSet<PeerId>,
+    peer_changes: HashMap<PeerId, Vec<(PeerId, PeerState)>>,
+    in_flight: Vec<InFlight>,
+}
+
+impl Hub {
+    fn delay(&mut self) -> Duration {
+        let jitter = self.conditions.jitter.as_secs_f64();
+        let extra = if jitter > 0.0 {
+            self.rng.gen_range(0.0..jitter)
+        } else {
+            0.0
+        };
+        self.conditions.latency + Duration::from_secs_f64(extra)
+    }
+
+    fn send(&mut self, from: PeerId, to: PeerId, packet: Box<[u8]>) {
+        if !self.peers.contains(&to) || self.rng.gen_bool(self.conditions.loss) {
+            return;
+        }
+        let copies = if self.rng.gen_bool(self.conditions.duplication) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let deliver_at = self.now + self.delay();
+            self.sequence += 1;
+            self.in_flight.push(InFlight {
+                deliver_at,
+                sequence: self.sequence,
+                from,
+                to,
+                packet: packet.clone(),
+            });
+        }
+    }
+
+    fn receive(&mut self, peer: PeerId) -> Vec<(PeerId, Box<[u8]>)> {
+        let now = self.now;
+        let (mut arrived, in_flight): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|packet| packet.to == peer && packet.deliver_at <= now);
+        self.in_flight = in_flight;
+        arrived.sort_by_key(|packet| (packet.deliver_at, packet.sequence));
+        arrived
+            .into_iter()
+            .map(|packet| (packet.from, packet.packet))
+            .collect()
+    }
+}
+
+/// In-process network shared by every `SimulatedSocket` created from it
+/// Nothing is delivered until the clock is moved with `advance`, so runs are deterministic for a given seed
+#[derive(Clone)]
+pub struct SimulatedNetwork(Arc<Mutex<Hub>>);
+
+impl SimulatedNetwork {
+    pub fn new(conditions: LinkConditions, seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(Hub {
+            now: Duration::ZERO,
+            conditions,
+            rng: StdRng::seed_from_u64(seed),
+            sequence: 0,
+            created: 0,
+            peers: Vec::new(),
+            closed: HashSet::new(),
+            peer_changes: HashMap::new(),
+            in_flight: Vec::new(),
+        })))
+    }
+
+    /// Change the conditions of the packets sent from now on
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.0.lock().unwrap().conditions = conditions;
+    }
+
+    pub fn now(&self) -> Duration {
+        self.0.lock().unwrap().now
+    }
+
+    pub fn advance(&self, delta: Duration) {
+        self.0.lock().unwrap().now += delta;
+    }
+
+    /// Create a new peer, every already connected peer is notified
+    pub fn connect(&self) -> SimulatedSocket {
+        let mut hub = self.0.lock().unwrap();
+        hub.created += 1;
+        let id = PeerId(Uuid::from_u128(hub.created));
+        let existing = hub.peers.clone();
+        for peer in existing.iter() {
+            hub.peer_changes
+                .entry(*peer)
+                .or_default()
+                .push((id, PeerState::Connected));
+        }
+        hub.peer_changes.insert(
+            id,
+            existing
+                .into_iter()
+                .map(|peer| (peer, PeerState::Connected))
+                .collect(),
+        );
+        hub.peers.push(id);
+        SimulatedSocket {
+            id,
+            network: self.clone(),
+        }
+    }
+
+    /// Drop a peer as if its connection was lost, packets still in flight to it are lost
+    pub fn disconnect(&self, id: PeerId) {
+        let mut hub = self.0.lock().unwrap();
+        if !hub.peers.contains(&id) {
+            return;
+        }
+        hub.peers.retain(|peer| *peer != id);
+        hub.closed.insert(id);
+        hub.peer_changes.remove(&id);
+        hub.in_flight.retain(|packet| packet.to != id);
+        let remaining = hub.peers.clone();
+        for peer in remaining {
+            hub.peer_changes
+                .entry(peer)
+                .or_default()
+                .push((id, PeerState::Disconnected));
+        }
+    }
+}
+
+/// One end of a `SimulatedNetwork`, used in place of the WebRTC socket
+pub struct SimulatedSocket {
+    id: PeerId,
+    network: SimulatedNetwork,
+}
+
+impl Transport for SimulatedSocket {
+    fn id(&mut self) -> Option<PeerId> {
+        Some(self.id)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.network.0.lock().unwrap().closed.contains(&self.id)
+    }
+
+    fn update_peers(&mut self) -> Vec<(PeerId, PeerState)> {
+        self.network
+            .0
+            .lock()
+            .unwrap()
+            .peer_changes
+            .remove(&self.id)
+            .unwrap_or_default()
+    }
+
+    fn receive(&mut self) -> Vec<(PeerId, Box<[u8]>)> {
+        self.network.0.lock().unwrap().receive(self.id)
+    }
+
+    fn send(&mut self, packet: Box<[u8]>, peer: PeerId) {
+        self.network.0.lock().unwrap().send(self.id, peer, packet);
+    }
+
+    fn connected_peers(&self) -> Vec<PeerId> {
+        let hub = self.network.0.lock().unwrap();
+        hub.peers
+            .iter()
+            .copied()
+            .filter(|peer| *peer != self.id)
+            .collect()
+    }
+}
+
+/// Several headless apps talking through the same simulated network, updated in lockstep
+pub struct Harness {
+    pub network: SimulatedNetwork,
+    pub apps: Vec<App>,
+    step: Duration,
+}
+
+impl Harness {
+    /// `step` is both the simulated network and the app clocks advance per update
+    pub fn new(conditions: LinkConditions, seed: u64, step: Duration) -> Self {
+        Self {
+            network: SimulatedNetwork::new(conditions, seed),
+            apps: Vec::new(),
+            step,
+        }
+    }
+
+    /// Create an app connected to the network, `setup` add whatever is under test
+    /// Return the index of the app in `apps` and its peer id
+    pub fn add_app(&mut self, setup: impl FnOnce(&mut App)) -> (usize, PeerId) {
+        let mut socket = self.network.connect();
+        let id = socket.id().unwrap();
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(self.step))
+            .insert_resource(Socket(Box::new(socket)));
+        socket::init_headless(&mut app);
+        setup(&mut app);
+        self.apps.push(app);
+        (self.apps.len() - 1, id)
+    }
+
+    /// Move the network clock by one step, then update every app in order
+    pub fn update(&mut self) {
+        self.network.advance(self.step);
+        for app in self.apps.iter_mut() {
+            app.update();
+        }
+    }
+
+    pub fn update_times(&mut self, count: usize) {
+        for _ in 0..count {
+            self.update();
+        }
+    }
+
+    /// Update until `condition` is true, give up after `max_updates`
+    pub fn update_until(
+        &mut self,
+        max_updates: usize,
+        mut condition: impl FnMut(&mut [App]) -> bool,
+    ) -> bool {
+        for _ in 0..max_updates {
+            self.update();
+            if condition(&mut self.apps) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATENCY: Duration = Duration::from_millis(100);
+
+    fn received(socket: &mut SimulatedSocket) -> Vec<u8> {
+        socket
+            .receive()
+            .into_iter()
+            .map(|(_, packet)| packet[0])
+            .collect()
+    }
+
+    #[test]
+    fn packets_wait_for_the_latency() {
+        let network = SimulatedNetwork::new(
+            LinkConditions {
+                latency: LATENCY,
+                ..default()
+            },
+            0,
+        );
+        let (mut a, mut b) = (network.connect(), network.connect());
+        a.send(Box::new([1]), b.id);
+        network.advance(LATENCY / 2);
+        assert!(received(&mut b).is_empty());
+        network.advance(LATENCY / 2);
+        assert_eq!(network.now(), LATENCY);
+        assert_eq!(received(&mut b), vec![1]);
+        assert!(received(&mut a).is_empty());
+    }
+
+    #[test]
+    fn jitter_can_reorder_packets() {
+        let network = SimulatedNetwork::new(
+            LinkConditions {
+                jitter: LATENCY,
+                ..default()
+            },
+            7,
+        );
+        let (mut a, mut b) = (network.connect(), network.connect());
+        for packet in 0..20 {
+            a.send(Box::new([packet]), b.id);
+        }
+        network.advance(LATENCY);
+        let packets = received(&mut b);
+        assert_eq!(packets.len(), 20);
+        assert!(packets.windows(2).any(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn loss_and_duplication() {
+        let network = SimulatedNetwork::new(
+            LinkConditions {
+                loss: 1.0,
+                ..default()
+            },
+            0,
+        );
+        let (mut a, mut b) = (network.connect(), network.connect());
+        a.send(Box::new([1]), b.id);
+        network.set_conditions(LinkConditions {
+            duplication: 1.0,
+            ..default()
+        });
+        a.send(Box::new([2]), b.id);
+        network.advance(LATENCY);
+        assert_eq!(received(&mut b), vec![2, 2]);
+    }
+
+    #[test]
+    fn disconnection_is_seen_by_both_ends() {
+        let network = SimulatedNetwork::new(LinkConditions::default(), 0);
+        let (mut a, mut b) = (network.connect(), network.connect());
+        assert_eq!(a.update_peers(), vec![(b.id, PeerState::Connected)]);
+        assert_eq!(b.update_peers(), vec![(a.id, PeerState::Connected)]);
+
+        a.send(Box::new([1]), b.id);
+        network.disconnect(b.id);
+        assert!(b.is_closed());
+        assert!(!a.is_closed());
+        assert_eq!(a.update_peers(), vec![(b.id, PeerState::Disconnected)]);
+        assert!(a.connected_peers().is_empty());
+        network.advance(LATENCY);
+        assert!(received(&mut b).is_empty());
+    }
+
+    #[test]
+    fn harness_apps_get_their_id() {
+        let mut harness = Harness::new(LinkConditions::default(), 0, LATENCY);
+        let (first, first_id) = harness.add_app(|_| {});
+        let (second, second_id) = harness.add_app(|_| {});
+        harness.update_times(2);
+        assert_eq!(harness.network.now(), LATENCY * 2);
+        for (index, id) in [(first, first_id), (second, second_id)] {
+            assert_eq!(harness.apps[index].world().resource::<socket::MyId>().0, id);
+        }
+    }
+}
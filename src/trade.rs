@@ -0,0 +1,662 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::PeerId;
+
+use crate::{inventory::Inventory, player::Player, save, socket, ui, GameMode, ProcessArgs};
+
+/// # Currency added or removed from the offer per click
+const CURRENCY_STEP: u64 = 10;
+
+pub fn init(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            update_window.run_if(resource_exists::<Trade>),
+            cleanup_window.run_if(not(resource_exists::<Trade>)),
+        ),
+    )
+    .add_systems(OnExit(GameMode::Exploration), cancel_on_leave)
+    .add_observer(on_request)
+    .add_observer(on_request_received)
+    .add_observer(on_accepted)
+    .add_observer(on_refused)
+    .add_observer(on_offer_changed)
+    .add_observer(on_partner_offer_changed)
+    .add_observer(on_confirm)
+    .add_observer(on_partner_confirmed)
+    .add_observer(on_prepared)
+    .add_observer(on_ready)
+    .add_observer(on_committed)
+    .add_observer(on_cancel)
+    .add_observer(on_partner_cancelled)
+    .add_observer(on_peer_deconection);
+}
+
+/// An ongoing exchange with another player
+/// Both offers are versioned, a confirmation only count for the versions it was made on,
+/// so any change to an offer reset both confirmations
+/// The player who asked for the trade arbitrates it in two phases: once both confirmations
+/// match it asks the partner to prepare, the partner checks its offer is in its inventory
+/// and answers ready, only then the arbiter applies the exchange and sends a commit
+/// the partner applies in turn. Any failure before the commit cancels both sides
+/// Peers can't see each other's inventory, each side only vouches for what it gives
+#[derive(Resource, Debug)]
+pub struct Trade {
+    pub partner: PeerId,
+    /// Whether we asked for the trade, and so are the one committing it
+    arbiter: bool,
+    /// Prepare sent by the arbiter, or ready sent by the partner
+    prepared: bool,
+    pub offer: Inventory,
+    offer_version: u32,
+    pub partner_offer: Inventory,
+    partner_offer_version: u32,
+    pub confirmed: bool,
+    pub partner_confirmed: bool,
+}
+
+impl Trade {
+    fn new(partner: PeerId, arbiter: bool) -> Self {
+        Self {
+            partner,
+            arbiter,
+            prepared: false,
+            offer: Inventory::default(),
+            offer_version: 0,
+            partner_offer: Inventory::default(),
+            partner_offer_version: 0,
+            confirmed: false,
+            partner_confirmed: false,
+        }
+    }
+}
+
+/// Our trade request waiting for an answer
+#[derive(Resource)]
+struct PendingRequest(PeerId);
+
+#[derive(Component)]
+struct TradeWindow;
+
+/// Ask a peer to trade with us
+#[derive(Debug, Event)]
+pub struct RequestEvent(pub PeerId);
+
+/// Local change to our own offer
+#[derive(Debug, Clone, Event)]
+pub enum OfferChangeEvent {
+    AddItem(String),
+    RemoveItem(String),
+    AddCurrency,
+    RemoveCurrency,
+}
+
+#[derive(Debug, Clone, Event)]
+pub struct ConfirmEvent;
+
+#[derive(Debug, Clone, Event)]
+pub struct CancelEvent;
+
+fn inventory(save_data_assets: &Assets<save::Data>, save_handle: &save::ResHandle) -> Inventory {
+    save_data_assets
+        .get(&save_handle.0)
+        .map(|data| data.inventory.clone())
+        .unwrap_or_default()
+}
+
+fn on_request(trigger: Trigger<RequestEvent>, mut commands: Commands, trade: Option<Res<Trade>>) {
+    if trade.is_some() {
+        ui::popup::spawn(&mut commands, "You are already trading".to_string());
+        return;
+    }
+    info!("Sending trade request to {}", trigger.0);
+    commands.insert_resource(PendingRequest(trigger.0));
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::TradeRequest,
+        trigger.0,
+    ));
+}
+
+fn on_request_received(
+    trigger: Trigger<socket::TradeRequestedEvent>,
+    mut commands: Commands,
+    trade: Option<Res<Trade>>,
+    game_mode: Res<State<GameMode>>,
+    q_player: Query<(&Player, &socket::Id)>,
+) {
+    let sender = trigger.0;
+    if trade.is_some() || *game_mode.get() != GameMode::Exploration {
+        commands.trigger(socket::SendMessageEvent::ToPeer(
+            socket::Message::TradeRefused,
+            sender,
+        ));
+        return;
+    }
+    let Some((player, _)) = q_player.iter().find(|(_, id)| id.0 == sender) else {
+        return;
+    };
+    ui::popup::spawn_with_choices(
+        &mut commands,
+        format!("{} want to trade with you", player.name),
+        "Accept",
+        "Decline",
+    )
+    .observe(
+        move |trigger: Trigger<ui::popup::ButtonEvent>, mut commands: Commands| {
+            let accepted = matches!(trigger.event(), ui::popup::ButtonEvent::Primary);
+            let answer = if accepted {
+                socket::Message::TradeAccepted
+            } else {
+                socket::Message::TradeRefused
+            };
+            commands.trigger(socket::SendMessageEvent::ToPeer(answer, sender));
+            if accepted {
+                commands.insert_resource(Trade::new(sender, false));
+            }
+        },
+    );
+}
+
+fn on_accepted(
+    trigger: Trigger<socket::TradeAcceptedEvent>,
+    mut commands: Commands,
+    pending: Option<Res<PendingRequest>>,
+) {
+    if !pending.is_some_and(|pending| pending.0 == trigger.0) {
+        warn!("Unexpected trade acceptance from {}", trigger.0);
+        return;
+    }
+    info!("Trade accepted by {}", trigger.0);
+    commands.remove_resource::<PendingRequest>();
+    commands.insert_resource(Trade::new(trigger.0, true));
+}
+
+fn on_refused(
+    trigger: Trigger<socket::TradeRefusedEvent>,
+    mut commands: Commands,
+    pending: Option<Res<PendingRequest>>,
+) {
+    if !pending.is_some_and(|pending| pending.0 == trigger.0) {
+        return;
+    }
+    commands.remove_resource::<PendingRequest>();
+    ui::popup::spawn(&mut commands, "Trade refused".to_string());
+}
+
+fn on_offer_changed(
+    trigger: Trigger<OfferChangeEvent>,
+    mut commands: Commands,
+    mut trade: ResMut<Trade>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    // A confirmation is binding until the partner changes their offer
+    if trade.confirmed {
+        return;
+    }
+    let owned = inventory(&save_data_assets, &save_handle);
+    let mut offer = trade.offer.clone();
+    match trigger.event() {
+        OfferChangeEvent::AddItem(item) => offer.add(item.clone(), 1),
+        OfferChangeEvent::RemoveItem(item) => {
+            offer.remove(item, 1);
+        }
+        OfferChangeEvent::AddCurrency => {
+            offer.currency = (offer.currency + CURRENCY_STEP).min(owned.currency)
+        }
+        OfferChangeEvent::RemoveCurrency => {
+            offer.currency = offer.currency.saturating_sub(CURRENCY_STEP)
+        }
+    }
+    if offer == trade.offer || !offer.is_subset_of(&owned) {
+        return;
+    }
+    trade.offer = offer;
+    trade.offer_version += 1;
+    trade.confirmed = false;
+    trade.partner_confirmed = false;
+    trade.prepared = false;
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::TradeOffer {
+            version: trade.offer_version,
+            offer: trade.offer.clone(),
+        },
+        trade.partner,
+    ));
+}
+
+fn on_partner_offer_changed(
+    trigger: Trigger<socket::TradeOfferChangedEvent>,
+    trade: Option<ResMut<Trade>>,
+) {
+    let Some(mut trade) = trade else {
+        return;
+    };
+    if trade.partner != trigger.peer_id || trigger.version <= trade.partner_offer_version {
+        return;
+    }
+    trade.partner_offer = trigger.offer.clone();
+    trade.partner_offer_version = trigger.version;
+    trade.confirmed = false;
+    trade.partner_confirmed = false;
+    trade.prepared = false;
+}
+
+fn on_confirm(
+    _trigger: Trigger<ConfirmEvent>,
+    mut commands: Commands,
+    mut trade: ResMut<Trade>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    if trade.confirmed {
+        return;
+    }
+    if !trade
+        .offer
+        .is_subset_of(&inventory(&save_data_assets, &save_handle))
+    {
+        ui::popup::spawn(
+            &mut commands,
+            "Your offer is no longer in your inventory".to_string(),
+        );
+        return;
+    }
+    trade.confirmed = true;
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::TradeConfirm {
+            offer_version: trade.offer_version,
+            partner_offer_version: trade.partner_offer_version,
+        },
+        trade.partner,
+    ));
+    try_prepare(&mut commands, &mut trade, &save_data_assets, &save_handle);
+}
+
+fn on_partner_confirmed(
+    trigger: Trigger<socket::TradeConfirmedEvent>,
+    mut commands: Commands,
+    trade: Option<ResMut<Trade>>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    let Some(mut trade) = trade else {
+        return;
+    };
+    // A confirmation made on outdated offers doesn't count
+    if trade.partner != trigger.peer_id
+        || trigger.offer_version != trade.partner_offer_version
+        || trigger.partner_offer_version != trade.offer_version
+    {
+        return;
+    }
+    trade.partner_confirmed = true;
+    try_prepare(&mut commands, &mut trade, &save_data_assets, &save_handle);
+}
+
+/// Whether a prepare, ready or commit from the partner is bound to the versions we confirmed
+fn matches_confirmation(trade: &Trade, offer_version: u32, partner_offer_version: u32) -> bool {
+    trade.confirmed
+        && offer_version == trade.partner_offer_version
+        && partner_offer_version == trade.offer_version
+}
+
+/// Run by the arbiter once both confirmations are bound to the same offer versions
+/// Nothing is applied yet, the partner has to vouch for its offer first
+fn try_prepare(
+    commands: &mut Commands,
+    trade: &mut Trade,
+    save_data_assets: &Assets<save::Data>,
+    save_handle: &save::ResHandle,
+) {
+    if !trade.arbiter || !trade.confirmed || !trade.partner_confirmed || trade.prepared {
+        return;
+    }
+    if !trade
+        .offer
+        .is_subset_of(&inventory(save_data_assets, save_handle))
+    {
+        error!("Offer is no longer in the inventory, cancelling trade");
+        commands.trigger(CancelEvent);
+        return;
+    }
+    trade.prepared = true;
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::TradePrepare {
+            offer_version: trade.offer_version,
+            partner_offer_version: trade.partner_offer_version,
+        },
+        trade.partner,
+    ));
+}
+
+/// Check our side of the exchange the arbiter is about to apply
+/// Our offer is locked since our confirmation, answering ready binds us to it
+fn on_prepared(
+    trigger: Trigger<socket::TradePreparedEvent>,
+    mut commands: Commands,
+    trade: Option<ResMut<Trade>>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    let Some(mut trade) = trade else {
+        return;
+    };
+    if trade.arbiter || trade.partner != trigger.peer_id {
+        return;
+    }
+    if !matches_confirmation(&trade, trigger.offer_version, trigger.partner_offer_version) {
+        error!(
+            "Prepare from {} doesn't match our confirmation, cancelling trade",
+            trigger.peer_id
+        );
+        commands.trigger(CancelEvent);
+        return;
+    }
+    if !trade
+        .offer
+        .is_subset_of(&inventory(&save_data_assets, &save_handle))
+    {
+        error!("Offer is no longer in the inventory, cancelling trade");
+        commands.trigger(CancelEvent);
+        return;
+    }
+    trade.prepared = true;
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::TradeReady {
+            offer_version: trade.offer_version,
+            partner_offer_version: trade.partner_offer_version,
+        },
+        trade.partner,
+    ));
+}
+
+/// The partner vouched for its offer, apply the exchange and let it apply its side
+fn on_ready(
+    trigger: Trigger<socket::TradeReadyEvent>,
+    mut commands: Commands,
+    trade: Option<Res<Trade>>,
+    mut save_data_assets: ResMut<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+    args: Res<ProcessArgs>,
+) {
+    let Some(trade) = trade else {
+        return;
+    };
+    if !trade.arbiter || trade.partner != trigger.peer_id {
+        return;
+    }
+    if !trade.prepared
+        || !trade.partner_confirmed
+        || !matches_confirmation(&trade, trigger.offer_version, trigger.partner_offer_version)
+    {
+        error!(
+            "Ready from {} doesn't match our prepare, cancelling trade",
+            trigger.peer_id
+        );
+        commands.trigger(CancelEvent);
+        return;
+    }
+    let Some(save_data) = save_data_assets.get_mut(&save_handle.0) else {
+        return;
+    };
+    if !save_data
+        .inventory
+        .exchange(&trade.offer, &trade.partner_offer)
+    {
+        error!("Offer is no longer in the inventory, cancelling trade");
+        commands.trigger(CancelEvent);
+        return;
+    }
+    save::persist(save_data, &args);
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::TradeCommit {
+            offer_version: trade.offer_version,
+            partner_offer_version: trade.partner_offer_version,
+        },
+        trade.partner,
+    ));
+    info!("Trade with {} completed", trade.partner);
+    commands.remove_resource::<Trade>();
+    ui::popup::spawn(&mut commands, "Trade completed".to_string());
+}
+
+/// Apply the exchange committed by the arbiter
+/// It can only commit versions we answered ready to, and our offer is locked since then
+fn on_committed(
+    trigger: Trigger<socket::TradeCommittedEvent>,
+    mut commands: Commands,
+    trade: Option<Res<Trade>>,
+    mut save_data_assets: ResMut<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+    args: Res<ProcessArgs>,
+) {
+    let Some(trade) = trade else {
+        return;
+    };
+    if trade.arbiter || trade.partner != trigger.peer_id {
+        return;
+    }
+    if !trade.prepared
+        || !matches_confirmation(&trade, trigger.offer_version, trigger.partner_offer_version)
+    {
+        error!(
+            "Commit from {} doesn't match our confirmation, cancelling trade",
+            trigger.peer_id
+        );
+        commands.trigger(CancelEvent);
+        return;
+    }
+    let Some(save_data) = save_data_assets.get_mut(&save_handle.0) else {
+        return;
+    };
+    if !save_data
+        .inventory
+        .exchange(&trade.offer, &trade.partner_offer)
+    {
+        error!(
+            "Offer confirmed to {} is no longer in the inventory, cancelling trade",
+            trade.partner
+        );
+        commands.trigger(CancelEvent);
+        return;
+    }
+    save::persist(save_data, &args);
+    info!("Trade with {} completed", trade.partner);
+    commands.remove_resource::<Trade>();
+    ui::popup::spawn(&mut commands, "Trade completed".to_string());
+}
+
+fn on_cancel(_trigger: Trigger<CancelEvent>, mut commands: Commands, trade: Option<Res<Trade>>) {
+    let Some(trade) = trade else {
+        return;
+    };
+    info!("Trade cancelled");
+    commands.trigger(socket::SendMessageEvent::ToPeer(
+        socket::Message::TradeCancelled,
+        trade.partner,
+    ));
+    commands.remove_resource::<Trade>();
+}
+
+fn on_partner_cancelled(
+    trigger: Trigger<socket::TradeCancelledEvent>,
+    mut commands: Commands,
+    trade: Option<Res<Trade>>,
+) {
+    if !trade.is_some_and(|trade| trade.partner == trigger.0) {
+        return;
+    }
+    commands.remove_resource::<Trade>();
+    ui::popup::spawn(&mut commands, "Trade cancelled".to_string());
+}
+
+fn on_peer_deconection(
+    trigger: Trigger<socket::PeerDeconectionEvent>,
+    mut commands: Commands,
+    trade: Option<Res<Trade>>,
+) {
+    if trade.is_some_and(|trade| trade.partner == trigger.0) {
+        commands.remove_resource::<Trade>();
+    }
+}
+
+fn cancel_on_leave(mut commands: Commands, trade: Option<Res<Trade>>) {
+    if trade.is_some() {
+        commands.trigger(CancelEvent);
+    }
+}
+
+fn spawn_label(parent: &mut ChildBuilder, text: impl Into<String>) {
+    parent.spawn((
+        Text::new(text.into()),
+        TextColor(Color::hsl(242.0, 0.15, 0.57)),
+        TextFont {
+            font_size: 10.0,
+            ..Default::default()
+        },
+    ));
+}
+
+fn spawn_button<E: Event + Clone>(
+    parent: &mut ChildBuilder,
+    text: impl Into<String>,
+    color: Color,
+    event: E,
+) {
+    parent
+        .spawn((
+            Text::new(text.into()),
+            TextColor(Color::hsl(190.0, 0.86, 0.97)),
+            TextFont {
+                font_size: 10.0,
+                ..Default::default()
+            },
+            BackgroundColor(color),
+        ))
+        .observe(
+            move |_trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+                commands.trigger(event.clone());
+            },
+        );
+}
+
+fn update_window(
+    mut commands: Commands,
+    trade: Option<Res<Trade>>,
+    q_window: Query<Entity, With<TradeWindow>>,
+    q_players: Query<(&Player, &socket::Id)>,
+    save_data_assets: Res<Assets<save::Data>>,
+    save_handle: Res<save::ResHandle>,
+) {
+    let Some(trade) = trade else {
+        return;
+    };
+    if !trade.is_changed() && !q_window.is_empty() {
+        return;
+    }
+    for entity in q_window.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let partner_name = q_players
+        .iter()
+        .find(|(_, id)| id.0 == trade.partner)
+        .map(|(player, _)| player.name.clone())
+        .unwrap_or_else(|| trade.partner.to_string());
+    let owned = inventory(&save_data_assets, &save_handle);
+    let status = |confirmed: bool| if confirmed { " (confirmed)" } else { "" };
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                display: Display::Flex,
+                flex_direction: FlexDirection::Row,
+                top: Val::Percent(25.0),
+                left: Val::Percent(30.0),
+                width: Val::Percent(40.0),
+                padding: UiRect::all(Val::Px(5.0)),
+                column_gap: Val::Px(10.0),
+                ..default()
+            },
+            BorderRadius::all(Val::Px(5.0)),
+            BackgroundColor(Color::hsl(246.0, 0.21, 0.29)),
+            ZIndex(50),
+            TradeWindow,
+        ))
+        .with_children(|parent| {
+            let column = Node {
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                flex_grow: 1.0,
+                ..default()
+            };
+            // Inventory, click to add to the offer
+            parent.spawn(column.clone()).with_children(|parent| {
+                spawn_label(parent, format!("Inventory ({})", owned.currency));
+                for (item, amount) in owned.items.iter() {
+                    let left = amount - trade.offer.count(item);
+                    if left == 0 {
+                        continue;
+                    }
+                    spawn_button(
+                        parent,
+                        format!("{} x{}", item, left),
+                        Color::hsl(209.0, 0.59, 0.61),
+                        OfferChangeEvent::AddItem(item.clone()),
+                    );
+                }
+            });
+            // Our offer, click to remove from it
+            parent.spawn(column.clone()).with_children(|parent| {
+                spawn_label(parent, format!("Your offer{}", status(trade.confirmed)));
+                spawn_label(parent, format!("Currency: {}", trade.offer.currency));
+                spawn_button(
+                    parent,
+                    format!("+{}", CURRENCY_STEP),
+                    Color::hsl(209.0, 0.59, 0.61),
+                    OfferChangeEvent::AddCurrency,
+                );
+                spawn_button(
+                    parent,
+                    format!("-{}", CURRENCY_STEP),
+                    Color::hsl(209.0, 0.59, 0.61),
+                    OfferChangeEvent::RemoveCurrency,
+                );
+                for (item, amount) in trade.offer.items.iter() {
+                    spawn_button(
+                        parent,
+                        format!("{} x{}", item, amount),
+                        Color::hsl(111.0, 0.52, 0.65),
+                        OfferChangeEvent::RemoveItem(item.clone()),
+                    );
+                }
+            });
+            // Partner offer, read only
+            parent.spawn(column).with_children(|parent| {
+                spawn_label(
+                    parent,
+                    format!("{} offer{}", partner_name, status(trade.partner_confirmed)),
+                );
+                spawn_label(
+                    parent,
+                    format!("Currency: {}", trade.partner_offer.currency),
+                );
+                for (item, amount) in trade.partner_offer.items.iter() {
+                    spawn_label(parent, format!("{} x{}", item, amount));
+                }
+                spawn_button(
+                    parent,
+                    "Confirm",
+                    Color::hsl(209.0, 0.59, 0.61),
+                    ConfirmEvent,
+                );
+                spawn_button(parent, "Cancel", Color::hsl(360.0, 0.5, 0.5), CancelEvent);
+            });
+        });
+}
+
+fn cleanup_window(mut commands: Commands, q_window: Query<Entity, With<TradeWindow>>) {
+    for entity in q_window.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,4 +1,599 @@
-fn init(app: &mut App) {}
+use std::collections::{HashMap, VecDeque};
+
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        mouse::{MouseScrollUnit, MouseWheel},
+        ButtonState,
+    },
+    prelude::*,
+};
+use bevy_matchbox::prelude::PeerId;
+
+use crate::{
+    combat, map,
+    player::{MainPlayer, Player},
+    socket, GameMode,
+};
+
+/// # Maximum amount of characters in a single message
+pub const MAX_MESSAGE_LENGTH: usize = 256;
+/// # Amount of lines kept in the scrollback
+pub const MAX_HISTORY: usize = 100;
+/// # Amount of messages a player can send within `RATE_LIMIT_WINDOW`
+pub const RATE_LIMIT_COUNT: usize = 5;
+/// # Time window (in seconds) used by the rate limiter
+pub const RATE_LIMIT_WINDOW: f32 = 10.0;
+
+pub fn init(app: &mut App) {
+    app.init_resource::<Focus>()
+        .init_resource::<RateLimiter>()
+        .init_resource::<History>()
+        .add_systems(
+            Update,
+            (
+                toggle_focus,
+                type_message,
+                scroll_history,
+                update_history_text,
+            )
+                .chain(),
+        )
+        .add_observer(send_chat)
+        .add_observer(on_chat_message_received)
+        .add_observer(on_chat_line_added);
+}
+
+#[derive(Component)]
+pub struct ChatNode;
+
+#[derive(Component)]
+struct ChatHistory;
 
 #[derive(Component)]
-struct ChatNode;
+struct ChatInput;
+
+/// Whether or not the chat input is capturing the keyboard
+#[derive(Resource, Default)]
+pub struct Focus {
+    pub typing: bool,
+    pub buffer: String,
+}
+
+/// Run condition used to mute gameplay shortcuts while typing
+pub fn is_typing(focus: Res<Focus>) -> bool {
+    focus.typing
+}
+
+/// Lines displayed in the chat box, oldest first
+#[derive(Resource, Default)]
+struct History(VecDeque<String>);
+
+/// Keep track of the last messages sent (and received per peer) to limit spam
+#[derive(Resource, Default)]
+struct RateLimiter {
+    sent: VecDeque<f32>,
+    received: HashMap<PeerId, VecDeque<f32>>,
+}
+
+impl RateLimiter {
+    fn allow(history: &mut VecDeque<f32>, now: f32) -> bool {
+        while let Some(oldest) = history.front() {
+            if now - oldest > RATE_LIMIT_WINDOW {
+                history.pop_front();
+            } else {
+                break;
+            }
+        }
+        if history.len() >= RATE_LIMIT_COUNT {
+            return false;
+        }
+        history.push_back(now);
+        true
+    }
+}
+
+/// A line to append to the chat scrollback
+#[derive(Debug, Event)]
+pub struct ChatLineEvent {
+    pub channel: socket::ChatChannel,
+    pub author: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Local,
+    Global,
+    Whisper(String),
+    Team,
+    Spectator,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CommandError {
+    Empty,
+    TooLong,
+    MissingRecipient,
+    Unknown(String),
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Empty => write!(f, "Empty message"),
+            CommandError::TooLong => {
+                write!(f, "Message is too long ({} max)", MAX_MESSAGE_LENGTH)
+            }
+            CommandError::MissingRecipient => write!(f, "Usage: /w <name> <message>"),
+            CommandError::Unknown(command) => write!(f, "Unknown command: /{}", command),
+        }
+    }
+}
+
+/// Split the raw input into its destination and content
+/// - `/w <name> <message>` whisper to a player
+/// - `/g <message>` global channel
+/// - `/t <message>` fight/team channel
+/// - `/s <message>` spectators of the watched fight
+/// - `/l <message>` or no command, local chunk channel
+fn parse_input(input: &str) -> Result<(Target, String), CommandError> {
+    let input = input.trim();
+    let (target, text) = match input.strip_prefix('/') {
+        Some(command_line) => {
+            let (command, rest) = command_line
+                .split_once(char::is_whitespace)
+                .unwrap_or((command_line, ""));
+            match command {
+                "w" | "whisper" => {
+                    let (name, text) = rest
+                        .trim_start()
+                        .split_once(char::is_whitespace)
+                        .ok_or(CommandError::MissingRecipient)?;
+                    (Target::Whisper(name.to_string()), text)
+                }
+                "g" | "global" => (Target::Global, rest),
+                "t" | "team" => (Target::Team, rest),
+                "s" | "spectator" => (Target::Spectator, rest),
+                "l" | "local" => (Target::Local, rest),
+                _ => return Err(CommandError::Unknown(command.to_string())),
+            }
+        }
+        None => (Target::Local, input),
+    };
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(CommandError::Empty);
+    }
+    if text.chars().count() > MAX_MESSAGE_LENGTH {
+        return Err(CommandError::TooLong);
+    }
+    Ok((target, text.to_string()))
+}
+
+/// Fill the chat box reserved in the HUD
+pub fn spawn(commands: &mut Commands, parent: Entity) {
+    commands
+        .entity(parent)
+        .insert(ChatNode)
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        display: Display::Flex,
+                        flex_direction: FlexDirection::Column,
+                        flex_grow: 1.0,
+                        width: Val::Percent(100.0),
+                        overflow: Overflow::scroll_y(),
+                        ..default()
+                    },
+                    ScrollPosition::default(),
+                    ChatHistory,
+                ))
+                .with_child((
+                    Text::default(),
+                    TextColor(Color::hsl(242.0, 0.15, 0.57)),
+                    TextLayout {
+                        justify: JustifyText::Left,
+                        linebreak: LineBreak::WordBoundary,
+                    },
+                    TextFont {
+                        font_size: 10.0,
+                        ..Default::default()
+                    },
+                ));
+            parent.spawn((
+                Node {
+                    width: Val::Percent(100.0),
+                    min_height: Val::Px(14.0),
+                    border: UiRect::top(Val::Px(1.0)),
+                    ..default()
+                },
+                BorderColor(Color::hsl(242.0, 0.15, 0.57)),
+                Text::default(),
+                TextColor(Color::hsl(190.0, 0.86, 0.97)),
+                TextFont {
+                    font_size: 10.0,
+                    ..Default::default()
+                },
+                ChatInput,
+            ));
+        });
+}
+
+fn toggle_focus(
+    mut commands: Commands,
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut focus: ResMut<Focus>,
+    mut rate_limiter: ResMut<RateLimiter>,
+    time: Res<Time>,
+) {
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match (&event.logical_key, focus.typing) {
+            (Key::Enter, false) => focus.typing = true,
+            (Key::Escape, true) => {
+                focus.typing = false;
+                focus.buffer.clear();
+            }
+            (Key::Enter, true) => {
+                focus.typing = false;
+                let input = std::mem::take(&mut focus.buffer);
+                if input.trim().is_empty() {
+                    continue;
+                }
+                match parse_input(&input) {
+                    Ok((target, text)) => {
+                        if !RateLimiter::allow(&mut rate_limiter.sent, time.elapsed_secs()) {
+                            commands.trigger(ChatLineEvent {
+                                channel: socket::ChatChannel::System,
+                                author: None,
+                                text: "You are sending messages too fast".to_string(),
+                            });
+                            continue;
+                        }
+                        commands.trigger(SendChatEvent { target, text });
+                    }
+                    Err(error) => commands.trigger(ChatLineEvent {
+                        channel: socket::ChatChannel::System,
+                        author: None,
+                        text: error.to_string(),
+                    }),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn type_message(mut keyboard_events: EventReader<KeyboardInput>, mut focus: ResMut<Focus>) {
+    // Keys pressed before the input got the focus were meant for the game
+    if !focus.typing {
+        keyboard_events.clear();
+        return;
+    }
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Backspace => {
+                focus.buffer.pop();
+            }
+            Key::Space => focus.buffer.push(' '),
+            Key::Character(characters) => {
+                for character in characters.chars().filter(|c| !c.is_control()) {
+                    if focus.buffer.chars().count() < MAX_MESSAGE_LENGTH + 16 {
+                        focus.buffer.push(character);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Event)]
+struct SendChatEvent {
+    target: Target,
+    text: String,
+}
+
+fn send_chat(
+    trigger: Trigger<SendChatEvent>,
+    mut commands: Commands,
+    q_main_player: Query<(&Transform, &Player), With<MainPlayer>>,
+    q_players: Query<(&Transform, &Player, &socket::Id), Without<MainPlayer>>,
+    game_mode: Res<State<GameMode>>,
+    my_id: Option<Res<socket::MyId>>,
+    spectator: Option<Res<combat::Spectator>>,
+    owner: Option<Res<combat::Owner>>,
+    allies: Option<Res<combat::Allies>>,
+    fighters: Option<Res<combat::Fighters>>,
+) {
+    let Ok((transform, main_player)) = q_main_player.get_single() else {
+        return;
+    };
+    let text = trigger.text.clone();
+    // Only the players the channel is meant for receive the message, `None` for everyone
+    let (channel, recipients) = match &trigger.target {
+        Target::Local => {
+            let chunk = map::ChunkCoordinate::from_world(transform.translation);
+            let peers = q_players
+                .iter()
+                .filter(|(transform, _, _)| {
+                    map::ChunkCoordinate::from_world(transform.translation) == chunk
+                })
+                .map(|(_, _, id)| id.0)
+                .collect();
+            (
+                socket::ChatChannel::Local {
+                    chunk_x: chunk.x,
+                    chunk_z: chunk.z,
+                },
+                Some(peers),
+            )
+        }
+        Target::Global => (socket::ChatChannel::Global, None),
+        Target::Team => {
+            if *game_mode.get() != GameMode::Combat || spectator.is_some() {
+                commands.trigger(ChatLineEvent {
+                    channel: socket::ChatChannel::System,
+                    author: None,
+                    text: "You are not in a fight".to_string(),
+                });
+                return;
+            }
+            // The allies who joined the fight, opponents never get it
+            let peers = allies
+                .zip(fighters)
+                .map(|(allies, fighters)| {
+                    allies
+                        .0
+                        .iter()
+                        .filter(|ally| {
+                            fighters.0.contains(ally)
+                                && my_id.as_ref().is_some_and(|my_id| my_id.0 != **ally)
+                        })
+                        .copied()
+                        .collect()
+                })
+                .unwrap_or_default();
+            (socket::ChatChannel::Team, Some(peers))
+        }
+        Target::Spectator => {
+            let (Some(_), Some(owner)) = (spectator, owner) else {
+                commands.trigger(ChatLineEvent {
+                    channel: socket::ChatChannel::System,
+                    author: None,
+                    text: "You are not watching a fight".to_string(),
+                });
+                return;
+            };
+            (socket::ChatChannel::Spectator { owner: owner.0 }, None)
+        }
+        Target::Whisper(name) => {
+            let Some((_, _, id)) = q_players.iter().find(|(_, player, _)| player.name == *name)
+            else {
+                commands.trigger(ChatLineEvent {
+                    channel: socket::ChatChannel::System,
+                    author: None,
+                    text: format!("{} is not connected", name),
+                });
+                return;
+            };
+            commands.trigger(socket::SendMessageEvent::ToPeer(
+                socket::Message::Chat {
+                    channel: socket::ChatChannel::Private,
+                    text: text.clone(),
+                },
+                id.0,
+            ));
+            commands.trigger(ChatLineEvent {
+                channel: socket::ChatChannel::Private,
+                author: Some(format!("To {}", name)),
+                text,
+            });
+            return;
+        }
+    };
+    let message = || socket::Message::Chat {
+        channel: channel.clone(),
+        text: text.clone(),
+    };
+    match recipients {
+        Some(peers) => {
+            for peer in peers {
+                commands.trigger(socket::SendMessageEvent::ToPeer(message(), peer));
+            }
+        }
+        None => commands.trigger(socket::SendMessageEvent::Broadcast(message())),
+    }
+    commands.trigger(ChatLineEvent {
+        channel,
+        author: Some(main_player.name.clone()),
+        text,
+    });
+}
+
+fn on_chat_message_received(
+    trigger: Trigger<socket::ChatMessageReceivedEvent>,
+    mut commands: Commands,
+    mut rate_limiter: ResMut<RateLimiter>,
+    time: Res<Time>,
+    q_main_player: Query<&Transform, With<MainPlayer>>,
+    q_players: Query<(&Player, &socket::Id)>,
+    game_mode: Res<State<GameMode>>,
+    allies: Option<Res<combat::Allies>>,
+    spectator: Option<Res<combat::Spectator>>,
+    owner: Option<Res<combat::Owner>>,
+) {
+    let Some((sender, _)) = q_players.iter().find(|(_, id)| id.0 == trigger.peer_id) else {
+        warn!("Chat message from unknown peer {}", trigger.peer_id);
+        return;
+    };
+    let history = rate_limiter.received.entry(trigger.peer_id).or_default();
+    if !RateLimiter::allow(history, time.elapsed_secs()) {
+        warn!("Dropping chat message from {}: rate limited", sender.name);
+        return;
+    }
+    if trigger.text.chars().count() > MAX_MESSAGE_LENGTH {
+        warn!("Dropping chat message from {}: too long", sender.name);
+        return;
+    }
+    let accepted = match &trigger.channel {
+        socket::ChatChannel::Local { chunk_x, chunk_z } => q_main_player
+            .get_single()
+            .map(|transform| {
+                map::ChunkCoordinate::from_world(transform.translation)
+                    == map::ChunkCoordinate::new(*chunk_x, *chunk_z)
+            })
+            .unwrap_or(false),
+        socket::ChatChannel::Global | socket::ChatChannel::Private => true,
+        socket::ChatChannel::Team => {
+            *game_mode.get() == GameMode::Combat
+                && spectator.is_none()
+                && allies.is_some_and(|allies| allies.0.contains(&trigger.peer_id))
+        }
+        socket::ChatChannel::Spectator { owner: fight } => {
+            spectator.is_some() && owner.is_some_and(|owner| owner.0 == *fight)
+        }
+        socket::ChatChannel::System => false,
+    };
+    if !accepted {
+        return;
+    }
+    let author = match trigger.channel {
+        socket::ChatChannel::Private => format!("From {}", sender.name),
+        _ => sender.name.clone(),
+    };
+    commands.trigger(ChatLineEvent {
+        channel: trigger.channel.clone(),
+        author: Some(author),
+        text: trigger.text.clone(),
+    });
+}
+
+fn on_chat_line_added(trigger: Trigger<ChatLineEvent>, mut history: ResMut<History>) {
+    let prefix = match trigger.channel {
+        socket::ChatChannel::Local { .. } => "",
+        socket::ChatChannel::Global => "[Global] ",
+        socket::ChatChannel::Private => "[Private] ",
+        socket::ChatChannel::Team => "[Team] ",
+        socket::ChatChannel::Spectator { .. } => "[Spectators] ",
+        socket::ChatChannel::System => "[System] ",
+    };
+    let line = match &trigger.author {
+        Some(author) => format!("{}{}: {}", prefix, author, trigger.text),
+        None => format!("{}{}", prefix, trigger.text),
+    };
+    history.0.push_back(line);
+    while history.0.len() > MAX_HISTORY {
+        history.0.pop_front();
+    }
+}
+
+fn update_history_text(
+    history: Res<History>,
+    focus: Res<Focus>,
+    mut q_history: Query<(&Children, &mut ScrollPosition), With<ChatHistory>>,
+    mut q_text: Query<&mut Text, Without<ChatInput>>,
+    mut q_input: Query<&mut Text, With<ChatInput>>,
+) {
+    if focus.is_changed() {
+        for mut text in q_input.iter_mut() {
+            text.0 = if focus.typing {
+                format!("> {}_", focus.buffer)
+            } else {
+                String::new()
+            };
+        }
+    }
+    if !history.is_changed() {
+        return;
+    }
+    for (children, mut scroll_position) in q_history.iter_mut() {
+        for child in children.iter() {
+            if let Ok(mut text) = q_text.get_mut(*child) {
+                text.0 = history.0.iter().cloned().collect::<Vec<_>>().join("\n");
+            }
+        }
+        // Stick to the latest message
+        scroll_position.offset_y = f32::MAX;
+    }
+}
+
+fn scroll_history(
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    mut q_history: Query<&mut ScrollPosition, With<ChatHistory>>,
+) {
+    for event in mouse_wheel_events.read() {
+        let delta = match event.unit {
+            MouseScrollUnit::Line => event.y * 12.0,
+            MouseScrollUnit::Pixel => event.y,
+        };
+        for mut scroll_position in q_history.iter_mut() {
+            scroll_position.offset_y = (scroll_position.offset_y - delta).max(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_goes_to_the_chunk() {
+        assert_eq!(
+            parse_input("  hello there "),
+            Ok((Target::Local, "hello there".to_string()))
+        );
+    }
+
+    #[test]
+    fn commands_pick_the_channel() {
+        assert_eq!(
+            parse_input("/g hi all"),
+            Ok((Target::Global, "hi all".to_string()))
+        );
+        assert_eq!(
+            parse_input("/team go left"),
+            Ok((Target::Team, "go left".to_string()))
+        );
+        assert_eq!(
+            parse_input("/s nice"),
+            Ok((Target::Spectator, "nice".to_string()))
+        );
+        assert_eq!(
+            parse_input("/local  here"),
+            Ok((Target::Local, "here".to_string()))
+        );
+    }
+
+    #[test]
+    fn whisper_needs_a_recipient_and_a_message() {
+        assert_eq!(
+            parse_input("/w Bob  see you"),
+            Ok((Target::Whisper("Bob".to_string()), "see you".to_string()))
+        );
+        assert_eq!(parse_input("/w Bob"), Err(CommandError::MissingRecipient));
+        assert_eq!(parse_input("/w"), Err(CommandError::MissingRecipient));
+    }
+
+    #[test]
+    fn rejects_empty_unknown_and_long_messages() {
+        assert_eq!(parse_input("/g   "), Err(CommandError::Empty));
+        assert_eq!(
+            parse_input("/dance now"),
+            Err(CommandError::Unknown("dance".to_string()))
+        );
+        let longest = "a".repeat(MAX_MESSAGE_LENGTH);
+        assert_eq!(parse_input(&longest), Ok((Target::Local, longest.clone())));
+        assert_eq!(
+            parse_input(&format!("{}a", longest)),
+            Err(CommandError::TooLong)
+        );
+    }
+}
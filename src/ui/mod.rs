@@ -3,12 +3,20 @@ use context_menu::spawn;
 
 use crate::{exploration, map, player, GameMode};
 
+pub mod chat;
 pub mod context_menu;
 pub mod input_catcher;
 pub mod popup;
 
-pub fn init(app: &mut App) {}
+pub fn init(app: &mut App) {
+    chat::init(app);
+    app.add_systems(
+        OnEnter(GameMode::Exploration),
+        setup.run_if(not(any_with_component::<chat::ChatNode>)),
+    );
+}
 
+/// Spawn the HUD the first time the player enters the world, it stays up afterwards
 fn setup(mut commands: Commands, player_info: Res<player::Info>) {
     let hud = commands
         .spawn((
@@ -25,16 +33,20 @@ fn setup(mut commands: Commands, player_info: Res<player::Info>) {
         .id();
 
     // Chat box
-    commands
+    let chat_box = commands
         .spawn((
             Node {
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
                 height: Val::Percent(100.0),
                 width: Val::Percent(40.0),
                 ..default()
             },
             BackgroundColor(Color::hsl(246.0, 0.21, 0.29)),
         ))
-        .set_parent(hud);
+        .set_parent(hud)
+        .id();
+    chat::spawn(&mut commands, chat_box);
 
     // Player stats
     commands
@@ -197,6 +209,7 @@ pub struct MapUiInfo;
 pub fn update_map_info<'a>(
     commands: &'a mut Commands,
     chunk_coordinate: map::ChunkCoordinate,
+    world_map: &map::WorldMap,
     q_map_info: Query<Entity, With<MapUiInfo>>,
 ) -> EntityCommands<'a> {
     if let Some(entity) = q_map_info.get_single().ok() {
@@ -212,8 +225,12 @@ pub fn update_map_info<'a>(
             MapUiInfo,
         ))
         .id();
+    let area_name = world_map
+        .get(chunk_coordinate)
+        .map(|info| info.area_name())
+        .unwrap_or_else(|| "Unknown".to_string());
     let _region_name = commands
-        .spawn((Node { ..default() }, Text::new("Incarnam")))
+        .spawn((Node { ..default() }, Text::new(area_name)))
         .set_parent(root)
         .id();
     let _chunk_coordinate = commands
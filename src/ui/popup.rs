@@ -4,15 +4,23 @@ use bevy::prelude::*;
 #[derive(Component)]
 pub struct Popup;
 
+/// Triggered on the popup entity when it's closed by clicking outside of it
 #[derive(Event)]
 pub struct CloseEvent;
 
+/// Close a popup, does nothing if it's already closed
+pub fn close(commands: &mut Commands, popup: Entity) {
+    if let Some(entity) = commands.get_entity(popup) {
+        entity.despawn_recursive();
+    }
+}
+
 pub fn spawn<'a>(commands: &'a mut Commands, text: String) -> EntityCommands<'a> {
     let input_catcher = ui::input_catcher::spawn_without_observer(commands)
         .observe(
             |mut trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
                 trigger.propagate(false);
-                commands.trigger(CloseEvent);
+                commands.trigger_targets(CloseEvent, trigger.entity());
                 commands.entity(trigger.entity()).despawn_recursive();
             },
         )
@@ -62,6 +70,7 @@ pub fn spawn<'a>(commands: &'a mut Commands, text: String) -> EntityCommands<'a>
     commands.entity(input_catcher)
 }
 
+/// Triggered on the popup entity returned by `spawn_with_choices`
 #[derive(Event)]
 pub enum ButtonEvent {
     Primary,
@@ -78,7 +87,7 @@ pub fn spawn_with_choices<'a>(
         .observe(
             |mut trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
                 trigger.propagate(false);
-                commands.trigger(ButtonEvent::Secondary);
+                commands.trigger_targets(ButtonEvent::Secondary, trigger.entity());
                 commands.entity(trigger.entity()).despawn_recursive();
             },
         )
@@ -141,13 +150,9 @@ pub fn spawn_with_choices<'a>(
                             BackgroundColor(Color::hsl(209.0, 0.59, 0.61)),
                         ))
                         .observe(
-                            |_trigger: Trigger<Pointer<Down>>,
-                             mut cmds: Commands,
-                             q_popups: Query<Entity, With<Popup>>| {
-                                cmds.trigger(ButtonEvent::Primary);
-                                for popup in q_popups.iter() {
-                                    cmds.entity(popup).despawn_recursive();
-                                }
+                            move |_trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+                                commands.trigger_targets(ButtonEvent::Primary, input_catcher);
+                                close(&mut commands, input_catcher);
                             },
                         );
                     parent
@@ -165,16 +170,92 @@ pub fn spawn_with_choices<'a>(
                             BackgroundColor(Color::hsl(111.0, 0.52, 0.65)),
                         ))
                         .observe(
-                            |_trigger: Trigger<Pointer<Down>>,
-                             mut commands: Commands,
-                             q_popups: Query<Entity, With<Popup>>| {
-                                commands.trigger(ButtonEvent::Secondary);
-                                for popup in q_popups.iter() {
-                                    commands.entity(popup).despawn_recursive();
-                                }
+                            move |_trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+                                commands.trigger_targets(ButtonEvent::Secondary, input_catcher);
+                                close(&mut commands, input_catcher);
                             },
                         );
                 });
         });
     commands.entity(input_catcher)
 }
+
+/// Triggered on the popup entity returned by `spawn_with_replies`, with the index of the reply
+#[derive(Event)]
+pub struct ReplyEvent(pub usize);
+
+/// Popup with a reply per line, clicking outside of it trigger `CloseEvent`
+pub fn spawn_with_replies<'a>(
+    commands: &'a mut Commands,
+    heading_text: impl Into<String>,
+    replies: Vec<String>,
+) -> EntityCommands<'a> {
+    let input_catcher = ui::input_catcher::spawn_without_observer(commands)
+        .observe(
+            |mut trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+                trigger.propagate(false);
+                commands.trigger_targets(CloseEvent, trigger.entity());
+                commands.entity(trigger.entity()).despawn_recursive();
+            },
+        )
+        .id();
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Stretch,
+                row_gap: Val::Px(4.0),
+                top: Val::Percent(50.0),
+                left: Val::Percent(50.0),
+                width: Val::Px(300.0),
+                padding: UiRect::all(Val::Px(6.0)),
+                ..default()
+            },
+            BackgroundColor(Color::hsl(246.0, 0.21, 0.29)),
+            Popup,
+        ))
+        .set_parent(input_catcher)
+        .observe(|mut trigger: Trigger<Pointer<Down>>| {
+            trigger.propagate(false);
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(heading_text.into()),
+                TextColor(Color::hsl(242.0, 0.15, 0.57)),
+                TextLayout {
+                    justify: JustifyText::Left,
+                    linebreak: LineBreak::WordBoundary,
+                },
+                TextFont {
+                    font_size: 10.0,
+                    ..Default::default()
+                },
+            ));
+            for (index, reply) in replies.into_iter().enumerate() {
+                parent
+                    .spawn((
+                        Text::new(reply),
+                        TextColor(Color::hsl(242.0, 0.15, 0.57)),
+                        TextLayout {
+                            justify: JustifyText::Left,
+                            linebreak: LineBreak::WordBoundary,
+                        },
+                        TextFont {
+                            font_size: 10.0,
+                            ..Default::default()
+                        },
+                        BackgroundColor(Color::hsl(209.0, 0.59, 0.61)),
+                    ))
+                    .observe(
+                        move |_trigger: Trigger<Pointer<Down>>, mut commands: Commands| {
+                            commands.trigger_targets(ReplyEvent(index), input_catcher);
+                            close(&mut commands, input_catcher);
+                        },
+                    );
+            }
+        });
+    commands.entity(input_catcher)
+}